@@ -0,0 +1,408 @@
+//! Async client for the rspb pastebin.
+//!
+//! Runs on the actix runtime, like the server. Every call is one API request, errors of the
+//! server come back as [`Error::Api`] with its status code and message.
+pub mod types;
+
+use crate::types::{
+    Info, LegalHold, Moderation, ModerationState, PasteAdminMeta, PasteDetails, ReportedPaste,
+    Response, UploadInfo,
+};
+
+use awc::http::StatusCode;
+use awc::{ClientRequest, ClientResponse};
+use bytes::Bytes;
+use chrono::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+
+// Replies other than paste content are small
+const MAX_REPLY: usize = 16 * 1024 * 1024;
+const MAX_CONTENT: usize = 1024 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server couldn't be reached, or the connection broke
+    Request(String),
+    /// The server refused the request
+    Api { status: u16, message: String },
+    /// The server answered with something that's not an rspb reply
+    Unexpected(u16),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(msg) => write!(f, "Request failed: {}", msg),
+            Self::Api { status, message } => write!(f, "{} ({})", message, status),
+            Self::Unexpected(status) => write!(f, "Unexpected reply from server ({})", status),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Settings of a new or modified paste. Only `name` and `expire_after` can be modified.
+#[derive(Serialize, Default, Clone)]
+pub struct PasteOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Minutes until the paste expires
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<i64>,
+    /// Days without views until the paste expires
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after_inactive: Option<u32>,
+    /// Replace secrets with placeholders before the paste is stored
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub redact: bool,
+}
+
+/// Filters of the admin paste list, newest pastes first
+#[derive(Serialize, Default, Clone)]
+pub struct ListQuery {
+    /// Only pastes whose id or name contains this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_before: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+}
+
+/// What a HEAD request tells about a paste
+#[derive(Debug)]
+pub struct PasteHead {
+    pub name: Option<String>,
+    pub size: u64,
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: awc::Client,
+    server: String,
+    admin: Option<(String, String)>,
+}
+
+impl Client {
+    /// Client of the server at `server`, like `https://example.com`
+    pub fn new(server: &str) -> Client {
+        Client {
+            http: awc::Client::builder().timeout(TIMEOUT).finish(),
+            server: server.trim_end_matches('/').to_string(),
+            admin: None,
+        }
+    }
+
+    /// Log in as admin for admin calls
+    pub fn admin(mut self, user: &str, password: &str) -> Client {
+        self.admin = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Full URL of a paste
+    pub fn url(&self, id: &str) -> String {
+        format!("{}/{}", self.server, id)
+    }
+
+    pub async fn create(&self, content: impl Into<Bytes>, options: &PasteOptions) -> Result<Info> {
+        let req = self
+            .http
+            .post(self.url(""))
+            .query(options)
+            .map_err(request_error)?;
+        call::<Info>(req, content.into())
+            .await?
+            .ok_or_else(|| Error::Request("Server sent no paste info".to_string()))
+    }
+
+    /// Content of a paste
+    pub async fn get(&self, id: &str) -> Result<Bytes> {
+        let mut res = self
+            .http
+            .get(self.url(id))
+            .send()
+            .await
+            .map_err(request_error)?;
+        if res.status() != StatusCode::OK {
+            return Err(text_error(&mut res).await);
+        }
+        res.body().limit(MAX_CONTENT).await.map_err(request_error)
+    }
+
+    pub async fn head(&self, id: &str) -> Result<PasteHead> {
+        // Compressed replies don't tell the size
+        let res = self
+            .http
+            .head(self.url(id))
+            .header("Accept-Encoding", "identity")
+            .send()
+            .await
+            .map_err(request_error)?;
+        if res.status() != StatusCode::OK {
+            return Err(Error::Api {
+                status: res.status().as_u16(),
+                message: res.status().canonical_reason().unwrap_or("").to_string(),
+            });
+        }
+        let header = |name: &str| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(PasteHead {
+            name: header("Name").filter(|name| !name.is_empty()),
+            size: header("Content-Length")
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(0),
+        })
+    }
+
+    /// Metadata of a paste, with view counts and revisions if `key` is given or logged in as
+    /// admin
+    pub async fn meta(&self, id: &str, key: Option<&str>) -> Result<PasteDetails> {
+        let req = self.http.get(self.url(&format!("{}/meta", id)));
+        let req = match (key, &self.admin) {
+            (Some(key), _) => req.header("Key", key),
+            (None, Some((user, password))) => req.basic_auth(user, Some(password)),
+            (None, None) => req,
+        };
+        call(req, Bytes::new())
+            .await?
+            .ok_or_else(|| Error::Request("Server sent no paste metadata".to_string()))
+    }
+
+    /// Replace the content of a paste if `content` is given, and change its settings
+    pub async fn modify(
+        &self,
+        id: &str,
+        key: &str,
+        content: Option<Bytes>,
+        options: &PasteOptions,
+    ) -> Result<()> {
+        let req = self
+            .http
+            .put(self.url(id))
+            .header("Key", key)
+            .query(options)
+            .map_err(request_error)?;
+        call::<()>(req, content.unwrap_or_default()).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, id: &str, key: &str) -> Result<()> {
+        let req = self.http.delete(self.url(id)).header("Key", key);
+        call::<()>(req, Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// Start a resumable upload of `length` bytes, if known
+    pub async fn start_upload(
+        &self,
+        length: Option<u64>,
+        options: &PasteOptions,
+    ) -> Result<UploadInfo> {
+        let req = self
+            .http
+            .post(self.url("uploads"))
+            .query(options)
+            .map_err(request_error)?;
+        let req = match length {
+            Some(length) => req.header("Upload-Length", length),
+            None => req,
+        };
+        upload_info(call(req, Bytes::new()).await?)
+    }
+
+    /// How far an upload got, where to resume after a broken connection
+    pub async fn upload_status(&self, id: &str) -> Result<UploadInfo> {
+        let req = self.http.get(self.url(&format!("uploads/{}", id)));
+        upload_info(call(req, Bytes::new()).await?)
+    }
+
+    /// Add `chunk` to an upload, `offset` has to be where the upload is at
+    pub async fn upload_chunk(
+        &self,
+        id: &str,
+        offset: u64,
+        chunk: impl Into<Bytes>,
+    ) -> Result<UploadInfo> {
+        let req = self
+            .http
+            .patch(self.url(&format!("uploads/{}", id)))
+            .header("Upload-Offset", offset);
+        upload_info(call(req, chunk.into()).await?)
+    }
+
+    /// Turn a complete upload into a paste
+    pub async fn finish_upload(&self, id: &str) -> Result<Info> {
+        let req = self.http.post(self.url(&format!("uploads/{}", id)));
+        call::<Info>(req, Bytes::new())
+            .await?
+            .ok_or_else(|| Error::Request("Server sent no paste info".to_string()))
+    }
+
+    pub async fn cancel_upload(&self, id: &str) -> Result<()> {
+        let req = self.http.delete(self.url(&format!("uploads/{}", id)));
+        call::<()>(req, Bytes::new()).await?;
+        Ok(())
+    }
+
+    fn as_admin(&self, req: ClientRequest) -> ClientRequest {
+        match &self.admin {
+            Some((user, password)) => req.basic_auth(user, Some(password)),
+            None => req,
+        }
+    }
+
+    /// All pastes on the server, needs admin login
+    pub async fn admin_list(&self, query: &ListQuery) -> Result<Vec<PasteAdminMeta>> {
+        let req = self
+            .http
+            .get(self.url("admin/list"))
+            .query(query)
+            .map_err(request_error)?;
+        Ok(call(self.as_admin(req), Bytes::new())
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// Content of any paste, even if moderated, needs admin login
+    pub async fn admin_get(&self, id: &str) -> Result<Bytes> {
+        let req = self.as_admin(self.http.get(self.url(&format!("admin/{}", id))));
+        let mut res = req.send().await.map_err(request_error)?;
+        if res.status() != StatusCode::OK {
+            return Err(text_error(&mut res).await);
+        }
+        res.body().limit(MAX_CONTENT).await.map_err(request_error)
+    }
+
+    /// Delete any paste without its key, needs admin login
+    pub async fn admin_delete(&self, id: &str) -> Result<()> {
+        let req = self.http.delete(self.url(&format!("admin/{}", id)));
+        call::<()>(self.as_admin(req), Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// Put a paste on legal hold, needs admin login
+    pub async fn hold(&self, id: &str) -> Result<LegalHold> {
+        let req = self.http.post(self.url(&format!("admin/{}/hold", id)));
+        call(self.as_admin(req), Bytes::new())
+            .await?
+            .ok_or_else(|| Error::Request("Server sent no hold".to_string()))
+    }
+
+    pub async fn release(&self, id: &str) -> Result<()> {
+        let req = self.http.delete(self.url(&format!("admin/{}/hold", id)));
+        call::<()>(self.as_admin(req), Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// Moderation queue, most reported first, needs admin login
+    pub async fn reports(&self) -> Result<Vec<ReportedPaste>> {
+        let req = self.http.get(self.url("admin/reports"));
+        Ok(call(self.as_admin(req), Bytes::new())
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// Close the reports of a paste without doing anything about it
+    pub async fn dismiss(&self, id: &str) -> Result<()> {
+        let req = self.http.delete(self.url(&format!("admin/reports/{}", id)));
+        call::<()>(self.as_admin(req), Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// Take a paste offline, needs admin login
+    pub async fn moderate(&self, id: &str, state: ModerationState) -> Result<Moderation> {
+        #[derive(Serialize)]
+        struct Query {
+            state: ModerationState,
+        }
+        let req = self
+            .http
+            .post(self.url(&format!("admin/{}/moderation", id)))
+            .query(&Query { state })
+            .map_err(request_error)?;
+        call(self.as_admin(req), Bytes::new())
+            .await?
+            .ok_or_else(|| Error::Request("Server sent no moderation".to_string()))
+    }
+
+    /// Put a moderated paste back online
+    pub async fn reinstate(&self, id: &str) -> Result<()> {
+        let req = self
+            .http
+            .delete(self.url(&format!("admin/{}/moderation", id)));
+        call::<()>(self.as_admin(req), Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// Restore a paste from the trash, needs admin login
+    pub async fn restore(&self, id: &str) -> Result<()> {
+        let req = self.http.post(self.url(&format!("admin/trash/{}", id)));
+        call::<()>(self.as_admin(req), Bytes::new()).await?;
+        Ok(())
+    }
+}
+
+fn request_error(err: impl std::fmt::Display) -> Error {
+    Error::Request(err.to_string())
+}
+
+fn upload_info(info: Option<UploadInfo>) -> Result<UploadInfo> {
+    info.ok_or_else(|| Error::Request("Server sent no upload info".to_string()))
+}
+
+/// Send an API request, returns the `info` of a successful reply
+async fn call<I: DeserializeOwned>(req: ClientRequest, body: Bytes) -> Result<Option<I>> {
+    let mut res = req
+        .header("Accept", "application/json")
+        .send_body(body)
+        .await
+        .map_err(request_error)?;
+    let status = res.status().as_u16();
+    let body = res.body().limit(MAX_REPLY).await.map_err(request_error)?;
+    let reply: Response<I> = match serde_json::from_slice(&body) {
+        Ok(reply) => reply,
+        // Failed logins aren't answered by the API
+        Err(_) if status == StatusCode::UNAUTHORIZED.as_u16() => {
+            return Err(Error::Api {
+                status,
+                message: "Wrong admin credentials".to_string(),
+            })
+        }
+        Err(_) => return Err(Error::Unexpected(status)),
+    };
+    if !reply.success {
+        return Err(Error::Api {
+            status,
+            message: reply.message,
+        });
+    }
+    Ok(reply.info)
+}
+
+/// Error of a request answered in plain text
+async fn text_error<S>(res: &mut ClientResponse<S>) -> Error
+where
+    S: futures::Stream<Item = std::result::Result<Bytes, awc::error::PayloadError>> + Unpin,
+{
+    let body = res.body().limit(MAX_REPLY).await.unwrap_or_default();
+    let message = String::from_utf8_lossy(&body);
+    Error::Api {
+        status: res.status().as_u16(),
+        message: message
+            .trim()
+            .trim_start_matches("Error: ")
+            .trim_end_matches('.')
+            .to_string(),
+    }
+}
@@ -0,0 +1,176 @@
+//! Request and response types of the rspb API, shared by the server and its clients.
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Body of every JSON reply
+#[derive(Serialize, Deserialize)]
+pub struct Response<I> {
+    pub success: bool,
+    pub message: String,
+    pub info: Option<I>,
+}
+
+/// Body of every error reply of API v1
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// What went wrong, for programs to tell errors apart without parsing messages
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    /// Admin login missing or wrong
+    Unauthorized,
+    /// Wrong paste key
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    /// Under legal hold, can't be changed
+    OnHold,
+    /// Taken offline by moderators pending review
+    Quarantined,
+    /// Taken down by moderators
+    Hidden,
+    /// Content matched a scan rule set to reject
+    Rejected,
+    /// Chunk of an upload sent at the wrong offset, or while another one is written
+    Conflict,
+    Internal,
+}
+
+/// Reply to creating a paste
+#[derive(Serialize, Deserialize)]
+pub struct Info {
+    pub id: String,
+    pub key: String,
+    pub expire_time: Option<DateTime<Utc>>,
+    pub expire_after_inactive: Option<u32>,
+    /// Secrets replaced, if redaction was asked for
+    pub redactions: Option<usize>,
+}
+
+/// State of a resumable upload
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadInfo {
+    pub id: String,
+    /// Bytes received so far, where the next chunk starts
+    pub offset: u64,
+    /// Size announced when the upload was created
+    pub length: Option<u64>,
+    /// When the upload is dropped unless more content arrives
+    pub expire_time: DateTime<Utc>,
+}
+
+/// Reply to reading the metadata of a paste
+#[derive(Serialize, Deserialize)]
+pub struct PasteDetails {
+    pub id: String,
+    pub name: Option<String>,
+    pub size: u64,
+    /// Guessed from the name
+    pub content_type: String,
+    pub create_time: DateTime<Utc>,
+    /// When the paste expires, by its expire time or inactivity
+    pub expire_time: Option<DateTime<Utc>>,
+    pub atime: Option<DateTime<Utc>>,
+    pub expire_after_inactive: Option<u32>,
+    pub on_hold: bool,
+    /// Only sent to the key holder and admins
+    pub private: Option<PrivateDetails>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PrivateDetails {
+    pub views: u64,
+    /// Starts at 1, counts up with every modification
+    pub revision: u32,
+    pub modify_time: Option<DateTime<Utc>>,
+    pub hold: Option<LegalHold>,
+    pub moderation: Option<Moderation>,
+}
+
+/// A paste as listed to admins
+#[derive(Serialize, Deserialize)]
+pub struct PasteAdminMeta {
+    pub id: String,
+    pub create_time: DateTime<Utc>,
+    pub expire_time: Option<DateTime<Utc>>,
+    pub atime: Option<DateTime<Utc>>,
+    pub name: Option<String>,
+    pub size: u64,
+    pub expire_after_inactive: Option<u32>,
+    pub hold: Option<LegalHold>,
+    pub moderation: Option<Moderation>,
+    pub scan: Option<ScanResult>,
+}
+
+/// Keeps a paste from expiring or being changed by its owner
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegalHold {
+    /// Admin who placed the hold
+    pub by: String,
+    pub time: DateTime<Utc>,
+}
+
+/// How a moderated paste is kept from being served
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationState {
+    /// Waiting for review, served as 451
+    Quarantined,
+    /// Taken down for good, served as 410
+    Hidden,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Moderation {
+    pub state: ModerationState,
+    /// Admin who did it, none if done automatically for reports or content rules
+    pub by: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+/// What to do with content matching a scan rule, from mildest to harshest
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanAction {
+    /// Only record the match
+    Flag,
+    Quarantine,
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanFinding {
+    pub rule: String,
+    pub action: ScanAction,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanResult {
+    pub time: DateTime<Utc>,
+    pub findings: Vec<ScanFinding>,
+}
+
+/// A report of a paste to moderators
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub time: DateTime<Utc>,
+    pub reason: Option<String>,
+    /// Address of the reporter
+    pub ip: Option<String>,
+}
+
+/// A paste in the moderation queue, with its open reports
+#[derive(Serialize, Deserialize)]
+pub struct ReportedPaste {
+    pub id: String,
+    pub name: Option<String>,
+    pub moderation: Option<Moderation>,
+    pub reports: Vec<Report>,
+}
@@ -0,0 +1,26 @@
+use crate::api::{ApiError, Response};
+use crate::audit::AuditQuery;
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+
+pub async fn get(
+    data: web::Data<PasteState>,
+    query: web::Query<AuditQuery>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.audit.is_enabled() {
+        return Err(ApiError::BadRequest(
+            "Audit log is not enabled.".to_string(),
+        ));
+    }
+
+    let entries = data.audit.query(&query)?;
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(entries),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
@@ -0,0 +1,19 @@
+use crate::api::{ApiError, Response};
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+
+pub async fn get(data: web::Data<PasteState>, _req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let stats = data.storage.inner.cache_stats();
+    let message = match stats {
+        Some(_) => String::new(),
+        None => "No in-process cache configured.".to_string(),
+    };
+    let res = Response {
+        success: true,
+        message,
+        info: stats,
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
@@ -0,0 +1,33 @@
+use crate::api::{ApiError, Response};
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::info;
+
+async fn fsck(data: web::Data<PasteState>, repair: bool) -> Result<HttpResponse, ApiError> {
+    let problems = data.storage.inner.fsck(repair).await?;
+    if repair {
+        info!("FSCK repaired {} inconsistencies.", problems.len());
+    }
+
+    let res = Response {
+        success: true,
+        message: format!("{} inconsistencies found.", problems.len()),
+        info: Some(problems),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Report inconsistencies without touching anything
+pub async fn get(data: web::Data<PasteState>, _req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    fsck(data, false).await
+}
+
+/// Report inconsistencies and repair them
+pub async fn post(
+    data: web::Data<PasteState>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    fsck(data, true).await
+}
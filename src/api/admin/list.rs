@@ -1,38 +1,33 @@
 use crate::api::{ApiError, Response};
-use crate::storage::PasteMeta;
+use crate::storage::{MetaQuery, PasteMeta};
 use crate::PasteState;
-use chrono::prelude::*;
-use serde::Serialize;
 
 use actix_web::{web, HttpRequest, HttpResponse};
+use rspb_client::types::PasteAdminMeta;
 
-#[derive(Serialize)]
-struct PasteAdminMeta {
-    id: String,
-    create_time: DateTime<Utc>,
-    expire_time: Option<DateTime<Utc>>,
-    atime: Option<DateTime<Utc>>,
-    name: Option<String>,
-    size: u64,
-}
-
-impl From<(String, PasteMeta)> for PasteAdminMeta {
-    fn from(i: (String, PasteMeta)) -> Self {
-        PasteAdminMeta {
-            id: i.0,
-            create_time: i.1.create_time,
-            expire_time: i.1.expire_time,
-            atime: i.1.atime,
-            name: i.1.name,
-            size: i.1.size,
-        }
+fn admin_meta((id, meta): (String, PasteMeta)) -> PasteAdminMeta {
+    PasteAdminMeta {
+        id,
+        create_time: meta.create_time,
+        expire_time: meta.expire_time,
+        atime: meta.atime,
+        name: meta.name,
+        size: meta.size,
+        expire_after_inactive: meta.expire_after_inactive,
+        hold: meta.hold.map(Into::into),
+        moderation: meta.moderation.map(Into::into),
+        scan: meta.scan.map(Into::into),
     }
 }
 
-pub async fn get(data: web::Data<PasteState>, _req: HttpRequest) -> Result<HttpResponse, ApiError> {
-    let metas = data.storage.inner.get_all_meta()?;
+pub async fn get(
+    data: web::Data<PasteState>,
+    query: web::Query<MetaQuery>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let metas = data.storage.inner.list_meta(&query).await?;
     let admin_metas: Vec<PasteAdminMeta> =
-        metas.into_iter().map(|m| PasteAdminMeta::from(m)).collect();
+        metas.into_iter().map(admin_meta).collect();
     let res = Response {
         success: true,
         message: "".to_string(),
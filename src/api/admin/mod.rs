@@ -1,3 +1,13 @@
+/// Querying the audit log
+pub mod audit;
+/// In-process cache statistics
+pub mod cache;
+/// Storage consistency check
+pub mod fsck;
 pub mod list;
+/// Abuse reports and taking pastes offline
+pub mod moderation;
 /// Modifying paste by admin
 pub mod paste;
+/// Listing and restoring deleted pastes
+pub mod trash;
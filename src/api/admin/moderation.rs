@@ -0,0 +1,127 @@
+use crate::api::{ApiError, Response};
+use crate::audit::{Actor, Operation};
+use crate::storage::{Moderation, ModerationState};
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use chrono::prelude::*;
+use log::info;
+use rspb_client::types;
+use serde::Deserialize;
+
+/// Moderation queue, most reported first
+pub async fn get(data: web::Data<PasteState>, _req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let mut pastes = Vec::new();
+    for reported in data.reports.list().await? {
+        if !data.storage.inner.exists(&reported.id).await? {
+            // Deleted or expired since
+            data.reports.dismiss(&reported.id).await?;
+            continue;
+        }
+        let meta = data.storage.inner.get_meta(&reported.id).await?;
+        pastes.push(types::ReportedPaste {
+            id: reported.id,
+            name: meta.name,
+            moderation: meta.moderation.map(Into::into),
+            reports: reported.reports.into_iter().map(Into::into).collect(),
+        });
+    }
+
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(pastes),
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Close the reports of a paste without doing anything about it
+pub async fn dismiss(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.reports.dismiss(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    let res: Response<()> = Response {
+        success: true,
+        message: String::new(),
+        info: None,
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+pub struct ModerateQuery {
+    state: ModerationState,
+}
+
+/// Quarantine or hide a paste, which also closes its reports
+pub async fn moderate(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    query: web::Query<ModerateQuery>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    let mut meta = data.storage.inner.get_meta(&id).await?;
+
+    let moderation = Moderation {
+        state: query.state,
+        by: Some(auth.user_id().to_string()),
+        time: Utc::now(),
+    };
+    info!("Paste {} set to {:?} by {}.", id, query.state, auth.user_id());
+    meta.moderation = Some(moderation.clone());
+    data.storage.inner.set_meta(&id, &meta).await?;
+    data.reports.dismiss(&id).await?;
+    let operation = match query.state {
+        ModerationState::Quarantined => Operation::Quarantine,
+        ModerationState::Hidden => Operation::Hide,
+    };
+    data.audit
+        .record(operation, &id, Actor::admin(&auth, &req));
+
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(types::Moderation::from(moderation)),
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Put a moderated paste back online, which also closes its reports
+pub async fn reinstate(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    let mut meta = data.storage.inner.get_meta(&id).await?;
+    if meta.moderation.is_none() {
+        return Err(ApiError::BadRequest("Paste is not moderated.".to_string()));
+    }
+
+    info!("Paste {} reinstated by {}.", id, auth.user_id());
+    meta.moderation = None;
+    data.storage.inner.set_meta(&id, &meta).await?;
+    data.reports.dismiss(&id).await?;
+    data.audit
+        .record(Operation::Reinstate, &id, Actor::admin(&auth, &req));
+
+    let res: Response<()> = Response {
+        success: true,
+        message: String::new(),
+        info: None,
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
@@ -1,30 +1,115 @@
-use crate::api::ApiError;
+use crate::api::{ApiError, Response};
+use crate::audit::{Actor, Operation};
+use crate::storage::LegalHold;
 use crate::PasteState;
 
-use actix_multipart::Multipart;
 use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use chrono::prelude::*;
+use log::info;
+use rspb_client::types;
+
+/// Serve a paste even if it's taken offline, so moderators can review it
+pub async fn get(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    crate::api::get::serve(&data, &id, true).await
+}
 
 pub async fn put(
     data: web::Data<PasteState>,
     id: web::Path<String>,
-    payload: Multipart,
+    payload: web::Payload,
+    auth: BasicAuth,
     req: HttpRequest,
 ) -> Result<HttpResponse, ApiError> {
-    if !data.storage.inner.exists(&id)? {
+    if !data.storage.inner.exists(&id).await? {
         return Err(ApiError::NotFound);
     }
 
-    crate::api::modify::modify(data, id, payload, req).await
+    let actor = Actor::admin(&auth, &req);
+    crate::api::modify::modify(data, id, payload, req, actor).await
 }
 
 pub async fn delete(
     data: web::Data<PasteState>,
     id: web::Path<String>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    // Held pastes stay until the hold is released, whoever asks
+    if data.storage.inner.get_meta(&id).await?.hold.is_some() {
+        return Err(ApiError::OnHold);
+    }
+
+    let actor = Actor::admin(&auth, &req);
+    crate::api::delete::delete_api(data, id, req, actor).await
+}
+
+/// Place a legal hold on a paste
+pub async fn hold(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    let mut meta = data.storage.inner.get_meta(&id).await?;
+    if meta.hold.is_some() {
+        return Err(ApiError::BadRequest("Paste is already on hold.".to_string()));
+    }
+
+    let hold = LegalHold {
+        by: auth.user_id().to_string(),
+        time: Utc::now(),
+    };
+    info!("Paste {} put on hold by {}.", id, hold.by);
+    meta.hold = Some(hold.clone());
+    data.storage.inner.set_meta(&id, &meta).await?;
+    data.audit
+        .record(Operation::Hold, &id, Actor::admin(&auth, &req));
+
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(types::LegalHold::from(hold)),
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Release a legal hold, so the paste expires and can be changed as usual again
+pub async fn release(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    auth: BasicAuth,
     req: HttpRequest,
 ) -> Result<HttpResponse, ApiError> {
-    if !data.storage.inner.exists(&id)? {
+    if !data.storage.inner.exists(&id).await? {
         return Err(ApiError::NotFound);
     }
+    let mut meta = data.storage.inner.get_meta(&id).await?;
+    if meta.hold.is_none() {
+        return Err(ApiError::BadRequest("Paste is not on hold.".to_string()));
+    }
+
+    info!("Paste {} released from hold by {}.", id, auth.user_id());
+    meta.hold = None;
+    data.storage.inner.set_meta(&id, &meta).await?;
+    data.audit
+        .record(Operation::Release, &id, Actor::admin(&auth, &req));
+    data.cleanup.notify();
 
-    crate::api::delete::delete_api(data, id, req).await
+    let res: Response<()> = Response {
+        success: true,
+        message: String::new(),
+        info: None,
+    };
+    Ok(HttpResponse::Ok().json(res))
 }
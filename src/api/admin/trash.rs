@@ -0,0 +1,71 @@
+use crate::api::{ApiError, Response};
+use crate::audit::{Actor, Operation};
+use crate::storage::trashstorage::Trash;
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+
+fn trash(data: &PasteState) -> Result<&Trash, ApiError> {
+    data.trash
+        .as_ref()
+        .ok_or_else(|| ApiError::BadRequest("Trash is not enabled.".to_string()))
+}
+
+pub async fn get(data: web::Data<PasteState>, _req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let pastes = trash(&data)?.list().await?;
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(pastes),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Put a trashed paste back in place
+pub async fn restore(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if data.storage.inner.exists(&id).await? {
+        return Err(ApiError::BadRequest(
+            "A paste with this id already exists.".to_string(),
+        ));
+    }
+    if !trash(&data)?.restore(&*data.storage.inner, &id).await? {
+        return Err(ApiError::NotFound);
+    }
+    data.audit
+        .record(Operation::Restore, &id, Actor::admin(&auth, &req));
+
+    let res: Response<()> = Response {
+        success: true,
+        message: String::new(),
+        info: None,
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Purge a trashed paste right away
+pub async fn delete(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    auth: BasicAuth,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !trash(&data)?.remove(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    data.audit
+        .record(Operation::Purge, &id, Actor::admin(&auth, &req));
+
+    let res: Response<()> = Response {
+        success: true,
+        message: String::new(),
+        info: None,
+    };
+    Ok(HttpResponse::Ok().json(res))
+}
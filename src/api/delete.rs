@@ -1,4 +1,6 @@
-use crate::api::{ApiError, Response};
+use crate::api::{ApiError, Reply, Response};
+use crate::audit::{Actor, Operation};
+use crate::webhook::Event;
 use crate::PasteState;
 
 use actix_web::{web, HttpRequest, HttpResponse};
@@ -15,23 +17,31 @@ pub async fn delete(
         }
     };
 
-    if !data.storage.inner.exists(&id)? {
+    if !data.storage.inner.exists(&id).await? {
         return Err(ApiError::NotFound);
     }
 
-    if !data.storage.inner.get_meta(&id)?.validate(&key) {
+    let meta = data.storage.inner.get_meta(&id).await?;
+    if !meta.validate(&key) {
         return Err(ApiError::Forbidden);
     }
+    if meta.hold.is_some() {
+        return Err(ApiError::OnHold);
+    }
 
-    delete_api(data, id, req).await
+    let actor = Actor::key_holder(&req);
+    delete_api(data, id, req, actor).await
 }
 
 pub async fn delete_api(
     data: web::Data<PasteState>,
     id: web::Path<String>,
-    _req: HttpRequest,
+    req: HttpRequest,
+    actor: Actor,
 ) -> Result<HttpResponse, ApiError> {
     data.storage.inner.delete(&id).await?;
+    data.audit.record(Operation::Delete, &id, actor);
+    data.webhooks.fire(Event::Deleted, &id).await;
 
     // Success!
     let response: Response<()> = Response {
@@ -39,5 +49,5 @@ pub async fn delete_api(
         message: String::new(),
         info: None,
     };
-    Ok(HttpResponse::Ok().json(response))
+    Ok(response.reply(&req))
 }
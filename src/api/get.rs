@@ -1,123 +1,137 @@
-use crate::storage::Response;
+use crate::api::ApiError;
+use crate::storage::{PasteMeta, Response};
 use crate::PasteState;
 
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use log::debug;
 use regex::Regex;
 
 use actix_web::web::BytesMut;
 use futures::TryStreamExt;
 
+/// Count a view, and record access time at most once an hour. Inactivity expiry relies on this.
+pub async fn record_view(data: &PasteState, id: &str, meta: &mut PasteMeta) {
+    // It's fine if these fail
+    let _ = data.storage.inner.add_views(id, 1).await;
+    let now = chrono::Utc::now();
+    if meta.atime.is_some_and(|t| now - t <= chrono::Duration::minutes(60)) {
+        return;
+    }
+    meta.atime = Some(now);
+    // `meta` may be stale by now, only the atime goes onto what's stored
+    if let Ok(mut current) = data.storage.inner.get_meta(id).await {
+        current.atime = Some(now);
+        let _ = data.storage.inner.set_meta(id, &current).await;
+    }
+}
+
+/// Refusal if moderators have taken the paste offline
+pub fn moderated(meta: &PasteMeta) -> Option<ApiError> {
+    Some(ApiError::Moderated(meta.moderation.as_ref()?.state))
+}
+
+/// Plain text error, pages and paste content aren't answered in JSON
+pub fn text_error(err: ApiError) -> HttpResponse {
+    let message = err.to_string();
+    HttpResponse::build(err.status_code())
+        .body(format!("Error: {}.", message.trim_end_matches('.')))
+}
+
+/// The paste id in a path, which may be followed by an extension
+pub fn paste_id(path: &str) -> Result<String, ApiError> {
+    let id_re = Regex::new(r"^[a-zA-Z0-9]{6}(\.|$)").unwrap();
+    if !id_re.is_match(path) {
+        return Err(ApiError::NotFound);
+    }
+
+    let mut id = path.to_string();
+    id.truncate(6); // Only use first 6 elements
+    Ok(id)
+}
+
 pub async fn head(
     data: web::Data<PasteState>,
     info: web::Path<String>,
-    req: HttpRequest,
-) -> impl Responder {
-    // Make sure this is a valid paste id
-    let id_uri_re = Regex::new(r"^/[a-zA-Z0-9]{6}(\.|\?|$)").unwrap();
-    if !id_uri_re.is_match(req.uri().path()) {
-        return HttpResponse::NotFound().body("404 Not Found");
-    }
+    _req: HttpRequest,
+) -> HttpResponse {
+    describe(&data, &info).await.unwrap_or_else(text_error)
+}
 
-    let mut id = info.clone();
-    id.truncate(6); // Only use first 6 elements
-    debug!("GET paste with id {}.", &id);
-
-    // Check if exists
-    match data.storage.inner.exists(&id) {
-        Ok(true) => {
-            let meta = match data.storage.inner.get_meta(&id) {
-                Ok(m) => m,
-                Err(_e) => {
-                    return HttpResponse::InternalServerError().body("Internal Server Error");
-                }
-            };
-
-            let size = meta.size;
-            let name = meta.name.clone().unwrap_or("".to_string());
-            return HttpResponse::Ok()
-                .header("Content-Length", size)
-                .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
-                .header("Name", name)
-                .body("");
-        }
-        Ok(false) => {
-            return HttpResponse::NotFound().body("Error: Paste not found.");
-        }
-        Err(_) => {
-            return HttpResponse::InternalServerError().body("Internal Server Error");
-        }
+/// Headers of the paste content, without the content
+pub async fn describe(data: &PasteState, path: &str) -> Result<HttpResponse, ApiError> {
+    let id = paste_id(path)?;
+    debug!("HEAD paste with id {}.", &id);
+
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
     }
+    let meta = data.storage.inner.get_meta(&id).await?;
+    if let Some(err) = moderated(&meta) {
+        return Err(err);
+    }
+
+    let size = meta.size;
+    let name = meta.name.clone().unwrap_or("".to_string());
+    // An unsized body keeps our Content-Length, none of it is sent for HEAD
+    Ok(HttpResponse::Ok()
+        .no_chunking(size)
+        .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
+        .header("Name", name)
+        .streaming(futures::stream::empty::<Result<web::Bytes, ApiError>>()))
 }
 
 pub async fn get(
     data: web::Data<PasteState>,
     info: web::Path<String>,
-    req: HttpRequest,
-) -> impl Responder {
-    // Make sure this is a valid paste id
-    let id_uri_re = Regex::new(r"^/[a-zA-Z0-9]{6}(\.|\?|$)").unwrap();
-    if !id_uri_re.is_match(req.uri().path()) {
-        return HttpResponse::NotFound().body("404 Not Found");
+    _req: HttpRequest,
+) -> HttpResponse {
+    let res = match paste_id(&info) {
+        Ok(id) => serve(&data, &id, false).await,
+        Err(err) => Err(err),
+    };
+    res.unwrap_or_else(text_error)
+}
+
+/// Respond with the paste content. Pastes taken offline by moderators are only served when an
+/// admin is `reviewing` them.
+pub async fn serve(data: &PasteState, id: &str, reviewing: bool) -> Result<HttpResponse, ApiError> {
+    debug!("GET paste with id {}.", id);
+    // Get paste content
+    let content = match data.storage.inner.get(id).await {
+        Ok(content) => content,
+        Err(err) => {
+            debug!("GET paste with id {} failed: {:?}", id, err);
+            return Err(ApiError::NotFound);
+        }
+    };
+    let mut meta = data.storage.inner.get_meta(id).await?;
+
+    if !reviewing {
+        if let Some(err) = moderated(&meta) {
+            return Err(err);
+        }
+        record_view(data, id, &mut meta).await;
     }
 
-    let mut id = info.clone();
-    id.truncate(6); // Only use first 6 elements
-    debug!("GET paste with id {}.", &id);
+    // Get size
+    let size = meta.size;
+    let name = meta.name.clone().unwrap_or("".to_string());
 
-    // Get paste content
-    let content = data.storage.inner.get(&id).await;
     match content {
-        Ok(content) => {
-            let mut meta = match data.storage.inner.get_meta(&id) {
-                Ok(m) => m,
-                Err(_e) => {
-                    return HttpResponse::InternalServerError().body("Internal Server Error");
-                }
-            };
-
-            // Get size
-            let size = meta.size;
-            let name = meta.name.clone().unwrap_or("".to_string());
-            // Record atime
-            match meta.atime {
-                Some(t) => {
-                    let now = chrono::Utc::now();
-                    if now - t > chrono::Duration::minutes(60) {
-                        meta.atime = Some(now);
-                        // It's fine if it fails
-                        let _ = data.storage.inner.set_meta(&id, &meta);
-                    }
-                }
-                None => {
-                    meta.atime = Some(chrono::Utc::now());
-                    let _ = data.storage.inner.set_meta(&id, &meta);
-                }
-            }
-
-            match content {
-                Response::Content(vec) => {
-                    return HttpResponse::Ok()
-                        .header("Content-Length", size)
-                        .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
-                        .header("Cache-Control", "max-age=600")
-                        .header("Name", name)
-                        .body(vec);
-                }
-                Response::Stream(stream) => {
-                    let s = stream.map_ok(BytesMut::freeze);
-                    return HttpResponse::Ok()
-                        .header("Content-Length", size)
-                        .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
-                        .header("Cache-Control", "max-age=600")
-                        .header("Name", name)
-                        .streaming(s);
-                }
-            }
-        }
-        Err(err) => {
-            debug!("GET paste with id {} failed: {:?}", &info, err);
-            return HttpResponse::NotFound().body("Error: Paste not found.");
+        Response::Content(vec) => Ok(HttpResponse::Ok()
+            .header("Content-Length", size)
+            .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
+            .header("Cache-Control", "max-age=600")
+            .header("Name", name)
+            .body(vec)),
+        Response::Stream(stream) => {
+            let s = stream.map_ok(BytesMut::freeze);
+            Ok(HttpResponse::Ok()
+                .header("Content-Length", size)
+                .header("Content-Disposition", format!("inline; filename=\"{}\"", &name))
+                .header("Cache-Control", "max-age=600")
+                .header("Name", name)
+                .streaming(s))
         }
     }
 }
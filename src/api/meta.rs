@@ -0,0 +1,81 @@
+use crate::api::get::{moderated, paste_id};
+use crate::api::{ApiError, Response};
+use crate::misc::auth::is_admin;
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use rspb_client::types::{PasteDetails, PrivateDetails};
+
+/// Content type by the extension of the paste name, pastes are text unless told otherwise
+fn content_type(name: Option<&str>) -> String {
+    name.and_then(|name| mime_guess::from_path(name).first_raw())
+        .unwrap_or("text/plain")
+        .to_string()
+}
+
+/// Metadata of a paste. The key holder and admins get more of it, and can see it even if the
+/// paste was taken offline by moderators.
+pub async fn get(
+    data: web::Data<PasteState>,
+    path: web::Path<String>,
+    auth: Option<BasicAuth>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let id = paste_id(&path)?;
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+    let meta = data.storage.inner.get_meta(&id).await?;
+
+    let owner = match (req.headers().get("Key"), auth) {
+        (Some(key), _) => {
+            if !meta.validate(key.to_str()?) {
+                return Err(ApiError::Forbidden);
+            }
+            true
+        }
+        (None, Some(auth)) => {
+            if !is_admin(&data.config.admins, &auth) {
+                return Err(ApiError::Unauthorized);
+            }
+            true
+        }
+        (None, None) => false,
+    };
+    if !owner {
+        if let Some(err) = moderated(&meta) {
+            return Err(err);
+        }
+    }
+
+    let private = match owner {
+        true => Some(PrivateDetails {
+            views: data.storage.inner.views(&id).await?,
+            revision: meta.revision,
+            modify_time: meta.modify_time,
+            hold: meta.hold.clone().map(Into::into),
+            moderation: meta.moderation.clone().map(Into::into),
+        }),
+        false => None,
+    };
+    let details = PasteDetails {
+        id,
+        content_type: content_type(meta.name.as_deref()),
+        expire_time: meta.expires_at(),
+        name: meta.name,
+        size: meta.size,
+        create_time: meta.create_time,
+        atime: meta.atime,
+        expire_after_inactive: meta.expire_after_inactive,
+        on_hold: meta.hold.is_some(),
+        private,
+    };
+    let res = Response {
+        success: true,
+        message: String::new(),
+        info: Some(details),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
@@ -1,26 +1,180 @@
 pub mod admin;
 pub mod delete;
 pub mod get;
+pub mod meta;
 pub mod modify;
 pub mod new;
+pub mod openapi;
+pub mod report;
+pub mod upload;
+pub mod v1;
 
-use log::error;
-use actix_web::{error::ResponseError, http::header::ToStrError, http::StatusCode, HttpResponse};
+use crate::moderation::Report;
+use crate::storage::{
+    LegalHold, Moderation, ModerationState, PasteMeta, ScanAction, ScanFinding, ScanResult,
+};
+use crate::PasteState;
+
+use chrono::prelude::*;
+use log::{error, info, warn};
+use actix_web::dev::ServiceResponse;
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, ToStrError};
+use actix_web::http::HeaderMap;
+use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
 use serde::Serialize;
 use std::{ num::ParseIntError, string::FromUtf8Error };
 
-#[derive(Serialize)]
-pub struct Response<I> {
-    success: bool,
-    message: String,
-    info: Option<I>,
+use rspb_client::types;
+
+pub use rspb_client::types::{ErrorCode, ErrorResponse, Response};
+
+pub trait Reply {
+    /// Reply as JSON, or with just the message for clients that want plain text
+    fn reply(&self, req: &HttpRequest) -> HttpResponse;
+}
+
+impl<I: Serialize> Reply for Response<I> {
+    fn reply(&self, req: &HttpRequest) -> HttpResponse {
+        if wants_text(req.path(), req.headers()) {
+            let message = if self.message.is_empty() {
+                "OK"
+            } else {
+                &self.message
+            };
+            return text_reply(format!("{}\n", message));
+        }
+        HttpResponse::Ok().json(self)
+    }
+}
+
+/// Whether the client prefers plain text to JSON, like command-line clients. API v1 is
+/// always JSON.
+pub fn wants_text(path: &str, headers: &HeaderMap) -> bool {
+    if path.starts_with(v1::PREFIX) {
+        return false;
+    }
+    let accept = match headers.get(header::ACCEPT).map(|a| a.to_str()) {
+        Some(Ok(accept)) => accept,
+        _ => return false,
+    };
+    for media in accept.split(',') {
+        match media.split(';').next().unwrap_or_default().trim() {
+            "text/plain" => return true,
+            "application/json" => return false,
+            _ => (),
+        }
+    }
+    // What curl sends unless told otherwise
+    let curl = headers
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .is_some_and(|ua| ua.starts_with("curl/"));
+    curl && accept.trim() == "*/*"
+}
+
+pub fn text_reply(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(body)
+}
+
+/// Turn API errors into plain text for clients that want it
+pub fn plain_error(res: ServiceResponse) -> ServiceResponse {
+    let message = match res.response().error().and_then(|err| err.as_error::<ApiError>()) {
+        Some(err) => format!("Error: {}.\n", err.to_string().trim_end_matches('.')),
+        None => return res,
+    };
+    let reply = HttpResponse::build(res.status())
+        .content_type("text/plain; charset=utf-8")
+        .body(message);
+    res.into_response(reply)
+}
+
+/// Turn every error into the JSON body of API v1
+pub fn json_error(res: ServiceResponse) -> ServiceResponse {
+    match json_error_reply(res.response()) {
+        Some(reply) => res.into_response(reply),
+        None => res,
+    }
+}
+
+/// Same as [`json_error`] for errors of middleware, like failed admin logins, which don't come
+/// back as responses
+pub fn json_middleware_error(err: actix_web::Error) -> actix_web::Error {
+    let message = err.to_string();
+    let res = HttpResponse::from_error(err);
+    let reply = json_error_reply(&res).unwrap_or(res);
+    InternalError::from_response(message, reply).into()
+}
+
+fn json_error_reply<B>(res: &HttpResponse<B>) -> Option<HttpResponse> {
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return None;
+    }
+    let (code, message) = match res.error() {
+        Some(err) => match err.as_error::<ApiError>() {
+            Some(err) => (err.code(), err.to_string()),
+            None => (status_error_code(status), err.to_string()),
+        },
+        None => (status_error_code(status), String::new()),
+    };
+    let message = match message.is_empty() {
+        true => status.canonical_reason().unwrap_or("Error").to_string(),
+        false => message,
+    };
+    let mut reply = HttpResponse::build(status).json(ErrorResponse {
+        success: false,
+        code,
+        message,
+    });
+    // Keep headers like WWW-Authenticate
+    for (name, value) in res.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            reply.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    Some(reply)
+}
+
+/// Code of errors that didn't come from a handler, like failed logins or unknown routes
+fn status_error_code(status: StatusCode) -> ErrorCode {
+    match status {
+        StatusCode::BAD_REQUEST => ErrorCode::BadRequest,
+        StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+        StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+        StatusCode::CONFLICT => ErrorCode::Conflict,
+        StatusCode::GONE => ErrorCode::Hidden,
+        StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+        StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::Rejected,
+        StatusCode::LOCKED => ErrorCode::OnHold,
+        StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => ErrorCode::Quarantined,
+        s if s.is_client_error() => ErrorCode::BadRequest,
+        _ => ErrorCode::Internal,
+    }
 }
 
 #[derive(Debug)]
 pub enum ApiError {
     BadRequest(String),
     NotFound,
+    /// No unfinished upload with this id
+    UploadNotFound,
     Forbidden,
+    /// Wrong admin credentials
+    Unauthorized,
+    OnHold,
+    /// Taken offline by moderators
+    Moderated(ModerationState),
+    /// Content matched a scan rule set to reject
+    Rejected(String),
+    /// Upload chunk at the wrong offset, or while another one is written
+    Conflict(String),
+    /// Upload past the largest size allowed
+    TooLarge(String),
     Unknown(String),
 }
 
@@ -29,7 +183,19 @@ impl std::fmt::Display for ApiError {
         let msg = match self {
             Self::BadRequest(msg) => msg.to_string(),
             Self::NotFound => "Paste Not Found".to_string(),
+            Self::UploadNotFound => "Upload Not Found".to_string(),
             Self::Forbidden => "Forbidden: Bad Key".to_string(),
+            Self::Unauthorized => "Wrong admin credentials".to_string(),
+            Self::OnHold => "Paste is under legal hold".to_string(),
+            Self::Moderated(ModerationState::Quarantined) => {
+                "Paste is quarantined pending review".to_string()
+            }
+            Self::Moderated(ModerationState::Hidden) => {
+                "Paste has been removed by moderators".to_string()
+            }
+            Self::Rejected(rule) => format!("Content rejected by rule {}", rule),
+            Self::Conflict(msg) => msg.to_string(),
+            Self::TooLarge(msg) => msg.to_string(),
             Self::Unknown(msg) => msg.to_string(),
         };
         write!(f, "{}", msg)
@@ -61,12 +227,39 @@ impl From<FromUtf8Error> for ApiError {
     }
 }
 
+impl ApiError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::BadRequest(_m) => ErrorCode::BadRequest,
+            Self::NotFound | Self::UploadNotFound => ErrorCode::NotFound,
+            Self::Forbidden => ErrorCode::Forbidden,
+            Self::Unauthorized => ErrorCode::Unauthorized,
+            Self::OnHold => ErrorCode::OnHold,
+            Self::Moderated(ModerationState::Quarantined) => ErrorCode::Quarantined,
+            Self::Moderated(ModerationState::Hidden) => ErrorCode::Hidden,
+            Self::Rejected(_rule) => ErrorCode::Rejected,
+            Self::Conflict(_m) => ErrorCode::Conflict,
+            Self::TooLarge(_m) => ErrorCode::PayloadTooLarge,
+            Self::Unknown(_m) => ErrorCode::Internal,
+        }
+    }
+}
+
 impl ResponseError for ApiError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::BadRequest(_m) => StatusCode::BAD_REQUEST,
-            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::NotFound | Self::UploadNotFound => StatusCode::NOT_FOUND,
             Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::OnHold => StatusCode::LOCKED,
+            Self::Moderated(ModerationState::Quarantined) => {
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS
+            }
+            Self::Moderated(ModerationState::Hidden) => StatusCode::GONE,
+            Self::Rejected(_rule) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Conflict(_m) => StatusCode::CONFLICT,
+            Self::TooLarge(_m) => StatusCode::PAYLOAD_TOO_LARGE,
             Self::Unknown(_m) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -82,31 +275,272 @@ impl ResponseError for ApiError {
     }
 }
 
-use actix_multipart::Field;
-use tokio::io::AsyncWriteExt;
+use crate::misc::parse_query_string;
+use actix_web::{web, HttpMessage, HttpRequest};
+use bytes::Bytes;
+use chrono::Duration;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::marker::Unpin;
-use futures::StreamExt;
-async fn read_field<T>(field: &mut Field, mut to: T) -> Result<(), ApiError>
+use tokio::io::AsyncWriteExt;
+
+// JSON uploads are parsed in memory
+const MAX_JSON_SIZE: usize = 32 * 1024 * 1024;
+
+/// How the content of an upload is sent
+pub enum BodyFormat {
+    Multipart,
+    Json,
+    /// The body is the content itself
+    Raw,
+}
+
+impl BodyFormat {
+    pub fn of(req: &HttpRequest) -> BodyFormat {
+        match req.content_type() {
+            "multipart/form-data" => BodyFormat::Multipart,
+            "application/json" => BodyFormat::Json,
+            _ => BodyFormat::Raw,
+        }
+    }
+}
+
+/// Paste settings, named like the multipart form fields
+#[derive(Serialize, Deserialize, Default)]
+pub struct PasteOptions {
+    pub name: Option<String>,
+    pub expire_after: Option<i64>,
+    pub expire_after_inactive: Option<u32>,
+    #[serde(default)]
+    pub redact: bool,
+}
+
+impl PasteOptions {
+    /// Settings of a raw upload, from headers or the query string, which wins
+    pub fn from_request(req: &HttpRequest) -> Result<PasteOptions, ApiError> {
+        let mut args = HashMap::new();
+        for header in &["Name", "Expire-After", "Expire-After-Inactive", "Redact"] {
+            if let Some(value) = req.headers().get(*header) {
+                let arg = header.to_lowercase().replace('-', "_");
+                args.insert(arg, value.to_str()?.to_string());
+            }
+        }
+        let query = parse_query_string(req.query_string())
+            .map_err(|_| ApiError::BadRequest("Bad query string.".to_string()))?;
+        args.extend(query);
+
+        Ok(PasteOptions {
+            name: args.remove("name"),
+            expire_after: args.remove("expire_after").map(|m| m.parse()).transpose()?,
+            expire_after_inactive: args
+                .remove("expire_after_inactive")
+                .map(|d| d.parse())
+                .transpose()?,
+            redact: match args.remove("redact").as_deref() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(_) => return Err(ApiError::BadRequest("Bad redact value.".to_string())),
+            },
+        })
+    }
+
+    pub fn expire_time(&self) -> Result<Option<DateTime<Utc>>, ApiError> {
+        match self.expire_after {
+            Some(minutes) if minutes > 0 => Ok(Some(Utc::now() + Duration::minutes(minutes))),
+            Some(_) => Err(ApiError::BadRequest("Bad expire time.".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn expire_after_inactive(&self) -> Result<Option<u32>, ApiError> {
+        match self.expire_after_inactive {
+            Some(0) => Err(ApiError::BadRequest(
+                "Bad inactivity expire time.".to_string(),
+            )),
+            days => Ok(days),
+        }
+    }
+}
+
+/// Body of a JSON upload
+#[derive(Deserialize)]
+pub struct JsonPaste {
+    pub content: Option<String>,
+    #[serde(flatten)]
+    pub options: PasteOptions,
+}
+
+async fn read_json(mut payload: web::Payload) -> Result<JsonPaste, ApiError> {
+    let mut body = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| {
+            ApiError::Unknown("Connection error: upload interrupted.".to_string())
+        })?;
+        if body.len() + chunk.len() > MAX_JSON_SIZE {
+            return Err(ApiError::BadRequest(
+                "JSON body too large, upload the content raw or as multipart.".to_string(),
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&body).map_err(|err| ApiError::BadRequest(format!("Bad JSON: {}.", err)))
+}
+
+/// Copy a stream of content to `to`, returns its size
+async fn write_stream<S, E, T>(stream: &mut S, mut to: T) -> Result<usize, ApiError>
 where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
     T: AsyncWriteExt + Unpin,
 {
     let mut size = 0;
-    while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        size += data.len();
-        match to.write_all(&data).await {
-            Ok(_res) => continue,
-            Err(_err) => {
-                return Err(ApiError::Unknown(
-                    "Connection error: upload interrupted.".to_string(),
-                ));
+    while let Some(chunk) = stream.next().await {
+        let written = match chunk {
+            Ok(data) => {
+                size += data.len();
+                to.write_all(&data).await
             }
+            Err(_err) => Err(std::io::ErrorKind::ConnectionAborted.into()),
         };
+        if written.is_err() {
+            return Err(ApiError::Unknown(
+                "Connection error: upload interrupted.".to_string(),
+            ));
+        }
+    }
+
+    // Make sure everything has reached storage before anyone looks at it
+    if to.flush().await.is_err() {
+        return Err(ApiError::Unknown(
+            "Connection error: upload interrupted.".to_string(),
+        ));
     }
+    Ok(size)
+}
 
+async fn read_field<S, E, T>(field: &mut S, to: T) -> Result<(), ApiError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    T: AsyncWriteExt + Unpin,
+{
     // Don't allow empty field
-    if size == 0 {
+    if write_stream(field, to).await? == 0 {
         return Err(ApiError::BadRequest("Bad form: Empty field".to_string()));
     }
     Ok(())
 }
+
+/// Delete a paste without keeping it in the trash, for content that must not or need not be kept
+async fn discard(data: &PasteState, id: &str) -> Result<(), ApiError> {
+    data.storage.inner.delete(id).await?;
+    if let Some(trash) = &data.trash {
+        trash.remove(id).await?;
+    }
+    Ok(())
+}
+
+/// Act on what scanning new content of a paste found, and record it in `meta`. Returns whether the
+/// paste got quarantined, or `ApiError::Rejected` for content the caller must not keep.
+fn apply_scan(
+    id: &str,
+    meta: &mut PasteMeta,
+    findings: Vec<ScanFinding>,
+) -> Result<bool, ApiError> {
+    let worst = match findings.iter().max_by_key(|f| f.action) {
+        Some(finding) => finding.clone(),
+        None => {
+            meta.scan = None;
+            return Ok(false);
+        }
+    };
+
+    let mut quarantined = false;
+    match worst.action {
+        ScanAction::Reject => {
+            warn!("Rejected content of paste {} for rule {}.", id, worst.rule);
+            return Err(ApiError::Rejected(worst.rule));
+        }
+        ScanAction::Quarantine if meta.moderation.is_none() => {
+            warn!("Paste {} quarantined for rule {}.", id, worst.rule);
+            meta.moderation = Some(Moderation {
+                state: ModerationState::Quarantined,
+                by: None,
+                time: Utc::now(),
+            });
+            quarantined = true;
+        }
+        _ => info!("Paste {} flagged by rule {}.", id, worst.rule),
+    }
+
+    meta.scan = Some(ScanResult {
+        time: Utc::now(),
+        findings,
+    });
+    Ok(quarantined)
+}
+
+// Records of the storage are sent as the types of the client crate, so their layout on disk can
+// change without changing the API
+impl From<LegalHold> for types::LegalHold {
+    fn from(hold: LegalHold) -> Self {
+        types::LegalHold {
+            by: hold.by,
+            time: hold.time,
+        }
+    }
+}
+
+impl From<ModerationState> for types::ModerationState {
+    fn from(state: ModerationState) -> Self {
+        match state {
+            ModerationState::Quarantined => types::ModerationState::Quarantined,
+            ModerationState::Hidden => types::ModerationState::Hidden,
+        }
+    }
+}
+
+impl From<Moderation> for types::Moderation {
+    fn from(moderation: Moderation) -> Self {
+        types::Moderation {
+            state: moderation.state.into(),
+            by: moderation.by,
+            time: moderation.time,
+        }
+    }
+}
+
+impl From<ScanAction> for types::ScanAction {
+    fn from(action: ScanAction) -> Self {
+        match action {
+            ScanAction::Flag => types::ScanAction::Flag,
+            ScanAction::Quarantine => types::ScanAction::Quarantine,
+            ScanAction::Reject => types::ScanAction::Reject,
+        }
+    }
+}
+
+impl From<ScanResult> for types::ScanResult {
+    fn from(scan: ScanResult) -> Self {
+        types::ScanResult {
+            time: scan.time,
+            findings: scan
+                .findings
+                .into_iter()
+                .map(|finding| types::ScanFinding {
+                    rule: finding.rule,
+                    action: finding.action.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Report> for types::Report {
+    fn from(report: Report) -> Self {
+        types::Report {
+            time: report.time,
+            reason: report.reason,
+            ip: report.ip,
+        }
+    }
+}
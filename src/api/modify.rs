@@ -1,16 +1,68 @@
-use crate::api::{ApiError, Response, read_field};
+use crate::api::{
+    self, apply_scan, read_field, write_stream, ApiError, BodyFormat, PasteOptions, Reply,
+    Response,
+};
+use crate::api::new::gen_random_chars;
+use crate::audit::{Actor, Operation};
+use crate::storage::PasteMeta;
+use crate::webhook::Event;
 use crate::PasteState;
 
 use actix_multipart::Multipart;
 use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::prelude::*;
 use chrono::Duration;
-use futures::TryStreamExt;
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+// New content waits in `base_dir/staging` until scanning accepted it
+const STAGING_DIR: &str = "staging";
+
+/// New content of a paste, removed once dropped
+struct Staged {
+    path: PathBuf,
+}
+
+impl Staged {
+    async fn create(data: &PasteState) -> Result<(Staged, fs::File), ApiError> {
+        let dir = Path::new(&data.config.storage.base_dir).join(STAGING_DIR);
+        fs::create_dir_all(&dir).await.map_err(anyhow::Error::from)?;
+        let path = dir.join(gen_random_chars(24));
+        let file = fs::File::create(&path)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok((Staged { path }, file))
+    }
+
+    /// Replace the content of paste `id`, returns its new size
+    async fn commit(self, data: &PasteState, id: &str) -> Result<u64, ApiError> {
+        let mut file = fs::File::open(&self.path)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let mut writer = data.storage.inner.update(id).await?;
+        let size = tokio::io::copy(&mut file, &mut writer)
+            .await
+            .map_err(anyhow::Error::from)?;
+        writer.flush().await.map_err(anyhow::Error::from)?;
+        Ok(size)
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
 
 pub async fn put(
     data: web::Data<PasteState>,
     id: web::Path<String>,
-    payload: Multipart,
+    payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, ApiError> {
     let mut response: Response<()> = Response {
@@ -27,71 +79,148 @@ pub async fn put(
         }
     };
 
-    let meta = data.storage.inner.get_meta(&id)?;
+    let meta = data.storage.inner.get_meta(&id).await?;
     if !meta.validate(&key) {
         return Err(ApiError::BadRequest("Invalid key for paste.".to_string()));
     }
+    if meta.hold.is_some() {
+        return Err(ApiError::OnHold);
+    }
+    if let Some(moderation) = &meta.moderation {
+        return Err(ApiError::Moderated(moderation.state));
+    }
 
-    modify(data, id, payload, req).await
+    let actor = Actor::key_holder(&req);
+    modify(data, id, payload, req, actor).await
 }
 
 pub async fn modify(
     data: web::Data<PasteState>,
     id: web::Path<String>,
-    mut payload: Multipart,
-    _req: HttpRequest,
+    payload: web::Payload,
+    req: HttpRequest,
+    actor: Actor,
 ) -> Result<HttpResponse, ApiError> {
     let mut response: Response<()> = Response {
         success: false,
         message: String::new(),
         info: None,
     };
-    if !data.storage.inner.exists(&id)? {
+    if !data.storage.inner.exists(&id).await? {
         return Err(ApiError::NotFound);
     }
-    let mut meta = data.storage.inner.get_meta(&id)?;
-
-    // Read multipart form
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let disposition = match field.content_disposition() {
-            Some(d) => d,
-            None => {
-                data.storage.inner.delete(&id).await?;
-                return Err(ApiError::BadRequest("Bad form: No disposition.".to_string()));
+    let mut meta = data.storage.inner.get_meta(&id).await?;
+    // Nothing is changed before the new content passed scanning
+    let content = match BodyFormat::of(&req) {
+        BodyFormat::Multipart => {
+            let mut payload = Multipart::new(req.headers(), payload);
+            let mut content = None;
+            // Read multipart form
+            while let Ok(Some(mut field)) = payload.try_next().await {
+                let disposition = match field.content_disposition() {
+                    Some(d) => d,
+                    None => {
+                        return Err(ApiError::BadRequest("Bad form: No disposition.".to_string()));
+                    }
+                };
+                match disposition.get_name() {
+                    Some("content") | Some("c") => {
+                        let (staged, file) = Staged::create(&data).await?;
+                        let mut file = data.scanner.wrap(file);
+                        read_field(&mut field, &mut file).await?;
+                        content = Some((staged, file.finish()));
+                    },
+                    Some("name") => {
+                        let mut buf: Vec<u8> = Vec::new();
+                        read_field(&mut field, &mut buf).await?;
+                        meta.name = Some(String::from_utf8(buf)?);
+                    },
+                    Some("expire_after") => {
+                        let mut buf: Vec<u8> = Vec::new();
+                        read_field(&mut field, &mut buf).await?;
+                        let m = String::from_utf8(buf)?;
+                        let minutes = m.parse::<i64>()?;
+                        if minutes > 0 {
+                            meta.expire_time = Some(Utc::now() + Duration::minutes(minutes));
+                        } else {
+                            return Err(ApiError::BadRequest("Bad expire time.".to_string()));
+                        }
+                    },
+                    _ => {
+                        return Err(ApiError::BadRequest("Bad form".to_string()));
+                    },
+                }
             }
-        };
-        match disposition.get_name() {
-            Some("content") | Some("c") => {
-                let mut file = data.storage.inner.update(&id).await?;
-                read_field(&mut field, &mut file).await?;
-            },
-            Some("name") => {
-                let mut buf: Vec<u8> = Vec::new();
-                read_field(&mut field, &mut buf).await?;
-                meta.name = Some(String::from_utf8(buf)?);
-            },
-            Some("expire_after") => {
-                let mut buf: Vec<u8> = Vec::new();
-                read_field(&mut field, &mut buf).await?;
-                let m = String::from_utf8(buf)?;
-                let minutes = m.parse::<i64>()?;
-                if minutes > 0 {
-                    meta.expire_time = Some(Utc::now() + Duration::minutes(minutes));
-                } else {
-                    data.storage.inner.delete(&id).await?;
-                    return Err(ApiError::BadRequest("Bad expire time.".to_string()));
+            content
+        }
+        BodyFormat::Json => {
+            let paste = api::read_json(payload).await?;
+            apply_options(&mut meta, paste.options)?;
+            match paste.content {
+                Some(content) if content.is_empty() => {
+                    return Err(ApiError::BadRequest("Bad form: Empty field".to_string()));
+                }
+                Some(content) => {
+                    let mut content = stream::iter(Some(Ok::<_, Infallible>(Bytes::from(content))));
+                    let (staged, file) = Staged::create(&data).await?;
+                    let mut file = data.scanner.wrap(file);
+                    write_stream(&mut content, &mut file).await?;
+                    Some((staged, file.finish()))
                 }
-            },
-            _ => {
-                data.storage.inner.delete(&id).await?;
-                return Err(ApiError::BadRequest("Bad form".to_string()));
-            },
+                None => None,
+            }
+        }
+        BodyFormat::Raw => {
+            apply_options(&mut meta, PasteOptions::from_request(&req)?)?;
+            let mut payload = payload.peekable();
+            // An empty body leaves the content alone
+            if Pin::new(&mut payload).peek().await.is_some() {
+                let (staged, file) = Staged::create(&data).await?;
+                let mut file = data.scanner.wrap(file);
+                write_stream(&mut payload, &mut file).await?;
+                Some((staged, file.finish()))
+            } else {
+                None
+            }
         }
+    };
+    let mut quarantined = false;
+    if let Some((staged, findings)) = content {
+        quarantined = apply_scan(&id, &mut meta, findings)?;
+        meta.size = staged.commit(&data, &id).await?;
     }
+
+    meta.revision += 1;
+    meta.modify_time = Some(Utc::now());
+
     // Write back meta
-    data.storage.inner.set_meta(&id, &meta)?;
+    data.storage.inner.set_meta(&id, &meta).await?;
+    if meta.expires_at().is_some() {
+        data.cleanup.notify();
+    }
+    data.audit.record(Operation::Modify, &id, actor);
+    if quarantined {
+        data.audit.record(Operation::Quarantine, &id, Actor::System);
+    }
+    data.webhooks.fire(Event::Modified, &id).await;
 
     // We have a success if we manage to get here
     response.success = true;
-    Ok(HttpResponse::Ok().json(response))
+    Ok(response.reply(&req))
+}
+
+/// Settings of a JSON or raw modification. Only what's in the multipart form can be changed.
+fn apply_options(meta: &mut PasteMeta, options: PasteOptions) -> Result<(), ApiError> {
+    if options.expire_after_inactive.is_some() || options.redact {
+        return Err(ApiError::BadRequest(
+            "Only content, name and expire_after can be modified.".to_string(),
+        ));
+    }
+    if let Some(t) = options.expire_time()? {
+        meta.expire_time = Some(t);
+    }
+    if options.name.is_some() {
+        meta.name = options.name;
+    }
+    Ok(())
 }
@@ -1,21 +1,28 @@
-use crate::api::{ApiError, Response, read_field};
+use crate::api::{
+    self, apply_scan, discard, read_field, text_reply, wants_text, write_stream, ApiError,
+    BodyFormat, PasteOptions, Response,
+};
+use crate::audit::{Actor, Operation};
+use crate::redact::RedactingWriter;
+use crate::storage::ScanFinding;
+use crate::webhook::Event;
 use crate::PasteState;
 
 use anyhow::Result;
 use actix_multipart::Multipart;
 use actix_web::{web, HttpRequest, HttpResponse};
-use chrono::prelude::*;
-use chrono::Duration;
-use futures::TryStreamExt;
+use bytes::Bytes;
+use futures::{stream, Stream, TryStreamExt};
 use log::{debug, info};
 use rand::{thread_rng, Rng};
-use serde::Serialize;
+use std::convert::Infallible;
+use tokio::io::AsyncWrite;
 
 const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz123456";
-const ID_LEN: usize = 6;
+pub const ID_LEN: usize = 6;
 const KEY_LEN: usize = 10;
 
-fn gen_random_chars(len: usize) -> String {
+pub fn gen_random_chars(len: usize) -> String {
     let mut rng = thread_rng();
 
     let res: String = (0..len)
@@ -28,73 +35,83 @@ fn gen_random_chars(len: usize) -> String {
     res
 }
 
-#[derive(Serialize)]
-struct Info {
-    id: String,
-    key: String,
-    expire_time: Option<DateTime<Utc>>,
+/// Whether `name` could be the id of a paste
+pub fn is_paste_id(name: &str) -> bool {
+    name.len() == ID_LEN && name.bytes().all(|c| CHARSET.contains(&c))
 }
 
+pub use rspb_client::types::Info;
+
 pub async fn post(
     data: web::Data<PasteState>,
-    mut payload: Multipart,
-    _req: HttpRequest,
+    payload: web::Payload,
+    req: HttpRequest,
 ) -> Result<HttpResponse, ApiError> {
-    // Get unused key
-    let mut id = gen_random_chars(ID_LEN);
-    while data.storage.inner.exists(&id)? {
-        id = gen_random_chars(ID_LEN);
-    }
-    let key = gen_random_chars(KEY_LEN);
-
+    let (id, key) = new_id(&data).await?;
 
-    // Set up empty values to be filled in (potentially)
-    let mut name: Option<String> = None;
-    let mut expire_time: Option<DateTime<Utc>> = None;
+    // Secrets replaced, once redaction is turned on
+    let mut redactions: Option<usize> = None;
+    let mut file = data.scanner.wrap(data.storage.inner.new(&id, &key).await?);
+    let res = match BodyFormat::of(&req) {
+        BodyFormat::Multipart => {
+            let payload = Multipart::new(req.headers(), payload);
+            read_multipart(payload, &mut file, &mut redactions).await
+        }
+        BodyFormat::Json => read_json(payload, &mut file, &mut redactions).await,
+        BodyFormat::Raw => read_raw(&req, payload, &mut file, &mut redactions).await,
+    };
+    create(&data, &req, id, key, file.finish(), res, redactions).await
+}
 
-    // iterate over multipart stream
-    let mut file = data.storage.inner.new(&id, &key).await?;
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let disposition = match field.content_disposition() {
-            Some(d) => d,
-            None => {
-                data.storage.inner.delete(&id).await?;
-                return Err(ApiError::BadRequest("Bad form: No disposition.".to_string()));
+/// Unused paste id, and a key for it
+pub(crate) async fn new_id(data: &PasteState) -> Result<(String, String), ApiError> {
+    loop {
+        let id = gen_random_chars(ID_LEN);
+        if data.storage.inner.exists(&id).await? {
+            continue;
+        }
+        if let Some(trash) = &data.trash {
+            if trash.contains(&id).await {
+                continue;
             }
-        };
-        match disposition.get_name() {
-            Some("content") | Some("c") => {
-                read_field(&mut field, &mut file).await?;
-            },
-            Some("name") => {
-                let mut buf: Vec<u8> = Vec::new();
-                read_field(&mut field, &mut buf).await?;
-                name = Some(String::from_utf8(buf)?);
-            },
-            Some("expire_after") => {
-                let mut buf: Vec<u8> = Vec::new();
-                read_field(&mut field, &mut buf).await?;
-                let m = String::from_utf8(buf)?;
-                let minutes = m.parse::<i64>()?;
-                if minutes > 0 {
-                    expire_time = Some(Utc::now() + Duration::minutes(minutes));
-                } else {
-                    data.storage.inner.delete(&id).await?;
-                    return Err(ApiError::BadRequest("Bad expire time.".to_string()));
-                }
-            },
-            _ => {
-                data.storage.inner.delete(&id).await?;
-                return Err(ApiError::BadRequest("Bad form".to_string()));
-            },
         }
+        return Ok((id, gen_random_chars(KEY_LEN)));
     }
+}
+
+/// Turn content written to a new paste into a proper paste, with the settings in `res` and what
+/// scanning the content found
+pub(crate) async fn create(
+    data: &PasteState,
+    req: &HttpRequest,
+    id: String,
+    key: String,
+    findings: Vec<ScanFinding>,
+    res: Result<PasteOptions, ApiError>,
+    redactions: Option<usize>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = res.and_then(|options| {
+        Ok((
+            options.expire_time()?,
+            options.expire_after_inactive()?,
+            options.name,
+        ))
+    });
+    let (expire_time, expire_after_inactive, name) = match settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            // Never became a paste, and may hold content that must not be kept
+            discard(data, &id).await?;
+            return Err(err);
+        }
+    };
+    let expire_after_inactive = expire_after_inactive.or(data.config.expire_after_inactive);
 
     // Update size && Check if it's an empty paste
     data.storage.inner.update_size(&id).await?;
-    let mut meta = data.storage.inner.get_meta(&id)?;
+    let mut meta = data.storage.inner.get_meta(&id).await?;
     if meta.size == 0 {
-        data.storage.inner.delete(&id).await?;
+        discard(data, &id).await?;
         return Err(ApiError::BadRequest(
             "Cannot create paste with no content.".to_string(),
         ));
@@ -105,25 +122,176 @@ pub async fn post(
         meta.expire_time = Some(t);
     }
 
+    meta.expire_after_inactive = expire_after_inactive;
+    let quarantined = match apply_scan(&id, &mut meta, findings) {
+        Err(err @ ApiError::Rejected(_)) => {
+            discard(data, &id).await?;
+            return Err(err);
+        }
+        res => res?,
+    };
+
     // Set name
     if name.is_some() && name.as_ref().unwrap().len() < 8000 {
         meta.name = name;
     }
 
     // Write back meta
-    data.storage.inner.set_meta(&id, &meta)?;
+    data.storage.inner.set_meta(&id, &meta).await?;
+    if meta.expires_at().is_some() {
+        data.cleanup.notify();
+    }
+    data.audit.record(Operation::Create, &id, Actor::uploader(req));
+    if quarantined {
+        data.audit.record(Operation::Quarantine, &id, Actor::System);
+    }
+    data.webhooks.fire(Event::Created, &id).await;
 
     // Success!
     info!("NEW paste {:?} expire at {:?}.", id, expire_time);
+    let message = if quarantined {
+        "Paste is quarantined pending review.".to_string()
+    } else {
+        String::new()
+    };
+    if wants_text(req.path(), req.headers()) {
+        // One value per line for shell scripts, then any notices
+        let url = format!("{}/{}", data.config.site.url.trim_end_matches('/'), id);
+        let expiry = meta
+            .expires_at()
+            .map_or_else(|| "never".to_string(), |t| t.to_rfc3339());
+        let mut body = format!("{}\n{}\n{}\n", url, key, expiry);
+        if let Some(count) = redactions {
+            body.push_str(&format!("Redacted {} secrets.\n", count));
+        }
+        if quarantined {
+            body.push_str(&format!("{}\n", message));
+        }
+        return Ok(text_reply(body));
+    }
     let res: Response<Info> = Response {
         success: true,
-        message: String::new(),
+        message,
         info: Some(Info {
             id,
             key,
             expire_time,
+            expire_after_inactive,
+            redactions,
         }),
     };
 
     Ok(HttpResponse::Ok().json(&res))
 }
+
+/// Write content to a new paste, redacted if `redactions` is set. Returns the content size.
+pub(crate) async fn write_content<S, E, W>(
+    stream: &mut S,
+    file: &mut W,
+    redactions: &mut Option<usize>,
+) -> Result<usize, ApiError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match redactions.as_mut() {
+        Some(count) => {
+            let mut writer = RedactingWriter::new(file);
+            let size = write_stream(stream, &mut writer).await?;
+            *count += writer.redactions();
+            Ok(size)
+        }
+        None => write_stream(stream, file).await,
+    }
+}
+
+async fn read_multipart<W: AsyncWrite + Unpin>(
+    mut payload: Multipart,
+    file: &mut W,
+    redactions: &mut Option<usize>,
+) -> Result<PasteOptions, ApiError> {
+    let mut options = PasteOptions::default();
+    let mut has_content = false;
+
+    // iterate over multipart stream
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let disposition = match field.content_disposition() {
+            Some(d) => d,
+            None => {
+                return Err(ApiError::BadRequest("Bad form: No disposition.".to_string()));
+            }
+        };
+        match disposition.get_name() {
+            Some("content") | Some("c") => {
+                has_content = true;
+                if write_content(&mut field, file, redactions).await? == 0 {
+                    return Err(ApiError::BadRequest("Bad form: Empty field".to_string()));
+                }
+            },
+            Some("redact") => {
+                let mut buf: Vec<u8> = Vec::new();
+                read_field(&mut field, &mut buf).await?;
+                let redact = match String::from_utf8(buf)?.as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(ApiError::BadRequest("Bad redact value.".to_string())),
+                };
+                if redact && has_content {
+                    return Err(ApiError::BadRequest(
+                        "redact must come before content.".to_string(),
+                    ));
+                }
+                *redactions = if redact { Some(0) } else { None };
+            },
+            Some("name") => {
+                let mut buf: Vec<u8> = Vec::new();
+                read_field(&mut field, &mut buf).await?;
+                options.name = Some(String::from_utf8(buf)?);
+            },
+            Some("expire_after") => {
+                let mut buf: Vec<u8> = Vec::new();
+                read_field(&mut field, &mut buf).await?;
+                options.expire_after = Some(String::from_utf8(buf)?.parse()?);
+            },
+            Some("expire_after_inactive") => {
+                let mut buf: Vec<u8> = Vec::new();
+                read_field(&mut field, &mut buf).await?;
+                options.expire_after_inactive = Some(String::from_utf8(buf)?.parse()?);
+            },
+            _ => {
+                return Err(ApiError::BadRequest("Bad form".to_string()));
+            },
+        }
+    }
+    Ok(options)
+}
+
+async fn read_json<W: AsyncWrite + Unpin>(
+    payload: web::Payload,
+    file: &mut W,
+    redactions: &mut Option<usize>,
+) -> Result<PasteOptions, ApiError> {
+    let paste = api::read_json(payload).await?;
+    if paste.options.redact {
+        *redactions = Some(0);
+    }
+    if let Some(content) = paste.content {
+        let mut content = stream::iter(Some(Ok::<_, Infallible>(Bytes::from(content))));
+        write_content(&mut content, file, redactions).await?;
+    }
+    Ok(paste.options)
+}
+
+async fn read_raw<W: AsyncWrite + Unpin>(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+    file: &mut W,
+    redactions: &mut Option<usize>,
+) -> Result<PasteOptions, ApiError> {
+    let options = PasteOptions::from_request(req)?;
+    if options.redact {
+        *redactions = Some(0);
+    }
+    write_content(&mut payload, file, redactions).await?;
+    Ok(options)
+}
@@ -0,0 +1,1351 @@
+//! OpenAPI 3 description of the API, served at `/api/openapi.json`.
+//!
+//! Reply types describe themselves through [`Schema`], next to the list of operations below. The
+//! tests here check serialized replies against their schemas.
+//! Operations of the API at the root are repeated under API v1.
+//! `tests/openapi.rs` fails if the paths here and the routes of the app drift apart.
+use crate::api::v1::PREFIX;
+use crate::api::{ErrorCode, ErrorResponse};
+use crate::audit::{Actor, AuditEntry, Operation};
+use crate::storage::trashstorage::{TrashReason, TrashedPaste};
+use crate::storage::{CacheStats, Inconsistency};
+use crate::PasteState;
+
+use actix_web::{web, HttpResponse};
+use rspb_client::types::{
+    Info, LegalHold, Moderation, ModerationState, PasteAdminMeta, PasteDetails, PrivateDetails,
+    Report, ReportedPaste, ScanAction, ScanFinding, ScanResult, UploadInfo,
+};
+use serde_json::{json, Map, Value};
+
+/// A type sent in API replies, described as a JSON schema
+pub trait Schema {
+    /// Name under `components/schemas`
+    const NAME: &'static str;
+    fn schema() -> Value;
+}
+
+fn schema_ref<T: Schema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::NAME) })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn time() -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+fn integer(minimum: i64) -> Value {
+    json!({ "type": "integer", "minimum": minimum })
+}
+
+fn string_enum(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn nullable(schema: Value) -> Value {
+    // Siblings of a $ref are ignored, so references are wrapped
+    if schema.get("$ref").is_some() {
+        return json!({ "allOf": [schema], "nullable": true });
+    }
+    let mut schema = schema;
+    schema["nullable"] = json!(true);
+    schema
+}
+
+/// Object with all `properties` required, nullable ones are still sent as `null`
+fn object(properties: Value) -> Value {
+    let required: Vec<&String> = properties.as_object().unwrap().keys().collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// Object of an internally tagged enum, told apart by `kind`
+fn variant(kind: &str, properties: Value) -> Value {
+    let mut properties = properties;
+    properties["kind"] = string_enum(&[kind]);
+    object(properties)
+}
+
+impl Schema for Info {
+    const NAME: &'static str = "Info";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "key": string(),
+            "expire_time": nullable(time()),
+            "expire_after_inactive": nullable(integer(1)),
+            "redactions": nullable(integer(0)),
+        }))
+    }
+}
+
+impl Schema for UploadInfo {
+    const NAME: &'static str = "UploadInfo";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "offset": integer(0),
+            "length": nullable(integer(1)),
+            "expire_time": time(),
+        }))
+    }
+}
+
+impl Schema for PasteDetails {
+    const NAME: &'static str = "PasteDetails";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "name": nullable(string()),
+            "size": integer(0),
+            "content_type": string(),
+            "create_time": time(),
+            "expire_time": nullable(time()),
+            "atime": nullable(time()),
+            "expire_after_inactive": nullable(integer(1)),
+            "on_hold": { "type": "boolean" },
+            "private": nullable(schema_ref::<PrivateDetails>()),
+        }))
+    }
+}
+
+impl Schema for PrivateDetails {
+    const NAME: &'static str = "PrivateDetails";
+    fn schema() -> Value {
+        object(json!({
+            "views": integer(0),
+            "revision": integer(1),
+            "modify_time": nullable(time()),
+            "hold": nullable(schema_ref::<LegalHold>()),
+            "moderation": nullable(schema_ref::<Moderation>()),
+        }))
+    }
+}
+
+impl Schema for PasteAdminMeta {
+    const NAME: &'static str = "PasteAdminMeta";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "create_time": time(),
+            "expire_time": nullable(time()),
+            "atime": nullable(time()),
+            "name": nullable(string()),
+            "size": integer(0),
+            "expire_after_inactive": nullable(integer(1)),
+            "hold": nullable(schema_ref::<LegalHold>()),
+            "moderation": nullable(schema_ref::<Moderation>()),
+            "scan": nullable(schema_ref::<ScanResult>()),
+        }))
+    }
+}
+
+impl Schema for LegalHold {
+    const NAME: &'static str = "LegalHold";
+    fn schema() -> Value {
+        object(json!({ "by": string(), "time": time() }))
+    }
+}
+
+impl Schema for ModerationState {
+    const NAME: &'static str = "ModerationState";
+    fn schema() -> Value {
+        string_enum(&["quarantined", "hidden"])
+    }
+}
+
+impl Schema for Moderation {
+    const NAME: &'static str = "Moderation";
+    fn schema() -> Value {
+        object(json!({
+            "state": schema_ref::<ModerationState>(),
+            "by": nullable(string()),
+            "time": time(),
+        }))
+    }
+}
+
+impl Schema for ScanAction {
+    const NAME: &'static str = "ScanAction";
+    fn schema() -> Value {
+        string_enum(&["flag", "quarantine", "reject"])
+    }
+}
+
+impl Schema for ScanFinding {
+    const NAME: &'static str = "ScanFinding";
+    fn schema() -> Value {
+        object(json!({ "rule": string(), "action": schema_ref::<ScanAction>() }))
+    }
+}
+
+impl Schema for ScanResult {
+    const NAME: &'static str = "ScanResult";
+    fn schema() -> Value {
+        object(json!({ "time": time(), "findings": array(schema_ref::<ScanFinding>()) }))
+    }
+}
+
+impl Schema for Operation {
+    const NAME: &'static str = "Operation";
+    fn schema() -> Value {
+        string_enum(&[
+            "create",
+            "modify",
+            "delete",
+            "expire",
+            "hold",
+            "release",
+            "restore",
+            "purge",
+            "quarantine",
+            "hide",
+            "reinstate",
+        ])
+    }
+}
+
+impl Schema for Actor {
+    const NAME: &'static str = "Actor";
+    fn schema() -> Value {
+        json!({ "oneOf": [
+            variant("uploader", json!({ "ip": nullable(string()) })),
+            variant("key_holder", json!({ "ip": nullable(string()) })),
+            variant("admin", json!({ "name": string(), "ip": nullable(string()) })),
+            variant("system", json!({})),
+        ]})
+    }
+}
+
+impl Schema for AuditEntry {
+    const NAME: &'static str = "AuditEntry";
+    fn schema() -> Value {
+        object(json!({
+            "time": time(),
+            "operation": schema_ref::<Operation>(),
+            "id": string(),
+            "actor": schema_ref::<Actor>(),
+        }))
+    }
+}
+
+impl Schema for CacheStats {
+    const NAME: &'static str = "CacheStats";
+    fn schema() -> Value {
+        object(json!({
+            "hits": integer(0),
+            "misses": integer(0),
+            "entries": integer(0),
+            "bytes": integer(0),
+            "budget": integer(0),
+        }))
+    }
+}
+
+impl Schema for Inconsistency {
+    const NAME: &'static str = "Inconsistency";
+    fn schema() -> Value {
+        json!({ "oneOf": [
+            variant("orphan_file", json!({ "id": string() })),
+            variant("missing_content", json!({ "id": string() })),
+            variant("wrong_size", json!({
+                "id": string(),
+                "recorded": integer(0),
+                "actual": integer(0),
+            })),
+            variant("unreadable", json!({ "id": string(), "reason": string() })),
+        ]})
+    }
+}
+
+impl Schema for TrashReason {
+    const NAME: &'static str = "TrashReason";
+    fn schema() -> Value {
+        string_enum(&["deleted", "expired"])
+    }
+}
+
+impl Schema for TrashedPaste {
+    const NAME: &'static str = "TrashedPaste";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "deleted_time": time(),
+            "purge_time": time(),
+            "reason": schema_ref::<TrashReason>(),
+            "name": nullable(string()),
+            "size": integer(0),
+        }))
+    }
+}
+
+impl Schema for Report {
+    const NAME: &'static str = "Report";
+    fn schema() -> Value {
+        object(json!({
+            "time": time(),
+            "reason": nullable(string()),
+            "ip": nullable(string()),
+        }))
+    }
+}
+
+impl Schema for ReportedPaste {
+    const NAME: &'static str = "ReportedPaste";
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "name": nullable(string()),
+            "moderation": nullable(schema_ref::<Moderation>()),
+            "reports": array(schema_ref::<Report>()),
+        }))
+    }
+}
+
+impl Schema for ErrorCode {
+    const NAME: &'static str = "ErrorCode";
+    fn schema() -> Value {
+        string_enum(&[
+            "bad_request",
+            "unauthorized",
+            "forbidden",
+            "not_found",
+            "method_not_allowed",
+            "payload_too_large",
+            "on_hold",
+            "quarantined",
+            "hidden",
+            "rejected",
+            "conflict",
+            "internal",
+        ])
+    }
+}
+
+impl Schema for ErrorResponse {
+    const NAME: &'static str = "ErrorResponse";
+    fn schema() -> Value {
+        object(json!({
+            "success": { "type": "boolean", "enum": [false] },
+            "code": schema_ref::<ErrorCode>(),
+            "message": string(),
+        }))
+    }
+}
+
+fn components() -> Map<String, Value> {
+    let mut schemas = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        schemas.insert(name.to_string(), schema);
+    };
+    add(Info::NAME, Info::schema());
+    add(UploadInfo::NAME, UploadInfo::schema());
+    add(PasteDetails::NAME, PasteDetails::schema());
+    add(PrivateDetails::NAME, PrivateDetails::schema());
+    add(PasteAdminMeta::NAME, PasteAdminMeta::schema());
+    add(LegalHold::NAME, LegalHold::schema());
+    add(ModerationState::NAME, ModerationState::schema());
+    add(Moderation::NAME, Moderation::schema());
+    add(ScanAction::NAME, ScanAction::schema());
+    add(ScanFinding::NAME, ScanFinding::schema());
+    add(ScanResult::NAME, ScanResult::schema());
+    add(Operation::NAME, Operation::schema());
+    add(Actor::NAME, Actor::schema());
+    add(AuditEntry::NAME, AuditEntry::schema());
+    add(CacheStats::NAME, CacheStats::schema());
+    add(Inconsistency::NAME, Inconsistency::schema());
+    add(TrashReason::NAME, TrashReason::schema());
+    add(TrashedPaste::NAME, TrashedPaste::schema());
+    add(Report::NAME, Report::schema());
+    add(ReportedPaste::NAME, ReportedPaste::schema());
+    add(ErrorCode::NAME, ErrorCode::schema());
+    add(ErrorResponse::NAME, ErrorResponse::schema());
+    schemas
+}
+
+/// Body of a JSON reply, see [`crate::api::Response`]
+fn reply(description: &str, info: Option<Value>) -> Value {
+    let info = info.map(nullable).unwrap_or_else(|| json!({ "nullable": true }));
+    json!({
+        "description": description,
+        "content": {
+            "application/json": { "schema": object(json!({
+                "success": { "type": "boolean" },
+                "message": string(),
+                "info": info,
+            }))},
+            "text/plain": { "schema": string() },
+        },
+    })
+}
+
+/// Plain text clients get the URL, key and expire time of a new paste on separate lines
+fn created() -> Value {
+    let mut reply = reply("Paste created", Some(schema_ref::<Info>()));
+    reply["content"]["text/plain"]["example"] =
+        json!("https://example.com/fcmg0q\nNbzeQTHTNq\nnever\n");
+    reply
+}
+
+/// Plain text error of paste content
+fn text_error(description: &str) -> Value {
+    json!({ "description": description, "content": { "text/plain": { "schema": string() } } })
+}
+
+fn html(description: &str) -> Value {
+    json!({ "description": description, "content": { "text/html": { "schema": string() } } })
+}
+
+/// Errors at the root, mapped from [`crate::api::ApiError`]
+fn errors() -> Value {
+    let error = |description: &str| {
+        let mut reply = reply(description, None);
+        reply["content"]["application/json"]["schema"]["properties"]["success"]["enum"] =
+            json!([false]);
+        reply
+    };
+    json!({
+        "BadRequest": error("Malformed request, or bad form values"),
+        "Unauthorized": { "description": "Admin login missing or wrong" },
+        "Forbidden": error("Wrong key"),
+        "NotFound": error("No such paste"),
+        "Hidden": error("Removed by moderators"),
+        "OnHold": error("Under legal hold, can't be changed"),
+        "Quarantined": error("Quarantined pending review"),
+        "Rejected": error("Content matched a scan rule set to reject"),
+        "Conflict": error("Upload at another offset, or busy with another chunk"),
+        "PayloadTooLarge": error("Upload larger than the server allows"),
+        "UploadNotFound": error("No such upload, or it was dropped"),
+    })
+}
+
+fn error(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/responses/{}", name) })
+}
+
+fn path_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": string(),
+    })
+}
+
+fn paste_id() -> Value {
+    path_param("paste_id", "Paste id")
+}
+
+fn upload_id() -> Value {
+    path_param("upload_id", "Upload id")
+}
+
+/// State of an upload, plain text clients get its URL and offset on separate lines
+fn upload_reply(description: &str) -> Value {
+    let mut reply = reply(description, Some(schema_ref::<UploadInfo>()));
+    reply["headers"] = json!({ "Upload-Offset": { "schema": integer(0) } });
+    reply["content"]["text/plain"]["example"] =
+        json!("https://example.com/uploads/3nd5kqa1zbm2w6hfxc0yrtp4\n0\n");
+    reply
+}
+
+fn query(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "description": description, "schema": schema })
+}
+
+fn key() -> Value {
+    json!({
+        "name": "Key",
+        "in": "header",
+        "required": true,
+        "description": "Key returned when the paste was created",
+        "schema": string(),
+    })
+}
+
+/// Settings of a new paste, as query parameters
+fn paste_options(new: bool) -> Vec<Value> {
+    let mut params = vec![
+        query("name", "File name of the paste", string()),
+        query("expire_after", "Minutes until the paste expires", integer(1)),
+    ];
+    if new {
+        params.push(query(
+            "expire_after_inactive",
+            "Days without views until the paste expires",
+            integer(1),
+        ));
+        params.push(query(
+            "redact",
+            "Replace secrets with placeholders before the paste is stored",
+            json!({ "type": "boolean" }),
+        ));
+    }
+    params
+}
+
+/// Content and settings of a paste, in any of the accepted body formats
+fn paste_body(new: bool) -> Value {
+    let mut form = json!({
+        "content": { "type": "string", "format": "binary" },
+        "c": { "type": "string", "format": "binary", "description": "Same as content" },
+        "name": string(),
+        "expire_after": integer(1),
+    });
+    let mut fields = json!({
+        "content": string(),
+        "name": string(),
+        "expire_after": integer(1),
+    });
+    if new {
+        for properties in [&mut form, &mut fields] {
+            properties["expire_after_inactive"] = integer(1);
+            properties["redact"] = json!({ "type": "boolean" });
+        }
+    }
+    json!({
+        "required": new,
+        "content": {
+            "multipart/form-data": { "schema": { "type": "object", "properties": form } },
+            "application/json": { "schema": { "type": "object", "properties": fields } },
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+        },
+    })
+}
+
+/// Every route as (path, method, operation)
+fn operations() -> Vec<(&'static str, &'static str, Value)> {
+    let admin_only = |mut operation: Value| {
+        operation["tags"] = json!(["admin"]);
+        operation["security"] = json!([{ "admin": [] }]);
+        operation["responses"]["401"] = error("Unauthorized");
+        operation
+    };
+
+    vec![
+        ("/", "get", json!({
+            "tags": ["pages"],
+            "summary": "Start page with usage instructions",
+            "responses": { "200": html("Start page") },
+        })),
+        ("/", "post", json!({
+            "tags": ["pastes"],
+            "summary": "Create a paste",
+            "description": "Settings can also be sent as the headers Name, Expire-After, \
+                            Expire-After-Inactive and Redact, query parameters win.",
+            "parameters": paste_options(true),
+            "requestBody": paste_body(true),
+            "responses": {
+                "200": created(),
+                "400": error("BadRequest"),
+                "422": error("Rejected"),
+            },
+        })),
+        ("/f", "get", json!({
+            "tags": ["pages"],
+            "summary": "Form to create and change pastes",
+            "responses": { "200": html("Form") },
+        })),
+        ("/api/openapi.json", "get", json!({
+            "tags": ["pages"],
+            "summary": "This document",
+            "responses": { "200": {
+                "description": "OpenAPI document",
+                "content": { "application/json": { "schema": { "type": "object" } } },
+            }},
+        })),
+        ("/api/docs", "get", json!({
+            "tags": ["pages"],
+            "summary": "This document as a web page",
+            "responses": { "200": html("API documentation") },
+        })),
+        ("/{paste_id}", "get", json!({
+            "tags": ["pastes"],
+            "summary": "Paste content",
+            "description": "Anything after the six characters of the id, like a file \
+                            extension, is ignored.",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": {
+                    "description": "Content of the paste",
+                    "headers": { "Name": { "schema": string() } },
+                    "content": { "application/octet-stream": {
+                        "schema": { "type": "string", "format": "binary" },
+                    }},
+                },
+                "404": text_error("No such paste"),
+                "410": text_error("Removed by moderators"),
+                "451": text_error("Quarantined pending review"),
+            },
+        })),
+        ("/{paste_id}", "head", json!({
+            "tags": ["pastes"],
+            "summary": "Paste name and size",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": {
+                    "description": "Headers of the content",
+                    "headers": {
+                        "Name": { "schema": string() },
+                        "Content-Length": { "schema": integer(0) },
+                    },
+                },
+                "404": { "description": "No such paste" },
+                "410": { "description": "Removed by moderators" },
+                "451": { "description": "Quarantined pending review" },
+            },
+        })),
+        ("/{paste_id}", "put", json!({
+            "tags": ["pastes"],
+            "summary": "Change a paste",
+            "description": "Replaces the content if there is any, and changes the name or \
+                            expire time.",
+            "parameters": ([vec![paste_id(), key()], paste_options(false)].concat()),
+            "requestBody": paste_body(false),
+            "responses": {
+                "200": reply("Paste changed", None),
+                "400": error("BadRequest"),
+                "403": error("Forbidden"),
+                "404": error("NotFound"),
+                "410": error("Hidden"),
+                "422": error("Rejected"),
+                "423": error("OnHold"),
+                "451": error("Quarantined"),
+            },
+        })),
+        ("/{paste_id}", "delete", json!({
+            "tags": ["pastes"],
+            "summary": "Delete a paste",
+            "parameters": [paste_id(), key()],
+            "responses": {
+                "200": reply("Paste deleted", None),
+                "403": error("Forbidden"),
+                "404": error("NotFound"),
+                "423": error("OnHold"),
+            },
+        })),
+        ("/{paste_id}/meta", "get", json!({
+            "tags": ["pastes"],
+            "summary": "Paste metadata",
+            "description": "The key holder and admins also get view counts and revisions, and \
+                            can read the metadata of pastes taken offline by moderators.",
+            "parameters": [
+                paste_id(),
+                {
+                    "name": "Key",
+                    "in": "header",
+                    "description": "Key returned when the paste was created",
+                    "schema": string(),
+                },
+            ],
+            "security": [{}, { "admin": [] }],
+            "responses": {
+                "200": reply("Paste metadata", Some(schema_ref::<PasteDetails>())),
+                "401": error("Unauthorized"),
+                "403": error("Forbidden"),
+                "404": error("NotFound"),
+                "410": error("Hidden"),
+                "451": error("Quarantined"),
+            },
+        })),
+        ("/{paste_id}/report", "post", json!({
+            "tags": ["pastes"],
+            "summary": "Report a paste to moderators",
+            "parameters": [paste_id()],
+            "requestBody": { "content": {
+                "application/x-www-form-urlencoded": { "schema": {
+                    "type": "object",
+                    "properties": { "reason": string() },
+                }},
+            }},
+            "responses": {
+                "200": reply("Report recorded", None),
+                "404": error("NotFound"),
+            },
+        })),
+        ("/uploads", "post", json!({
+            "tags": ["uploads"],
+            "summary": "Start a resumable upload",
+            "description": "Settings of the paste are sent like for raw uploads, as headers or \
+                            query parameters. Send the content with PATCH, and finish the \
+                            upload with POST. Uploads without new content for a while are \
+                            dropped.",
+            "parameters": ([
+                vec![json!({
+                    "name": "Upload-Length",
+                    "in": "header",
+                    "description": "Size of the content, if known. Chunks can't go past it, \
+                                    and the upload can't be finished before it's reached. \
+                                    Can't be more than the server allows.",
+                    "schema": integer(1),
+                })],
+                paste_options(true),
+            ].concat()),
+            "responses": {
+                "200": upload_reply("Upload started"),
+                "400": error("BadRequest"),
+                "413": error("PayloadTooLarge"),
+            },
+        })),
+        ("/uploads/{upload_id}", "get", json!({
+            "tags": ["uploads"],
+            "summary": "Offset an upload is at",
+            "description": "Where to resume after a broken connection.",
+            "parameters": [upload_id()],
+            "responses": {
+                "200": upload_reply("State of the upload"),
+                "404": error("UploadNotFound"),
+            },
+        })),
+        ("/uploads/{upload_id}", "patch", json!({
+            "tags": ["uploads"],
+            "summary": "Add a chunk of content",
+            "description": "If the connection breaks, what arrived is kept. Get the offset and \
+                            send the rest from there.",
+            "parameters": [
+                upload_id(),
+                {
+                    "name": "Upload-Offset",
+                    "in": "header",
+                    "required": true,
+                    "description": "Where the chunk starts, has to be the offset of the upload",
+                    "schema": integer(0),
+                },
+            ],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/octet-stream": {
+                        "schema": { "type": "string", "format": "binary" },
+                    },
+                },
+            },
+            "responses": {
+                "200": upload_reply("Chunk stored"),
+                "400": error("BadRequest"),
+                "404": error("UploadNotFound"),
+                "409": error("Conflict"),
+                "413": error("PayloadTooLarge"),
+            },
+        })),
+        ("/uploads/{upload_id}", "post", json!({
+            "tags": ["uploads"],
+            "summary": "Finish an upload into a paste",
+            "description": "The upload is gone afterwards. It's kept if the paste can't be \
+                            created, unless the content was rejected.",
+            "parameters": [upload_id()],
+            "responses": {
+                "200": created(),
+                "400": error("BadRequest"),
+                "404": error("UploadNotFound"),
+                "409": error("Conflict"),
+                "422": error("Rejected"),
+            },
+        })),
+        ("/uploads/{upload_id}", "delete", json!({
+            "tags": ["uploads"],
+            "summary": "Give up on an upload",
+            "parameters": [upload_id()],
+            "responses": {
+                "200": reply("Upload dropped", None),
+                "404": error("UploadNotFound"),
+                "409": error("Conflict"),
+            },
+        })),
+        ("/{paste_id}/audio", "get", json!({
+            "tags": ["pages"],
+            "summary": "Paste played in an audio player",
+            "parameters": [paste_id()],
+            "responses": { "200": html("Audio player") },
+        })),
+        ("/{paste_id}/{lang}", "get", json!({
+            "tags": ["pages"],
+            "summary": "Paste with syntax highlighting",
+            "parameters": [
+                paste_id(),
+                path_param("lang", "Language or file extension to highlight as"),
+            ],
+            "responses": { "200": html("Highlighted paste") },
+        })),
+        ("/admin/audit", "get", admin_only(json!({
+            "summary": "Audit log, newest entries first",
+            "parameters": [
+                query("id", "Only entries of this paste", string()),
+                query("operation", "Only entries of this operation", schema_ref::<Operation>()),
+                query("since", "Only entries at or after this time", time()),
+                query("until", "Only entries before this time", time()),
+                query("limit", "Most entries to return", integer(0)),
+                query("offset", "Entries to skip", integer(0)),
+            ],
+            "responses": {
+                "200": reply("Matching entries", Some(array(schema_ref::<AuditEntry>()))),
+                "400": error("BadRequest"),
+            },
+        }))),
+        ("/admin/cache", "get", admin_only(json!({
+            "summary": "In-process cache statistics",
+            "responses": {
+                "200": reply("Statistics, none if there's no cache",
+                             Some(schema_ref::<CacheStats>())),
+            },
+        }))),
+        ("/admin/fsck", "get", admin_only(json!({
+            "summary": "Check the storage for inconsistencies",
+            "responses": {
+                "200": reply("Problems found", Some(array(schema_ref::<Inconsistency>()))),
+            },
+        }))),
+        ("/admin/fsck", "post", admin_only(json!({
+            "summary": "Check the storage and repair what was found",
+            "responses": {
+                "200": reply("Problems repaired", Some(array(schema_ref::<Inconsistency>()))),
+            },
+        }))),
+        ("/admin/list", "get", admin_only(json!({
+            "summary": "List pastes, newest first",
+            "parameters": [
+                query("search", "Part of the paste id or name", string()),
+                query("expire_before", "Only pastes expiring before this time", time()),
+                query("limit", "Most pastes to return", integer(0)),
+                query("offset", "Pastes to skip", integer(0)),
+            ],
+            "responses": {
+                "200": reply("Matching pastes", Some(array(schema_ref::<PasteAdminMeta>()))),
+            },
+        }))),
+        ("/admin/reports", "get", admin_only(json!({
+            "summary": "Moderation queue, most reported first",
+            "responses": {
+                "200": reply("Reported pastes",
+                             Some(array(schema_ref::<ReportedPaste>()))),
+            },
+        }))),
+        ("/admin/reports/{paste_id}", "delete", admin_only(json!({
+            "summary": "Dismiss the reports of a paste",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Reports dismissed", None),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/trash", "get", admin_only(json!({
+            "summary": "Deleted and expired pastes that can be restored",
+            "responses": {
+                "200": reply("Trashed pastes", Some(array(schema_ref::<TrashedPaste>()))),
+                "400": error("BadRequest"),
+            },
+        }))),
+        ("/admin/trash/{paste_id}", "post", admin_only(json!({
+            "summary": "Restore a paste from the trash",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Paste restored", None),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/trash/{paste_id}", "delete", admin_only(json!({
+            "summary": "Purge a paste from the trash",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Paste purged", None),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}/hold", "post", admin_only(json!({
+            "summary": "Place a legal hold on a paste",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Hold placed", Some(schema_ref::<LegalHold>())),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}/hold", "delete", admin_only(json!({
+            "summary": "Release the legal hold of a paste",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Hold released", None),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}/moderation", "post", admin_only(json!({
+            "summary": "Quarantine or hide a paste, closing its reports",
+            "parameters": [
+                paste_id(),
+                {
+                    "name": "state",
+                    "in": "query",
+                    "required": true,
+                    "schema": schema_ref::<ModerationState>(),
+                },
+            ],
+            "responses": {
+                "200": reply("Paste moderated", Some(schema_ref::<Moderation>())),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}/moderation", "delete", admin_only(json!({
+            "summary": "Put a moderated paste back online, closing its reports",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Paste reinstated", None),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}", "get", admin_only(json!({
+            "summary": "Paste content, even if moderated",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": {
+                    "description": "Content of the paste",
+                    "content": { "application/octet-stream": {
+                        "schema": { "type": "string", "format": "binary" },
+                    }},
+                },
+                "404": error("NotFound"),
+            },
+        }))),
+        ("/admin/{paste_id}", "put", admin_only(json!({
+            "summary": "Change a paste without its key",
+            "parameters": ([vec![paste_id()], paste_options(false)].concat()),
+            "requestBody": paste_body(false),
+            "responses": {
+                "200": reply("Paste changed", None),
+                "400": error("BadRequest"),
+                "404": error("NotFound"),
+                "422": error("Rejected"),
+                "423": error("OnHold"),
+            },
+        }))),
+        ("/admin/{paste_id}", "delete", admin_only(json!({
+            "summary": "Delete a paste without its key",
+            "parameters": [paste_id()],
+            "responses": {
+                "200": reply("Paste deleted", None),
+                "404": error("NotFound"),
+                "423": error("OnHold"),
+            },
+        }))),
+    ]
+}
+
+/// Where an API operation is found under API v1
+fn v1_path(path: &str) -> String {
+    match path {
+        "/" => format!("{}/pastes", PREFIX),
+        path if path.starts_with("/admin/") || path.starts_with("/uploads") => {
+            format!("{}{}", PREFIX, path)
+        }
+        path => format!("{}/pastes{}", PREFIX, path),
+    }
+}
+
+/// The same operation under API v1, which only answers in JSON and adds codes to errors
+fn v1(operation: &Value) -> Value {
+    let errors = errors();
+    let mut operation = operation.clone();
+    for (status, response) in operation["responses"].as_object_mut().unwrap() {
+        if let Some(content) = response["content"].as_object_mut() {
+            content.remove("text/plain");
+        }
+        if status.starts_with('4') || status.starts_with('5') {
+            let description = match response["$ref"].as_str() {
+                Some(target) => errors[target.rsplit('/').next().unwrap()]["description"].clone(),
+                None => response["description"].clone(),
+            };
+            *response = json!({
+                "description": description,
+                "content": { "application/json": { "schema": schema_ref::<ErrorResponse>() } },
+            });
+        }
+    }
+    operation["tags"] = json!(["v1"]);
+    operation
+}
+
+/// The whole document, `url` is the address of the server
+pub fn spec(url: &str) -> Value {
+    let mut paths = Map::new();
+    for (path, method, operation) in operations() {
+        if operation["tags"][0] != "pages" {
+            let item = paths.entry(v1_path(path)).or_insert_with(|| json!({}));
+            item[method] = v1(&operation);
+        }
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "rspb",
+            "description": "A really simple pastebin",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": url }],
+        "tags": [
+            { "name": "pastes", "description": "Creating and changing pastes" },
+            { "name": "uploads", "description": "Resumable uploads of large pastes" },
+            { "name": "admin", "description": "Moderation and maintenance, needs admin login" },
+            { "name": "pages", "description": "Web pages" },
+            {
+                "name": "v1",
+                "description": "The API under /api/v1, always answering in JSON, with codes in \
+                                errors",
+            },
+        ],
+        "paths": paths,
+        "components": {
+            "schemas": components(),
+            "responses": errors(),
+            "securitySchemes": { "admin": { "type": "http", "scheme": "basic" } },
+        },
+    })
+}
+
+pub async fn get(data: web::Data<PasteState>) -> HttpResponse {
+    HttpResponse::Ok().json(spec(&data.config.site.url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use serde::Serialize;
+    use std::collections::BTreeSet;
+
+    fn keys(value: &Value) -> Vec<String> {
+        let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Checks serialized replies against the schemas, noting which schemas, enum values and
+    /// variants they covered
+    struct Checker {
+        schemas: Map<String, Value>,
+        covered: BTreeSet<String>,
+    }
+
+    impl Checker {
+        fn new() -> Checker {
+            Checker {
+                schemas: components(),
+                covered: BTreeSet::new(),
+            }
+        }
+
+        fn check<T: Schema + Serialize>(&mut self, value: &T) {
+            let value = serde_json::to_value(value).unwrap();
+            self.validate(&value, &schema_ref::<T>(), T::NAME);
+        }
+
+        fn validate(&mut self, value: &Value, schema: &Value, at: &str) {
+            if let Some(target) = schema["$ref"].as_str() {
+                let name = target.trim_start_matches("#/components/schemas/");
+                let schema = self.schemas[name].clone();
+                self.covered.insert(name.to_string());
+                return self.validate(value, &schema, name);
+            }
+            if value.is_null() {
+                assert_eq!(schema["nullable"], true, "{}: not nullable", at);
+                return;
+            }
+            if let Some(schemas) = schema["allOf"].as_array() {
+                return schemas.iter().for_each(|s| self.validate(value, s, at));
+            }
+            if let Some(variants) = schema["oneOf"].as_array() {
+                let variant = variants
+                    .iter()
+                    .find(|v| v["properties"]["kind"]["enum"][0] == value["kind"])
+                    .unwrap_or_else(|| panic!("{}: no variant {}", at, value["kind"]))
+                    .clone();
+                self.covered.insert(format!("{}/{}", at, value["kind"]));
+                return self.validate(value, &variant, at);
+            }
+            if let Some(values) = schema["enum"].as_array() {
+                assert!(values.contains(value), "{}: {} missing", at, value);
+                self.covered.insert(format!("{}/{}", at, value));
+            }
+
+            match schema["type"].as_str() {
+                Some("object") => {
+                    assert_eq!(keys(value), keys(&schema["properties"]), "{}", at);
+                    for (key, field) in value.as_object().unwrap() {
+                        let at = format!("{}.{}", at, key);
+                        self.validate(field, &schema["properties"][key], &at);
+                    }
+                }
+                Some("array") => {
+                    for item in value.as_array().expect(at) {
+                        self.validate(item, &schema["items"], at);
+                    }
+                }
+                Some("string") => {
+                    let string = value.as_str().expect(at);
+                    if schema["format"] == "date-time" {
+                        assert!(DateTime::parse_from_rfc3339(string).is_ok(), "{}", at);
+                    }
+                }
+                Some("integer") => {
+                    let number = value.as_i64().expect(at);
+                    let minimum = schema["minimum"].as_i64().unwrap_or(i64::MIN);
+                    assert!(number >= minimum, "{}: {} too small", at, number);
+                }
+                Some("boolean") => assert!(value.is_boolean(), "{}", at),
+                _ => panic!("{}: schema without a type", at),
+            }
+        }
+
+        /// Schemas, enum values and variants of the components no reply had
+        fn uncovered(&self) -> Vec<String> {
+            let mut all = Vec::new();
+            for (name, schema) in &self.schemas {
+                all.push(name.clone());
+                for value in schema["enum"].as_array().into_iter().flatten() {
+                    all.push(format!("{}/{}", name, value));
+                }
+                for variant in schema["oneOf"].as_array().into_iter().flatten() {
+                    let kind = &variant["properties"]["kind"]["enum"][0];
+                    all.push(format!("{}/{}", name, kind));
+                }
+            }
+            all.retain(|name| !self.covered.contains(name));
+            all
+        }
+    }
+
+    fn time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 2, 7, 12, 51, 55).unwrap()
+    }
+
+    fn moderation() -> Moderation {
+        Moderation {
+            state: ModerationState::Quarantined,
+            by: None,
+            time: time(),
+        }
+    }
+
+    fn scan() -> ScanResult {
+        ScanResult {
+            time: time(),
+            findings: vec![ScanFinding {
+                rule: "spam".to_string(),
+                action: ScanAction::Flag,
+            }],
+        }
+    }
+
+    #[test]
+    fn schemas_match_replies() {
+        let mut checker = Checker::new();
+        paste_replies(&mut checker);
+        admin_replies(&mut checker);
+        error_replies(&mut checker);
+        let uncovered = checker.uncovered();
+        assert!(uncovered.is_empty(), "no reply has {:?}", uncovered);
+    }
+
+    fn paste_replies(checker: &mut Checker) {
+        checker.check(&Info {
+            id: "abcdef".to_string(),
+            key: "nbzethtnq1".to_string(),
+            expire_time: None,
+            expire_after_inactive: Some(3),
+            redactions: None,
+        });
+        let hold = LegalHold {
+            by: "admin".to_string(),
+            time: time(),
+        };
+        checker.check(&hold);
+        checker.check(&moderation());
+        checker.check(&scan());
+        checker.check(&scan().findings[0]);
+        checker.check(&PasteDetails {
+            id: "abcdef".to_string(),
+            name: None,
+            size: 12,
+            content_type: "text/plain".to_string(),
+            create_time: time(),
+            expire_time: None,
+            atime: None,
+            expire_after_inactive: None,
+            on_hold: false,
+            private: None,
+        });
+        checker.check(&UploadInfo {
+            id: "3nd5kqa1zbm2w6hfxc0yrtp4".to_string(),
+            offset: 0,
+            length: Some(12),
+            expire_time: time(),
+        });
+        checker.check(&PrivateDetails {
+            views: 3,
+            revision: 1,
+            modify_time: None,
+            hold: None,
+            moderation: None,
+        });
+        checker.check(&PasteAdminMeta {
+            id: "abcdef".to_string(),
+            create_time: time(),
+            expire_time: None,
+            atime: None,
+            name: None,
+            size: 12,
+            expire_after_inactive: None,
+            hold: Some(hold),
+            moderation: Some(moderation()),
+            scan: Some(scan()),
+        });
+        for state in &[ModerationState::Quarantined, ModerationState::Hidden] {
+            checker.check(state);
+        }
+        for action in &[ScanAction::Flag, ScanAction::Quarantine, ScanAction::Reject] {
+            checker.check(action);
+        }
+    }
+
+    fn admin_replies(checker: &mut Checker) {
+        let operations = [
+            Operation::Create,
+            Operation::Modify,
+            Operation::Delete,
+            Operation::Expire,
+            Operation::Hold,
+            Operation::Release,
+            Operation::Restore,
+            Operation::Purge,
+            Operation::Quarantine,
+            Operation::Hide,
+            Operation::Reinstate,
+        ];
+        for operation in &operations {
+            checker.check(operation);
+        }
+        let actors = [
+            Actor::Uploader { ip: None },
+            Actor::KeyHolder { ip: None },
+            Actor::Admin {
+                name: "admin".to_string(),
+                ip: None,
+            },
+            Actor::System,
+        ];
+        for actor in actors {
+            checker.check(&actor);
+            checker.check(&AuditEntry {
+                time: time(),
+                operation: Operation::Create,
+                id: "abcdef".to_string(),
+                actor,
+            });
+        }
+        checker.check(&CacheStats {
+            hits: 1,
+            misses: 2,
+            entries: 3,
+            bytes: 4,
+            budget: 5,
+        });
+        let id = || "abcdef".to_string();
+        let problems = [
+            Inconsistency::OrphanFile { id: id() },
+            Inconsistency::MissingContent { id: id() },
+            Inconsistency::WrongSize {
+                id: id(),
+                recorded: 1,
+                actual: 2,
+            },
+            Inconsistency::Unreadable {
+                id: id(),
+                reason: "broken".to_string(),
+            },
+        ];
+        for problem in &problems {
+            checker.check(problem);
+        }
+        for reason in [TrashReason::Deleted, TrashReason::Expired] {
+            checker.check(&reason);
+            checker.check(&TrashedPaste {
+                id: id(),
+                deleted_time: time(),
+                purge_time: time(),
+                reason,
+                name: None,
+                size: 12,
+            });
+        }
+        let report = Report {
+            time: time(),
+            reason: None,
+            ip: None,
+        };
+        checker.check(&report);
+        checker.check(&ReportedPaste {
+            id: id(),
+            name: None,
+            moderation: Some(moderation()),
+            reports: vec![report],
+        });
+    }
+
+    fn error_replies(checker: &mut Checker) {
+        let codes = [
+            ErrorCode::BadRequest,
+            ErrorCode::Unauthorized,
+            ErrorCode::Forbidden,
+            ErrorCode::NotFound,
+            ErrorCode::MethodNotAllowed,
+            ErrorCode::PayloadTooLarge,
+            ErrorCode::OnHold,
+            ErrorCode::Quarantined,
+            ErrorCode::Hidden,
+            ErrorCode::Rejected,
+            ErrorCode::Conflict,
+            ErrorCode::Internal,
+        ];
+        for code in &codes {
+            checker.check(code);
+        }
+        checker.check(&ErrorResponse {
+            success: false,
+            code: ErrorCode::NotFound,
+            message: "Paste Not Found".to_string(),
+        });
+    }
+
+    /// Every schema reference points at a component
+    #[test]
+    fn references() {
+        fn walk(value: &Value, spec: &Value) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::String(target)) = map.get("$ref") {
+                        let path = target.trim_start_matches('#');
+                        assert!(spec.pointer(path).is_some(), "{} not found", target);
+                    }
+                    map.values().for_each(|v| walk(v, spec));
+                }
+                Value::Array(values) => values.iter().for_each(|v| walk(v, spec)),
+                _ => (),
+            }
+        }
+        let spec = spec("http://localhost");
+        walk(&spec, &spec);
+    }
+}
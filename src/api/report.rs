@@ -0,0 +1,62 @@
+use crate::api::{ApiError, Reply, Response};
+use crate::audit::{remote_ip, Actor, Operation};
+use crate::moderation::Report;
+use crate::storage::{Moderation, ModerationState};
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use log::{info, warn};
+use serde::Deserialize;
+
+const MAX_REASON_LEN: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ReportForm {
+    reason: Option<String>,
+}
+
+/// Report a paste to moderators, quarantining it once enough people did
+pub async fn post(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    form: Option<web::Form<ReportForm>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if !data.storage.inner.exists(&id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    let reason = form
+        .and_then(|f| f.into_inner().reason)
+        .filter(|r| !r.trim().is_empty())
+        .map(|r| r.chars().take(MAX_REASON_LEN).collect());
+    let report = Report {
+        time: Utc::now(),
+        reason,
+        ip: remote_ip(&req),
+    };
+    let count = data.reports.add(&id, report).await?;
+    info!("Paste {} reported, by {} addresses so far.", id, count);
+
+    if data.config.report_threshold.is_some_and(|t| count >= t) {
+        let mut meta = data.storage.inner.get_meta(&id).await?;
+        if meta.moderation.is_none() {
+            warn!("Paste {} quarantined after {} reports.", id, count);
+            meta.moderation = Some(Moderation {
+                state: ModerationState::Quarantined,
+                by: None,
+                time: Utc::now(),
+            });
+            data.storage.inner.set_meta(&id, &meta).await?;
+            data.audit.record(Operation::Quarantine, &id, Actor::System);
+        }
+    }
+
+    let res: Response<()> = Response {
+        success: true,
+        message: "Thanks, moderators will take a look.".to_string(),
+        info: None,
+    };
+    Ok(res.reply(&req))
+}
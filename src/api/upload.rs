@@ -0,0 +1,255 @@
+//! Resumable uploads: create an upload, send the content in chunks at the offset the server has
+//! reached, and finish it into a paste. A broken connection only loses the chunk it was sending.
+use crate::api::new::{self, write_content};
+use crate::api::{text_reply, wants_text, ApiError, PasteOptions, Reply, Response};
+use crate::upload::UploadRecord;
+use crate::PasteState;
+
+use actix_web::http::ConnectionType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bytes::BytesMut;
+use futures::{StreamExt, TryStreamExt};
+use log::info;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+pub use rspb_client::types::UploadInfo;
+
+/// Number in a header, if it's there
+fn header_number(req: &HttpRequest, name: &str) -> Result<Option<u64>, ApiError> {
+    match req.headers().get(name) {
+        Some(value) => Ok(Some(value.to_str()?.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Largest upload allowed, in bytes
+fn max_length(data: &PasteState) -> u64 {
+    data.config.upload_max_mib.unwrap_or(1024) * 1024 * 1024
+}
+
+async fn find(data: &PasteState, id: &str) -> Result<UploadRecord, ApiError> {
+    data.uploads
+        .record(id)
+        .await?
+        .ok_or(ApiError::UploadNotFound)
+}
+
+/// State of the upload, plain text clients get its URL and offset on separate lines
+async fn reply(
+    data: &PasteState,
+    req: &HttpRequest,
+    id: &str,
+    record: &UploadRecord,
+) -> Result<HttpResponse, ApiError> {
+    let offset = data.uploads.offset(id).await?;
+    let mut res = if wants_text(req.path(), req.headers()) {
+        let url = format!(
+            "{}/uploads/{}",
+            data.config.site.url.trim_end_matches('/'),
+            id
+        );
+        text_reply(format!("{}\n{}\n", url, offset))
+    } else {
+        let res: Response<UploadInfo> = Response {
+            success: true,
+            message: String::new(),
+            info: Some(UploadInfo {
+                id: id.to_string(),
+                offset,
+                length: record.length,
+                expire_time: data.uploads.expire_time(record),
+            }),
+        };
+        HttpResponse::Ok().json(&res)
+    };
+    res.headers_mut()
+        .insert("Upload-Offset".parse().unwrap(), offset.into());
+    Ok(res)
+}
+
+/// Start an upload. Settings are sent like for raw uploads, the size as Upload-Length if known.
+pub async fn post(data: web::Data<PasteState>, req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let options = PasteOptions::from_request(&req)?;
+    // Better to know now than after uploading gigabytes
+    options.expire_time()?;
+    options.expire_after_inactive()?;
+    let length = header_number(&req, "Upload-Length")?;
+    if length == Some(0) {
+        return Err(ApiError::BadRequest(
+            "Cannot create paste with no content.".to_string(),
+        ));
+    }
+    let max = max_length(&data);
+    if length.is_some_and(|length| length > max) {
+        return Err(ApiError::TooLarge(format!(
+            "Upload can't be larger than {} bytes.",
+            max
+        )));
+    }
+
+    let id = data.uploads.create(length, options).await?;
+    info!("NEW upload {} of {:?} bytes.", id, length);
+    let record = find(&data, &id).await?;
+    reply(&data, &req, &id, &record).await
+}
+
+/// How far an upload got
+pub async fn get(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let record = find(&data, &id).await?;
+    reply(&data, &req, &id, &record).await
+}
+
+/// Add a chunk at Upload-Offset, which has to be where the upload is at
+pub async fn patch(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    payload: web::Payload,
+    req: HttpRequest,
+) -> HttpResponse {
+    match append(&data, &id, payload, &req).await {
+        Ok(res) => res,
+        Err(err) => {
+            // The rest of the chunk may still be on its way, don't read it as the next request
+            let mut res = HttpResponse::from_error(err.into());
+            res.head_mut().set_connection_type(ConnectionType::Close);
+            res
+        }
+    }
+}
+
+async fn append(
+    data: &PasteState,
+    id: &str,
+    mut payload: web::Payload,
+    req: &HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let mut record = find(data, id).await?;
+    let _lock = data.uploads.lock(id).ok_or_else(|| {
+        ApiError::Conflict("Another chunk is being written to this upload.".to_string())
+    })?;
+    let offset = match header_number(req, "Upload-Offset")? {
+        Some(offset) => offset,
+        None => {
+            return Err(ApiError::BadRequest(
+                "Please provide Upload-Offset.".to_string(),
+            ))
+        }
+    };
+    let current = data.uploads.offset(id).await?;
+    if offset != current {
+        return Err(ApiError::Conflict(format!(
+            "Upload is at offset {}.",
+            current
+        )));
+    }
+
+    let max = max_length(data);
+    let mut file = data.uploads.append(id).await?;
+    let mut size = current;
+    let mut res = Ok(());
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_err) => {
+                res = Err(ApiError::Unknown(
+                    "Connection error: upload interrupted.".to_string(),
+                ));
+                break;
+            }
+        };
+        size += chunk.len() as u64;
+        if record.length.is_some_and(|length| size > length) {
+            res = Err(ApiError::BadRequest(
+                "Chunk goes past the upload length.".to_string(),
+            ));
+            break;
+        }
+        if size > max {
+            res = Err(ApiError::TooLarge(format!(
+                "Upload can't be larger than {} bytes.",
+                max
+            )));
+            break;
+        }
+        if file.write_all(&chunk).await.is_err() {
+            res = Err(ApiError::Unknown("Failed to store chunk.".to_string()));
+            break;
+        }
+    }
+
+    // Whatever arrived is kept, the client resumes from there
+    file.flush().await.map_err(anyhow::Error::from)?;
+    data.uploads.touch(id, &mut record).await?;
+    res?;
+    reply(data, req, id, &record).await
+}
+
+/// Turn a complete upload into a paste, answered like creating a paste in one request
+pub async fn finish(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let record = find(&data, &id).await?;
+    let _lock = data.uploads.lock(&id).ok_or_else(|| {
+        ApiError::Conflict("A chunk is still being written to this upload.".to_string())
+    })?;
+    let offset = data.uploads.offset(&id).await?;
+    if let Some(length) = record.length.filter(|length| *length != offset) {
+        return Err(ApiError::BadRequest(format!(
+            "Upload incomplete, {} of {} bytes received.",
+            offset, length
+        )));
+    }
+
+    let (paste_id, key) = new::new_id(&data).await?;
+    let mut redactions = if record.options.redact { Some(0) } else { None };
+    let mut file = data
+        .scanner
+        .wrap(data.storage.inner.new(&paste_id, &key).await?);
+    let content = fs::File::open(data.uploads.content_path(&id))
+        .await
+        .map_err(anyhow::Error::from)?;
+    let mut content = FramedRead::new(content, BytesCodec::new()).map_ok(BytesMut::freeze);
+    let res = write_content(&mut content, &mut file, &mut redactions)
+        .await
+        .map(|_size| record.options);
+    let findings = file.finish();
+
+    let created = new::create(&data, &req, paste_id, key, findings, res, redactions).await;
+    match &created {
+        // Content that must not be kept is gone from the upload too
+        Ok(_) | Err(ApiError::Rejected(_)) => {
+            data.uploads.remove(&id).await?;
+        }
+        Err(_err) => (),
+    }
+    created
+}
+
+/// Give up on an upload
+pub async fn delete(
+    data: web::Data<PasteState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    find(&data, &id).await?;
+    let _lock = data.uploads.lock(&id).ok_or_else(|| {
+        ApiError::Conflict("A chunk is still being written to this upload.".to_string())
+    })?;
+    data.uploads.remove(&id).await?;
+    info!("Upload {} dropped.", id);
+
+    let res: Response<()> = Response {
+        success: true,
+        message: "Upload dropped.".to_string(),
+        info: None,
+    };
+    Ok(res.reply(&req))
+}
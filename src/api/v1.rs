@@ -0,0 +1,25 @@
+//! API v1, the paste and admin API under `/api/v1`. Same handlers as the routes at the root,
+//! but every reply is JSON, errors included, and errors carry an [`ErrorCode`].
+//!
+//! [`ErrorCode`]: crate::api::ErrorCode
+use crate::api::{get as paste, ApiError};
+use crate::PasteState;
+
+use actix_web::{web, HttpResponse};
+
+pub const PREFIX: &str = "/api/v1";
+
+pub async fn get(
+    data: web::Data<PasteState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = paste::paste_id(&path)?;
+    paste::serve(&data, &id, false).await
+}
+
+pub async fn head(
+    data: web::Data<PasteState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    paste::describe(&data, &path).await
+}
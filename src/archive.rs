@@ -0,0 +1,436 @@
+//! Portable export and import of all pastes, independent of the storage backend.
+//!
+//! An archive is a tar file holding, for every paste, `meta/{id}.json` followed by `content/{id}`.
+use crate::api::new::{gen_random_chars, ID_LEN};
+use crate::audit::{Actor, AuditLog, Operation};
+use crate::storage::{
+    LegalHold, Moderation, ModerationState, PasteMeta, Response, ScanAction, ScanFinding,
+    ScanResult, Storage,
+};
+
+use anyhow::{format_err, Result};
+use chrono::prelude::*;
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// Format of the metadata `export` writes, archives from before it was versioned are 1
+const ARCHIVE_VERSION: u32 = 2;
+
+/// Metadata of an archived paste. Kept apart from `PasteMeta`, so the storage format can change
+/// without breaking archives.
+#[derive(Serialize, Deserialize)]
+struct ArchivedMeta {
+    #[serde(default = "first_version")]
+    version: u32,
+    id: String,
+    key: String,
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    // Missing in archives from older versions
+    #[serde(default)]
+    expire_after_inactive: Option<u32>,
+    #[serde(default)]
+    hold: Option<ArchivedHold>,
+    #[serde(default)]
+    moderation: Option<ArchivedModeration>,
+    #[serde(default)]
+    scan: Option<ArchivedScan>,
+    #[serde(default)]
+    views: u64,
+    #[serde(default = "first_revision")]
+    revision: u32,
+    #[serde(default)]
+    modify_time: Option<DateTime<Utc>>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+fn first_revision() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedHold {
+    by: String,
+    time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ArchivedModerationState {
+    Quarantined,
+    Hidden,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedModeration {
+    state: ArchivedModerationState,
+    by: Option<String>,
+    time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ArchivedScanAction {
+    Flag,
+    Quarantine,
+    Reject,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedFinding {
+    rule: String,
+    action: ArchivedScanAction,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedScan {
+    time: DateTime<Utc>,
+    findings: Vec<ArchivedFinding>,
+}
+
+impl From<LegalHold> for ArchivedHold {
+    fn from(hold: LegalHold) -> Self {
+        ArchivedHold {
+            by: hold.by,
+            time: hold.time,
+        }
+    }
+}
+
+impl From<ArchivedHold> for LegalHold {
+    fn from(hold: ArchivedHold) -> Self {
+        LegalHold {
+            by: hold.by,
+            time: hold.time,
+        }
+    }
+}
+
+impl From<Moderation> for ArchivedModeration {
+    fn from(moderation: Moderation) -> Self {
+        let state = match moderation.state {
+            ModerationState::Quarantined => ArchivedModerationState::Quarantined,
+            ModerationState::Hidden => ArchivedModerationState::Hidden,
+        };
+        ArchivedModeration {
+            state,
+            by: moderation.by,
+            time: moderation.time,
+        }
+    }
+}
+
+impl From<ArchivedModeration> for Moderation {
+    fn from(moderation: ArchivedModeration) -> Self {
+        let state = match moderation.state {
+            ArchivedModerationState::Quarantined => ModerationState::Quarantined,
+            ArchivedModerationState::Hidden => ModerationState::Hidden,
+        };
+        Moderation {
+            state,
+            by: moderation.by,
+            time: moderation.time,
+        }
+    }
+}
+
+impl From<ScanResult> for ArchivedScan {
+    fn from(scan: ScanResult) -> Self {
+        let findings = scan
+            .findings
+            .into_iter()
+            .map(|finding| ArchivedFinding {
+                rule: finding.rule,
+                action: match finding.action {
+                    ScanAction::Flag => ArchivedScanAction::Flag,
+                    ScanAction::Quarantine => ArchivedScanAction::Quarantine,
+                    ScanAction::Reject => ArchivedScanAction::Reject,
+                },
+            })
+            .collect();
+        ArchivedScan {
+            time: scan.time,
+            findings,
+        }
+    }
+}
+
+impl From<ArchivedScan> for ScanResult {
+    fn from(scan: ArchivedScan) -> Self {
+        let findings = scan
+            .findings
+            .into_iter()
+            .map(|finding| ScanFinding {
+                rule: finding.rule,
+                action: match finding.action {
+                    ArchivedScanAction::Flag => ScanAction::Flag,
+                    ArchivedScanAction::Quarantine => ScanAction::Quarantine,
+                    ArchivedScanAction::Reject => ScanAction::Reject,
+                },
+            })
+            .collect();
+        ScanResult {
+            time: scan.time,
+            findings,
+        }
+    }
+}
+
+/// What to do when an imported paste id is already taken
+#[derive(Clone, Copy)]
+pub enum OnConflict {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+impl std::str::FromStr for OnConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "rename" => Ok(Self::Rename),
+            _ => Err(format_err!("Unknown conflict policy {}", s)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExportSummary {
+    pub exported: usize,
+    /// Pastes that couldn't be read, left out of the archive
+    pub failed: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+    pub renamed: Vec<(String, String)>,
+}
+
+fn append<R: Read>(archive: &mut tar::Builder<File>, path: &str, size: u64, data: R) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o600);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Content of a paste, read in full before anything of it goes into the archive
+enum Spooled {
+    Memory(Vec<u8>),
+    /// In the spool file, of this size
+    File(u64),
+}
+
+async fn spool(storage: &dyn Storage, id: &str, spool_path: &Path) -> Result<Spooled> {
+    match storage.get(id).await? {
+        Response::Content(vec) => Ok(Spooled::Memory(vec)),
+        Response::Stream(mut stream) => {
+            // Tar needs the exact size up front, so spool large pastes to disk first
+            let mut spool = File::create(spool_path)?;
+            while let Some(chunk) = stream.next().await {
+                spool.write_all(&chunk?)?;
+            }
+            Ok(Spooled::File(spool.metadata()?.len()))
+        }
+    }
+}
+
+/// Metadata and content of a paste, ready to be archived
+async fn read_paste(
+    storage: &dyn Storage,
+    id: &str,
+    meta: PasteMeta,
+    spool_path: &Path,
+) -> Result<(Vec<u8>, Spooled)> {
+    let archived = ArchivedMeta {
+        version: ARCHIVE_VERSION,
+        id: id.to_string(),
+        key: meta.key().to_string(),
+        create_time: meta.create_time,
+        expire_time: meta.expire_time,
+        atime: meta.atime,
+        name: meta.name,
+        size: meta.size,
+        expire_after_inactive: meta.expire_after_inactive,
+        hold: meta.hold.map(Into::into),
+        moderation: meta.moderation.map(Into::into),
+        scan: meta.scan.map(Into::into),
+        views: storage.views(id).await?,
+        revision: meta.revision,
+        modify_time: meta.modify_time,
+    };
+    let json = serde_json::to_vec_pretty(&archived)?;
+    Ok((json, spool(storage, id, spool_path).await?))
+}
+
+async fn write_archive(
+    storage: &dyn Storage,
+    path: &Path,
+    spool_path: &Path,
+) -> Result<ExportSummary> {
+    let mut archive = tar::Builder::new(File::create(path)?);
+    let mut summary = ExportSummary::default();
+
+    for (id, meta) in storage.get_all_meta().await? {
+        let (json, content) = match read_paste(storage, &id, meta, spool_path).await {
+            Ok(paste) => paste,
+            Err(err) => {
+                warn!("Failed to read paste {}, not exported: {}", id, err);
+                summary.failed.push(id);
+                continue;
+            }
+        };
+
+        let meta_path = format!("meta/{}.json", id);
+        append(&mut archive, &meta_path, json.len() as u64, &json[..])?;
+        let content_path = format!("content/{}", id);
+        match content {
+            Spooled::Memory(vec) => {
+                append(&mut archive, &content_path, vec.len() as u64, &vec[..])?
+            }
+            Spooled::File(size) => {
+                append(&mut archive, &content_path, size, File::open(spool_path)?)?
+            }
+        }
+        summary.exported += 1;
+    }
+
+    archive.into_inner()?.sync_all()?;
+    Ok(summary)
+}
+
+/// Write every paste in `storage` into a tar archive at `path`. Pastes that can't be read are
+/// left out and reported.
+pub async fn export(storage: &dyn Storage, path: &Path) -> Result<ExportSummary> {
+    let spool_path = path.with_extension("spool");
+    let res = write_archive(storage, path, &spool_path).await;
+    if spool_path.exists() {
+        if let Err(err) = std::fs::remove_file(&spool_path) {
+            warn!("Failed to remove {:?}: {}", spool_path, err);
+        }
+    }
+
+    let summary = res?;
+    info!(
+        "Exported {} pastes to {:?}, {} failed.",
+        summary.exported,
+        path,
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+/// Load every paste in the tar archive at `path` into `storage`, keeping their ids when possible.
+/// Pastes under legal hold or moderation are never overwritten, others are recorded in `audit`.
+pub async fn import(
+    storage: &dyn Storage,
+    path: &Path,
+    on_conflict: OnConflict,
+    audit: &AuditLog,
+) -> Result<ImportSummary> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut summary = ImportSummary::default();
+    let mut pending: Option<ArchivedMeta> = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_string_lossy().to_string();
+
+        if entry_path.starts_with("meta/") {
+            let mut json = Vec::new();
+            entry.read_to_end(&mut json)?;
+            let archived: ArchivedMeta = serde_json::from_slice(&json)?;
+            if archived.version > ARCHIVE_VERSION {
+                return Err(format_err!(
+                    "Archive version {} is newer than this rspb supports",
+                    archived.version
+                ));
+            }
+            pending = Some(archived);
+            continue;
+        }
+
+        let archived = match pending.take() {
+            Some(m) if entry_path == format!("content/{}", m.id) => m,
+            _ => return Err(format_err!("Unexpected entry {} in archive", entry_path)),
+        };
+
+        let mut id = archived.id.clone();
+        if storage.exists(&id).await? {
+            match on_conflict {
+                OnConflict::Skip => {
+                    warn!("Paste {} already exists, skipped.", id);
+                    summary.skipped += 1;
+                    continue;
+                }
+                OnConflict::Overwrite => {
+                    let meta = storage.get_meta(&id).await?;
+                    if meta.hold.is_some() || meta.moderation.is_some() {
+                        warn!("Paste {} is held or moderated, not overwritten.", id);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    storage.delete(&id).await?;
+                    audit.record(Operation::Delete, &id, Actor::System);
+                }
+                OnConflict::Rename => {
+                    while storage.exists(&id).await? {
+                        id = gen_random_chars(ID_LEN);
+                    }
+                    summary.renamed.push((archived.id.clone(), id.clone()));
+                }
+            }
+        }
+
+        let mut file = storage.new(&id, &archived.key).await?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let len = entry.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            file.write_all(&buf[..len]).await?;
+        }
+        file.flush().await?;
+        storage.update_size(&id).await?;
+
+        let mut meta = storage.get_meta(&id).await?;
+        meta.create_time = archived.create_time;
+        meta.expire_time = archived.expire_time;
+        meta.atime = archived.atime;
+        meta.name = archived.name;
+        meta.expire_after_inactive = archived.expire_after_inactive;
+        meta.hold = archived.hold.map(Into::into);
+        meta.moderation = archived.moderation.map(Into::into);
+        meta.scan = archived.scan.map(Into::into);
+        meta.revision = archived.revision;
+        meta.modify_time = archived.modify_time;
+        storage.set_meta(&id, &meta).await?;
+        storage.add_views(&id, archived.views).await?;
+        summary.imported += 1;
+    }
+
+    info!(
+        "Imported {} pastes from {:?}, {} skipped.",
+        summary.imported, path, summary.skipped
+    );
+    Ok(summary)
+}
@@ -0,0 +1,193 @@
+//! Append-only audit log of everything that changes a paste, one JSON record per line.
+use crate::PasteState;
+
+use actix_web::{web, HttpRequest};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use anyhow::Result;
+use chrono::prelude::*;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Create,
+    Modify,
+    Delete,
+    /// Removed by cleanup
+    Expire,
+    Hold,
+    Release,
+    Restore,
+    Purge,
+    Quarantine,
+    Hide,
+    /// Moderation lifted
+    Reinstate,
+}
+
+/// Who did it
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Actor {
+    /// Creator of a new paste, there's no key yet
+    Uploader {
+        ip: Option<String>,
+    },
+    KeyHolder {
+        ip: Option<String>,
+    },
+    Admin {
+        name: String,
+        ip: Option<String>,
+    },
+    /// Background tasks like cleanup
+    System,
+}
+
+/// Client address without the port. Forwarding headers can be sent by anyone, so they only count
+/// on connections from one of the `trusted_proxies`.
+pub fn remote_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let trusted = req
+        .app_data::<web::Data<PasteState>>()
+        .is_some_and(|data| data.config.trusted_proxies.contains(&peer));
+    if !trusted {
+        return Some(peer.to_string());
+    }
+    req.connection_info().realip_remote_addr().map(|addr| {
+        match addr.parse::<std::net::SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => addr.to_string(),
+        }
+    })
+}
+
+impl Actor {
+    pub fn uploader(req: &HttpRequest) -> Self {
+        Self::Uploader { ip: remote_ip(req) }
+    }
+
+    pub fn key_holder(req: &HttpRequest) -> Self {
+        Self::KeyHolder { ip: remote_ip(req) }
+    }
+
+    pub fn admin(auth: &BasicAuth, req: &HttpRequest) -> Self {
+        Self::Admin {
+            name: auth.user_id().to_string(),
+            ip: remote_ip(req),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub operation: Operation,
+    pub id: String,
+    pub actor: Actor,
+}
+
+/// Filters for reading the audit log, all of them optional
+#[derive(Deserialize, Default)]
+pub struct AuditQuery {
+    pub id: Option<String>,
+    pub operation: Option<Operation>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.id.as_ref().is_none_or(|id| &entry.id == id)
+            && self.operation.is_none_or(|op| entry.operation == op)
+            && self.since.is_none_or(|t| entry.time >= t)
+            && self.until.is_none_or(|t| entry.time < t)
+    }
+}
+
+#[derive(Clone)]
+struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+/// Does nothing unless opened with a path
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    log: Option<LogFile>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            log: Some(LogFile {
+                path: PathBuf::from(path),
+                file: Arc::new(Mutex::new(file)),
+            }),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.log.is_some()
+    }
+
+    /// Append an entry. Failures are logged, but never fail the operation itself.
+    pub fn record(&self, operation: Operation, id: &str, actor: Actor) {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return,
+        };
+        let entry = AuditEntry {
+            time: Utc::now(),
+            operation,
+            id: id.to_string(),
+            actor,
+        };
+
+        let res = serde_json::to_vec(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                // One write per line, so lines never interleave
+                let mut file = log.file.lock().unwrap();
+                file.write_all(&line)?;
+                Ok(file.flush()?)
+            });
+        if let Err(err) = res {
+            error!("Failed to write audit log: {}", err);
+        }
+    }
+
+    /// Entries matching `query`, newest first
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&log.path)?).lines() {
+            let line = line?;
+            match serde_json::from_str::<AuditEntry>(&line) {
+                Ok(entry) if query.matches(&entry) => entries.push(entry),
+                Ok(_entry) => (),
+                Err(err) => error!("Skipping bad audit log line: {}", err),
+            }
+        }
+
+        Ok(entries
+            .into_iter()
+            .rev()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+}
@@ -0,0 +1,553 @@
+//! Command-line client for rspb.
+//!
+//! Keys of uploaded pastes are kept in a local config file, so pastes can be modified and
+//! deleted later without handling keys by hand.
+use rspb_client::types::ModerationState;
+use rspb_client::{Client, ListQuery, PasteOptions};
+
+use anyhow::{format_err, Context, Result};
+use chrono::prelude::*;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Default)]
+struct CliConfig {
+    /// Used unless --server is given
+    server: Option<String>,
+    admin_user: Option<String>,
+    /// RSPB_ADMIN_PASSWORD takes precedence
+    admin_password: Option<String>,
+    /// Pastes uploaded from here, by id
+    #[serde(default)]
+    pastes: BTreeMap<String, SavedPaste>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPaste {
+    server: String,
+    key: String,
+    name: Option<String>,
+    expire_time: Option<DateTime<Utc>>,
+}
+
+impl CliConfig {
+    fn load(path: &Path) -> Result<CliConfig> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .with_context(|| format!("Bad config file {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(CliConfig::default()),
+            Err(err) => Err(err).with_context(|| format!("Can't read {}", path.display())),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Replace atomically, and readable only by the owner, it holds the keys
+        let tmp = path.with_extension("tmp");
+        // A leftover would keep its permissions
+        if tmp.exists() {
+            std::fs::remove_file(&tmp)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config"),
+    };
+    base.join("rspb").join("cli.toml")
+}
+
+/// Paste settings given on the command line
+fn paste_options(m: &ArgMatches) -> Result<PasteOptions> {
+    Ok(PasteOptions {
+        name: m.value_of("name").map(str::to_string),
+        expire_after: m
+            .value_of("expire-after")
+            .map(str::parse)
+            .transpose()
+            .context("Bad expire time")?,
+        expire_after_inactive: m
+            .value_of("expire-after-inactive")
+            .map(str::parse)
+            .transpose()
+            .context("Bad inactivity expire time")?,
+        redact: m.is_present("redact"),
+    })
+}
+
+/// Content of a file, or of stdin for `-`
+fn read_content(file: &str) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    if file == "-" {
+        std::io::stdin().read_to_end(&mut content)?;
+    } else {
+        std::fs::File::open(file)
+            .with_context(|| format!("Can't open {}", file))?
+            .read_to_end(&mut content)?;
+    }
+    Ok(content)
+}
+
+struct Cli {
+    client: Client,
+    server: String,
+    config: CliConfig,
+    config_path: PathBuf,
+}
+
+impl Cli {
+    fn saved(&self, id: &str) -> Result<(Client, &SavedPaste)> {
+        let saved = self
+            .config
+            .pastes
+            .get(id)
+            .ok_or_else(|| format_err!("No key saved for paste {}", id))?;
+        Ok((Client::new(&saved.server), saved))
+    }
+
+    fn admin(&self) -> Result<Client> {
+        let user = self
+            .config
+            .admin_user
+            .as_deref()
+            .ok_or_else(|| format_err!("Set admin_user in {}", self.config_path.display()))?;
+        let password = std::env::var("RSPB_ADMIN_PASSWORD")
+            .ok()
+            .or_else(|| self.config.admin_password.clone())
+            .unwrap_or_default();
+        Ok(self.client.clone().admin(user, &password))
+    }
+
+    async fn upload(&mut self, m: &ArgMatches<'_>) -> Result<()> {
+        let content = read_content(m.value_of("file").unwrap_or("-"))?;
+        let options = paste_options(m)?;
+        let info = self.client.create(content, &options).await?;
+
+        println!("{}", self.client.url(&info.id));
+        if let Some(n) = info.redactions.filter(|n| *n > 0) {
+            eprintln!("{} secrets redacted.", n);
+        }
+        self.config.pastes.insert(
+            info.id,
+            SavedPaste {
+                server: self.server.clone(),
+                key: info.key,
+                name: options.name,
+                expire_time: info.expire_time,
+            },
+        );
+        self.config.save(&self.config_path)
+    }
+
+    async fn modify(&mut self, m: &ArgMatches<'_>) -> Result<()> {
+        let id = m.value_of("id").unwrap();
+        let options = paste_options(m)?;
+        // Without a file, only the settings change
+        let content = m.value_of("file").map(read_content).transpose()?;
+        let (client, saved) = self.saved(id)?;
+        client
+            .modify(id, &saved.key, content.map(Into::into), &options)
+            .await?;
+
+        if let Some(paste) = self.config.pastes.get_mut(id) {
+            if let Some(name) = options.name {
+                paste.name = Some(name);
+            }
+            if let Some(minutes) = options.expire_after {
+                paste.expire_time = Some(Utc::now() + chrono::Duration::minutes(minutes));
+            }
+        }
+        self.config.save(&self.config_path)
+    }
+
+    async fn delete(&mut self, id: &str) -> Result<()> {
+        let (client, saved) = self.saved(id)?;
+        client.delete(id, &saved.key).await?;
+
+        self.config.pastes.remove(id);
+        self.config.save(&self.config_path)
+    }
+
+    async fn admin_command(&self, m: &ArgMatches<'_>) -> Result<()> {
+        let client = self.admin()?;
+        let (sub, m) = m.subcommand();
+        let id = m.and_then(|m| m.value_of("id")).unwrap_or_default();
+        match sub {
+            "list" => {
+                let query = ListQuery {
+                    search: m.and_then(|m| m.value_of("search")).map(str::to_string),
+                    ..Default::default()
+                };
+                print_json(&client.admin_list(&query).await?)?;
+            }
+            "reports" => print_json(&client.reports().await?)?,
+            "get" => print_content(&client.admin_get(id).await?)?,
+            "delete" => {
+                client.admin_delete(id).await?;
+                // Can't be modified with its key anymore either
+                let mut config = CliConfig::load(&self.config_path)?;
+                if config.pastes.remove(id).is_some() {
+                    config.save(&self.config_path)?;
+                }
+            }
+            "hold" => print_json(&client.hold(id).await?)?,
+            "release" => client.release(id).await?,
+            "moderate" => {
+                let state = match m.and_then(|m| m.value_of("state")) {
+                    Some("hidden") => ModerationState::Hidden,
+                    _ => ModerationState::Quarantined,
+                };
+                print_json(&client.moderate(id, state).await?)?;
+            }
+            "reinstate" => client.reinstate(id).await?,
+            "restore" => client.restore(id).await?,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+fn print_json(info: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(info)?);
+    Ok(())
+}
+
+fn print_content(content: &[u8]) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(content)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Pastes uploaded from here, no server needed
+fn list(config: &CliConfig) {
+    let now = Utc::now();
+    for (id, paste) in &config.pastes {
+        let expiry = match paste.expire_time {
+            Some(t) if t < now => "expired".to_string(),
+            Some(t) => format!("expires {}", t.to_rfc3339()),
+            None => "no expiry".to_string(),
+        };
+        println!(
+            "{}/{}\t{}\t{}",
+            paste.server,
+            id,
+            paste.name.as_deref().unwrap_or("-"),
+            expiry
+        );
+    }
+}
+
+fn paste_args<'a, 'b>(cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.arg(
+        Arg::with_name("name")
+            .short("n")
+            .long("name")
+            .value_name("NAME")
+            .help("Name of the paste"),
+    )
+    .arg(
+        Arg::with_name("expire-after")
+            .short("e")
+            .long("expire-after")
+            .value_name("MINUTES")
+            .help("Expire the paste after this many minutes"),
+    )
+}
+
+fn id_arg() -> Arg<'static, 'static> {
+    Arg::with_name("id")
+        .value_name("ID")
+        .help("Paste id")
+        .required(true)
+}
+
+/// The command line
+fn app() -> App<'static, 'static> {
+    App::new("rspb-cli")
+        .version("0.1")
+        .about("Command-line client for rspb")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("server")
+                .short("s")
+                .long("server")
+                .value_name("URL")
+                .help("rspb server, defaults to server in the config file"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Config file with saved keys, defaults to ~/.config/rspb/cli.toml"),
+        )
+        .subcommand(
+            paste_args(SubCommand::with_name("upload"))
+                .about("Upload a file, or stdin")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("File to upload, - for stdin"),
+                )
+                .arg(
+                    Arg::with_name("expire-after-inactive")
+                        .long("expire-after-inactive")
+                        .value_name("DAYS")
+                        .help("Expire the paste after this many days without views"),
+                )
+                .arg(
+                    Arg::with_name("redact")
+                        .long("redact")
+                        .help("Replace secrets with placeholders before storing"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Print the content of a paste")
+                .arg(id_arg()),
+        )
+        .subcommand(SubCommand::with_name("list").about("List pastes uploaded from here"))
+        .subcommand(
+            paste_args(SubCommand::with_name("modify"))
+                .about("Change one of your pastes")
+                .arg(id_arg())
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("New content, - for stdin"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Delete one of your pastes")
+                .arg(id_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("admin")
+                .about("Admin operations, using admin_user and admin_password")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list").about("List all pastes").arg(
+                        Arg::with_name("search")
+                            .value_name("TEXT")
+                            .help("Only pastes whose id or name contains this"),
+                    ),
+                )
+                .subcommand(SubCommand::with_name("reports").about("Show the moderation queue"))
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print any paste, even if moderated")
+                        .arg(id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("delete")
+                        .about("Delete any paste")
+                        .arg(id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("hold")
+                        .about("Put a paste on legal hold")
+                        .arg(id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("release")
+                        .about("Release a legal hold")
+                        .arg(id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("moderate")
+                        .about("Take a paste offline")
+                        .arg(id_arg())
+                        .arg(
+                            Arg::with_name("state")
+                                .value_name("STATE")
+                                .possible_values(&["quarantined", "hidden"])
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("reinstate")
+                        .about("Put a moderated paste back online")
+                        .arg(id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Restore a paste from the trash")
+                        .arg(id_arg()),
+                ),
+        )
+}
+
+#[actix_web::main]
+async fn main() {
+    let matches = app().get_matches();
+    if let Err(err) = run(&matches).await {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let config_path = matches
+        .value_of("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_config_path);
+    let config = CliConfig::load(&config_path)?;
+    if matches.subcommand_matches("list").is_some() {
+        list(&config);
+        return Ok(());
+    }
+    let server = matches
+        .value_of("server")
+        .map(str::to_string)
+        .or_else(|| config.server.clone())
+        .ok_or_else(|| format_err!("No server given, use --server or set server in the config"))?;
+
+    let server = server.trim_end_matches('/').to_string();
+    let mut cli = Cli {
+        client: Client::new(&server),
+        server,
+        config,
+        config_path,
+    };
+    match matches.subcommand() {
+        ("upload", Some(m)) => cli.upload(m).await,
+        ("get", Some(m)) => print_content(&cli.client.get(m.value_of("id").unwrap()).await?),
+        ("modify", Some(m)) => cli.modify(m).await,
+        ("delete", Some(m)) => cli.delete(m.value_of("id").unwrap()).await,
+        ("admin", Some(m)) => cli.admin_command(m).await,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(key: &str) -> SavedPaste {
+        SavedPaste {
+            server: "https://example.com".to_string(),
+            key: key.to_string(),
+            name: Some("notes.txt".to_string()),
+            expire_time: None,
+        }
+    }
+
+    #[test]
+    fn config_load_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rspb").join("cli.toml");
+        let config = CliConfig::load(&path).unwrap();
+        assert!(config.server.is_none() && config.pastes.is_empty());
+
+        let mut config = CliConfig {
+            server: Some("https://example.com".to_string()),
+            ..Default::default()
+        };
+        config.pastes.insert("abcdef".to_string(), saved("key1"));
+        config.save(&path).unwrap();
+        // Saving again replaces the file
+        config.pastes.insert("ghijkl".to_string(), saved("key2"));
+        config.save(&path).unwrap();
+
+        let loaded = CliConfig::load(&path).unwrap();
+        assert_eq!(loaded.server.as_deref(), Some("https://example.com"));
+        assert_eq!(
+            loaded.pastes.keys().collect::<Vec<_>>(),
+            ["abcdef", "ghijkl"]
+        );
+        assert_eq!(loaded.pastes["ghijkl"].key, "key2");
+        assert_eq!(loaded.pastes["abcdef"].name.as_deref(), Some("notes.txt"));
+        assert!(!path.with_extension("tmp").exists());
+
+        std::fs::write(&path, "pastes = 1").unwrap();
+        assert!(CliConfig::load(&path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn config_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cli.toml");
+        // A leftover temporary file readable by everyone doesn't pass on its permissions
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, "").unwrap();
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        CliConfig::default().save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn parse_args() {
+        let matches = app()
+            .get_matches_from_safe(&[
+                "rspb-cli",
+                "-s",
+                "https://example.com/",
+                "upload",
+                "-n",
+                "notes.txt",
+                "-e",
+                "60",
+                "--expire-after-inactive",
+                "7",
+                "--redact",
+                "notes.txt",
+            ])
+            .unwrap();
+        assert_eq!(matches.value_of("server"), Some("https://example.com/"));
+        let m = matches.subcommand_matches("upload").unwrap();
+        assert_eq!(m.value_of("file"), Some("notes.txt"));
+        let options = paste_options(m).unwrap();
+        assert_eq!(options.name.as_deref(), Some("notes.txt"));
+        assert_eq!(options.expire_after, Some(60));
+        assert_eq!(options.expire_after_inactive, Some(7));
+        assert!(options.redact);
+
+        let matches = app()
+            .get_matches_from_safe(&["rspb-cli", "modify", "abcdef", "-e", "soon"])
+            .unwrap();
+        let m = matches.subcommand_matches("modify").unwrap();
+        assert_eq!(m.value_of("id"), Some("abcdef"));
+        assert!(m.value_of("file").is_none());
+        assert!(paste_options(m).is_err());
+
+        let matches = app()
+            .get_matches_from_safe(&["rspb-cli", "admin", "moderate", "abcdef", "hidden"])
+            .unwrap();
+        let (sub, m) = matches.subcommand_matches("admin").unwrap().subcommand();
+        assert_eq!(sub, "moderate");
+        assert_eq!(m.unwrap().value_of("state"), Some("hidden"));
+
+        for args in &[
+            &["rspb-cli"][..],
+            &["rspb-cli", "delete"],
+            &["rspb-cli", "admin"],
+            &["rspb-cli", "admin", "moderate", "abcdef", "gone"],
+        ] {
+            assert!(app().get_matches_from_safe(*args).is_err(), "{:?}", args);
+        }
+    }
+}
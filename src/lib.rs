@@ -0,0 +1,342 @@
+//! rspb, a really simple pastebin.
+//!
+//! The server binary and the command-line client share these modules.
+pub mod api;
+pub mod archive;
+pub mod audit;
+pub mod migrate;
+pub mod misc;
+pub mod moderation;
+pub mod page;
+pub mod redact;
+pub mod scan;
+pub mod storage;
+pub mod upload;
+pub mod webhook;
+
+use crate::audit::AuditLog;
+use crate::moderation::Reports;
+use crate::scan::{ContentScanner, ScanConfig};
+use crate::storage::trashstorage::Trash;
+use crate::storage::{StorageBox, StorageConfig};
+use crate::upload::Uploads;
+use crate::webhook::{WebhookConfig, Webhooks};
+
+use actix_web::dev::Service;
+use actix_web::{guard, web};
+use futures::TryFutureExt;
+use actix_web_httpauth::middleware::HttpAuthentication;
+use anyhow::Result;
+use async_std::path::PathBuf;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+// Static files
+include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    pub memory_cache_size: Option<u64>,
+    /// Longest time between two cleanups in seconds
+    pub cleanup_interval: Option<u64>,
+    /// Default days without access after which pastes expire
+    pub expire_after_inactive: Option<u32>,
+    /// Days deleted and expired pastes stay restorable, no trash if unset
+    pub trash_days: Option<u32>,
+    /// Hours without new content after which unfinished uploads are dropped, 24 if unset
+    pub upload_expire_hours: Option<u32>,
+    /// Largest resumable upload in MiB, 1024 if unset
+    pub upload_max_mib: Option<u64>,
+    /// Path of the audit log, nothing is recorded if unset
+    pub audit_log: Option<String>,
+    /// Reverse proxies trusted to tell the client address in X-Forwarded-For or Forwarded
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Notified of paste lifecycle events
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Reports after which a paste is quarantined until reviewed, never if unset
+    pub report_threshold: Option<usize>,
+    /// Rules uploads are checked against
+    #[serde(default)]
+    pub scan: ScanConfig,
+    pub bind_address: String,
+    pub admins: HashMap<String, String>,
+    pub site: SiteConfig,
+    pub migration: Option<MigrationConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SiteConfig {
+    pub name: String,
+    pub slogan: String,
+    pub description: String,
+    pub url: String,
+}
+
+/// Storage backend to migrate pastes to
+#[derive(Deserialize, Clone)]
+pub struct MigrationConfig {
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    pub journal: String,
+    #[serde(default)]
+    pub dual_write: bool,
+}
+
+#[derive(Clone)]
+pub struct PasteState {
+    pub storage: StorageBox,
+    // Tells the cleanup task that an expire time has changed
+    pub cleanup: Arc<Notify>,
+    pub trash: Option<Trash>,
+    pub audit: AuditLog,
+    pub webhooks: Webhooks,
+    pub reports: Reports,
+    pub scanner: ContentScanner,
+    pub uploads: Uploads,
+    pub config: Config,
+}
+
+impl PasteState {
+    /// State of a server on `storage`, with the trash, cache and everything else set up as
+    /// `config` says. Background tasks are left to the caller.
+    pub fn new(config: Config, mut storage: StorageBox) -> Result<PasteState> {
+        let base = PathBuf::from(&config.storage.base_dir);
+        let trash = config.trash_days.map(|days| Trash::new(&base, days));
+        if let Some(trash) = &trash {
+            storage = StorageBox::trashed(storage, trash.clone());
+        }
+        if let Some(size) = config.memory_cache_size {
+            storage = StorageBox::memory_cached(storage, size);
+        }
+        let audit = match &config.audit_log {
+            Some(path) => AuditLog::open(std::path::Path::new(path))?,
+            None => AuditLog::default(),
+        };
+
+        Ok(PasteState {
+            storage,
+            cleanup: Arc::new(Notify::new()),
+            trash,
+            audit,
+            webhooks: Webhooks::new(config.webhooks.clone(), &base),
+            reports: Reports::new(&base),
+            scanner: ContentScanner::new(&config.scan)?,
+            uploads: Uploads::new(&base, config.upload_expire_hours.unwrap_or(24)),
+            config,
+        })
+    }
+}
+
+/// Pages and API of the server
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("")
+            .wrap_fn(|req, srv| {
+                let text = api::wants_text(req.path(), req.headers());
+                srv.call(req).map_ok(move |res| {
+                    if text {
+                        api::plain_error(res)
+                    } else {
+                        res
+                    }
+                })
+            })
+            .configure(site),
+    );
+}
+
+/// Everything `routes` serves, with errors left as they are
+fn site(cfg: &mut web::ServiceConfig) {
+    let generated = generate();
+    cfg.service(web::resource("/f").route(web::route().guard(guard::Get()).to(page::form::render)))
+        .service(
+            web::scope("/api")
+                .service(
+                    web::resource("/openapi.json")
+                        .route(web::route().guard(guard::Get()).to(api::openapi::get)),
+                )
+                .service(
+                    web::resource("/docs")
+                        .route(web::route().guard(guard::Get()).to(page::docs::render)),
+                )
+                .service(
+                    web::scope("/v1")
+                        .wrap_fn(|req, srv| {
+                            srv.call(req)
+                                .map_ok(api::json_error)
+                                .map_err(api::json_middleware_error)
+                        })
+                        .configure(api_v1),
+                ),
+        )
+        .service(
+            web::scope("/admin")
+                .wrap(HttpAuthentication::basic(misc::auth::validator))
+                .configure(admin),
+        )
+        .service(web::scope("/uploads").configure(uploads))
+        .service(
+            web::resource("/")
+                .route(web::route().guard(guard::Get()).to(page::index::render))
+                .route(web::route().guard(guard::Post()).to(api::new::post)),
+        )
+        .service(
+            web::resource("/{paste_id}")
+                .route(web::route().guard(guard::Delete()).to(api::delete::delete))
+                .route(web::route().guard(guard::Get()).to(api::get::get))
+                .route(web::route().guard(guard::Head()).to(api::get::head))
+                .route(web::route().guard(guard::Put()).to(api::modify::put)),
+        )
+        .service(
+            web::resource("/{paste_id}/report")
+                .route(web::route().guard(guard::Post()).to(api::report::post)),
+        )
+        .service(
+            web::resource("/{paste_id}/meta")
+                .route(web::route().guard(guard::Get()).to(api::meta::get)),
+        )
+        .service(
+            web::resource("/{paste_id}/audio")
+                .route(web::route().guard(guard::Get()).to(page::audio::render)),
+        )
+        .service(
+            web::resource("/{paste_id}/{lang}")
+                .route(web::route().guard(guard::Get()).to(page::code::render)),
+        )
+        .service(actix_web_static_files::ResourceFiles::new(
+            "/static", generated,
+        ));
+}
+
+/// API v1, the same handlers as the API at the root
+fn api_v1(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/pastes").route(web::route().guard(guard::Post()).to(api::new::post)),
+    )
+    .service(
+        web::resource("/pastes/{paste_id}")
+            .route(web::route().guard(guard::Delete()).to(api::delete::delete))
+            .route(web::route().guard(guard::Get()).to(api::v1::get))
+            .route(web::route().guard(guard::Head()).to(api::v1::head))
+            .route(web::route().guard(guard::Put()).to(api::modify::put)),
+    )
+    .service(
+        web::resource("/pastes/{paste_id}/meta")
+            .route(web::route().guard(guard::Get()).to(api::meta::get)),
+    )
+    .service(
+        web::resource("/pastes/{paste_id}/report")
+            .route(web::route().guard(guard::Post()).to(api::report::post)),
+    )
+    .service(web::scope("/uploads").configure(uploads))
+    .service(
+        web::scope("/admin")
+            .wrap(HttpAuthentication::basic(misc::auth::validator))
+            .configure(admin),
+    );
+}
+
+/// Resumable uploads, before pastes so `uploads` is never taken for a paste id
+fn uploads(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("").route(web::route().guard(guard::Post()).to(api::upload::post)),
+    )
+    .service(
+        web::resource("/{upload_id}")
+            .route(web::route().guard(guard::Get()).to(api::upload::get))
+            .route(web::route().guard(guard::Patch()).to(api::upload::patch))
+            .route(web::route().guard(guard::Post()).to(api::upload::finish))
+            .route(web::route().guard(guard::Delete()).to(api::upload::delete)),
+    );
+}
+
+/// Admin API, behind a login
+fn admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/audit").route(web::route().guard(guard::Get()).to(api::admin::audit::get)),
+    )
+    .service(
+        web::resource("/cache").route(web::route().guard(guard::Get()).to(api::admin::cache::get)),
+    )
+    .service(
+        web::resource("/fsck")
+            .route(web::route().guard(guard::Get()).to(api::admin::fsck::get))
+            .route(web::route().guard(guard::Post()).to(api::admin::fsck::post)),
+    )
+    .service(
+        web::resource("/list").route(web::route().guard(guard::Get()).to(api::admin::list::get)),
+    )
+    .service(
+        web::resource("/reports").route(
+            web::route()
+                .guard(guard::Get())
+                .to(api::admin::moderation::get),
+        ),
+    )
+    .service(
+        web::resource("/reports/{paste_id}").route(
+            web::route()
+                .guard(guard::Delete())
+                .to(api::admin::moderation::dismiss),
+        ),
+    )
+    .service(
+        web::resource("/trash").route(web::route().guard(guard::Get()).to(api::admin::trash::get)),
+    )
+    .service(
+        web::resource("/trash/{paste_id}")
+            .route(
+                web::route()
+                    .guard(guard::Post())
+                    .to(api::admin::trash::restore),
+            )
+            .route(
+                web::route()
+                    .guard(guard::Delete())
+                    .to(api::admin::trash::delete),
+            ),
+    )
+    .service(
+        web::resource("/{paste_id}/hold")
+            .route(
+                web::route()
+                    .guard(guard::Post())
+                    .to(api::admin::paste::hold),
+            )
+            .route(
+                web::route()
+                    .guard(guard::Delete())
+                    .to(api::admin::paste::release),
+            ),
+    )
+    .service(
+        web::resource("/{paste_id}/moderation")
+            .route(
+                web::route()
+                    .guard(guard::Post())
+                    .to(api::admin::moderation::moderate),
+            )
+            .route(
+                web::route()
+                    .guard(guard::Delete())
+                    .to(api::admin::moderation::reinstate),
+            ),
+    )
+    .service(
+        web::resource("/{paste_id}")
+            .route(web::route().guard(guard::Get()).to(api::admin::paste::get))
+            .route(web::route().guard(guard::Put()).to(api::admin::paste::put))
+            .route(
+                web::route()
+                    .guard(guard::Delete())
+                    .to(api::admin::paste::delete),
+            ),
+    );
+}
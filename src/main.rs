@@ -1,42 +1,17 @@
-mod storage;
-use crate::storage::StorageBox;
-mod api;
-pub mod misc;
-mod page;
-
-use actix_web::{guard, middleware, rt, web, App, HttpServer};
-use actix_web_httpauth::middleware::HttpAuthentication;
+use rspb::audit::{Actor, AuditLog, Operation};
+use rspb::storage::StorageBox;
+use rspb::webhook::{Event, Webhooks};
+use rspb::{archive, migrate, Config, PasteState};
+
+use actix_web::{middleware, rt, App, HttpServer};
 use async_std::path::PathBuf;
-use clap::Arg;
-use log::warn;
-use serde::Deserialize;
-use std::collections::HashMap;
+use chrono::prelude::*;
+use clap::{Arg, SubCommand};
+use futures::future::{self, Either};
+use log::{error, warn};
+use std::sync::Arc;
 use std::time::Duration;
-
-// Static files
-include!(concat!(env!("OUT_DIR"), "/generated.rs"));
-
-#[derive(Deserialize, Clone)]
-struct Config {
-    base_dir: String,
-    redis_address: Option<String>,
-    bind_address: String,
-    admins: HashMap<String, String>,
-    site: SiteConfig,
-}
-
-#[derive(Deserialize, Clone)]
-struct SiteConfig {
-    name: String,
-    slogan: String,
-    description: String,
-    url: String,
-}
-
-pub struct PasteState {
-    storage: StorageBox,
-    config: Config,
-}
+use tokio::sync::Notify;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -55,85 +30,239 @@ async fn main() -> std::io::Result<()> {
                 .help("Set a config file")
                 .required(true),
         )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check consistency between paste contents and metadata")
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Fix inconsistencies instead of only reporting them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export all pastes into a portable archive")
+                .arg(
+                    Arg::with_name("archive")
+                        .value_name("FILE")
+                        .help("Archive to write")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import pastes from an archive created by export")
+                .arg(
+                    Arg::with_name("archive")
+                        .value_name("FILE")
+                        .help("Archive to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .value_name("POLICY")
+                        .possible_values(&["skip", "overwrite", "rename"])
+                        .default_value("skip")
+                        .help("What to do when a paste id is already taken"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Copy all pastes to the storage backend in the migration section"),
+        )
         .get_matches();
 
     let config_path = PathBuf::from(matches.value_of("config").unwrap());
     let config: Config = toml::from_str(&std::fs::read_to_string(&config_path)?)?;
 
-    let base_dir = PathBuf::from(&config.base_dir);
-    let storage = StorageBox::new(&base_dir, config.redis_address.clone())
+    let mut storage = StorageBox::new(&config.storage)
         .await
         .expect("Failed to initialize storage backend");
+    let migration_target = match &config.migration {
+        Some(m) => Some(
+            StorageBox::new(&m.storage)
+                .await
+                .expect("Failed to initialize migration storage backend"),
+        ),
+        None => None,
+    };
 
-    // Periodically check paste expire
-    let s1 = storage.clone();
-    rt::spawn(async move {
-        loop {
-            rt::time::delay_for(Duration::from_secs(60)).await;
-            match s1.inner.cleanup().await {
-                Ok(_ok) => (),
-                Err(err) => warn!("{}", &err.to_string()),
+    let audit = match &config.audit_log {
+        Some(path) => AuditLog::open(std::path::Path::new(path)).expect("Failed to open audit log"),
+        None => AuditLog::default(),
+    };
+
+    if let Some(m) = matches.subcommand_matches("fsck") {
+        return fsck(&storage, m.is_present("repair")).await;
+    }
+    if let Some(m) = matches.subcommand_matches("export") {
+        let path = std::path::Path::new(m.value_of("archive").unwrap());
+        let summary = archive::export(&*storage.inner, path)
+            .await
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        for id in &summary.failed {
+            println!("{} failed", id);
+        }
+        println!(
+            "{} pastes exported, {} failed.",
+            summary.exported,
+            summary.failed.len()
+        );
+        return Ok(());
+    }
+    if let Some(m) = matches.subcommand_matches("import") {
+        let path = std::path::Path::new(m.value_of("archive").unwrap());
+        let on_conflict = m.value_of("on-conflict").unwrap().parse().unwrap();
+        let summary = archive::import(&*storage.inner, path, on_conflict, &audit)
+            .await
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        for (old, new) in &summary.renamed {
+            println!("{} renamed to {}", old, new);
+        }
+        println!(
+            "{} pastes imported, {} skipped.",
+            summary.imported, summary.skipped
+        );
+        return Ok(());
+    }
+    if matches.subcommand_matches("migrate").is_some() {
+        let (m, target) = match (&config.migration, &migration_target) {
+            (Some(m), Some(target)) => (m, target),
+            _ => {
+                return Err(std::io::Error::other("No migration section in config"));
             }
+        };
+        let summary = migrate::migrate(
+            &*storage.inner,
+            &*target.inner,
+            std::path::Path::new(&m.journal),
+        )
+        .await
+        .map_err(|err| std::io::Error::other(err.to_string()))?;
+        for id in &summary.failed {
+            println!("{} failed", id);
         }
-    });
+        println!(
+            "{} pastes copied, {} already done, {} failed.",
+            summary.copied,
+            summary.skipped,
+            summary.failed.len()
+        );
+        return Ok(());
+    }
+
+    // Live migration: mirror all changes to the new backend while copying the old pastes
+    if let (Some(m), Some(target)) = (&config.migration, migration_target) {
+        if m.dual_write {
+            let source = storage.clone();
+            let dest = target.clone();
+            let journal = m.journal.clone();
+            rt::spawn(async move {
+                let res =
+                    migrate::migrate(&*source.inner, &*dest.inner, std::path::Path::new(&journal))
+                        .await;
+                if let Err(err) = res {
+                    error!("Migration failed: {}", err);
+                }
+            });
+            storage = StorageBox::dual_write(storage, target);
+        }
+    }
+    let state = PasteState::new(config.clone(), storage).expect("Failed to set up server");
+    if state.webhooks.is_enabled() {
+        rt::spawn(state.webhooks.clone().run());
+    }
+
+    // Periodically check paste expire
+    let interval = Duration::from_secs(config.cleanup_interval.unwrap_or(60));
+    rt::spawn(cleanup_loop(
+        state.storage.clone(),
+        interval,
+        state.cleanup.clone(),
+        state.audit.clone(),
+        state.webhooks.clone(),
+    ));
+    rt::spawn(state.uploads.clone().run(interval));
 
     // Run http server
-    let c2 = config.clone();
     HttpServer::new(move || {
-        let generated = generate();
-        let auth = HttpAuthentication::basic(misc::auth::validator);
         App::new()
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
-            .data(PasteState {
-                storage: storage.clone(),
-                config: c2.clone(),
-            })
-            .service(
-                web::resource("/f").route(web::route().guard(guard::Get()).to(page::form::render)),
-            )
-            .service(
-                web::scope("/admin")
-                    .wrap(auth)
-                    .service(
-                        web::resource("/list")
-                            .route(web::route().guard(guard::Get()).to(api::admin::list::get)),
-                    )
-                    .service(
-                        web::resource("/{paste_id}")
-                            .route(web::route().guard(guard::Put()).to(api::admin::paste::put))
-                            .route(
-                                web::route()
-                                    .guard(guard::Delete())
-                                    .to(api::admin::paste::delete),
-                            ),
-                    ),
-            )
-            .service(
-                web::resource("/")
-                    .route(web::route().guard(guard::Get()).to(page::index::render))
-                    .route(web::route().guard(guard::Post()).to(api::new::post)),
-            )
-            .service(
-                web::resource("/{paste_id}")
-                    .route(web::route().guard(guard::Delete()).to(api::delete::delete))
-                    .route(web::route().guard(guard::Get()).to(api::get::get))
-                    .route(web::route().guard(guard::Head()).to(api::get::head))
-                    .route(web::route().guard(guard::Put()).to(api::modify::put)),
-            )
-            .service(
-                web::resource("/{paste_id}/audio")
-                    .route(web::route().guard(guard::Get()).to(page::audio::render)),
-            )
-            .service(
-                web::resource("/{paste_id}/{lang}")
-                    .route(web::route().guard(guard::Get()).to(page::code::render)),
-            )
-            .service(actix_web_static_files::ResourceFiles::new(
-                "/static", generated,
-            ))
+            .data(state.clone())
+            .configure(rspb::routes)
     })
     .bind(&config.bind_address)?
     .run()
     .await
 }
+
+/// Delete expired pastes every `interval`, or as soon as the next one is due
+async fn cleanup_loop(
+    storage: StorageBox,
+    interval: Duration,
+    wakeup: Arc<Notify>,
+    audit: AuditLog,
+    webhooks: Webhooks,
+) {
+    // Don't spin on pastes that can't be deleted
+    const MIN_DELAY: Duration = Duration::from_secs(1);
+
+    loop {
+        let delay = match storage.inner.next_expiry().await {
+            Ok(Some(t)) => {
+                let until = (t - Utc::now()).to_std().unwrap_or(MIN_DELAY);
+                interval.min(until.max(MIN_DELAY))
+            }
+            Ok(None) => interval,
+            Err(err) => {
+                warn!("{}", &err.to_string());
+                interval
+            }
+        };
+
+        let sleep = rt::time::delay_for(delay);
+        let notified = wakeup.notified();
+        futures::pin_mut!(sleep, notified);
+        if let Either::Right(_) = future::select(sleep, notified).await {
+            // Expire times changed, schedule again
+            continue;
+        }
+
+        match storage.inner.cleanup().await {
+            Ok(deleted) => {
+                for id in &deleted {
+                    audit.record(Operation::Expire, id, Actor::System);
+                    webhooks.fire(Event::Expired, id).await;
+                }
+            }
+            Err(err) => warn!("{}", &err.to_string()),
+        }
+    }
+}
+
+async fn fsck(storage: &StorageBox, repair: bool) -> std::io::Result<()> {
+    let problems = storage
+        .inner
+        .fsck(repair)
+        .await
+        .map_err(|err| std::io::Error::other(err.to_string()))?;
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("No inconsistencies found.");
+    } else if repair {
+        println!("{} inconsistencies repaired.", problems.len());
+    } else {
+        println!(
+            "{} inconsistencies found, run with --repair to fix them.",
+            problems.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Copy every paste from one storage backend to another through the `Storage` trait.
+//!
+//! Finished pastes are recorded in a journal file (one `id hash` line each), so an interrupted
+//! migration can be resumed by simply running it again.
+use crate::storage::{Response, Storage};
+
+use anyhow::{format_err, Result};
+use blake2::{Blake2b, Digest};
+use futures::StreamExt;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Default)]
+pub struct MigrationSummary {
+    pub copied: usize,
+    pub skipped: usize,
+    pub failed: Vec<String>,
+}
+
+/// Hash the content of a paste, optionally copying it to `to` along the way
+async fn hash_content(
+    storage: &dyn Storage,
+    id: &str,
+    mut to: Option<&mut (dyn tokio::io::AsyncWrite + Send + Unpin)>,
+) -> Result<String> {
+    let mut hasher = Blake2b::new();
+    match storage.get(id).await? {
+        Response::Content(vec) => {
+            hasher.update(&vec);
+            if let Some(w) = to.as_mut() {
+                w.write_all(&vec).await?;
+            }
+        }
+        Response::Stream(mut stream) => {
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                hasher.update(&chunk);
+                if let Some(w) = to.as_mut() {
+                    w.write_all(&chunk).await?;
+                }
+            }
+        }
+    }
+    if let Some(w) = to.as_mut() {
+        w.flush().await?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// View counts are kept apart from the metadata. Pastes mirrored by dual-write have some counted
+/// already.
+async fn copy_views(source: &dyn Storage, dest: &dyn Storage, id: &str) -> Result<()> {
+    let missing = source.views(id).await?.saturating_sub(dest.views(id).await?);
+    dest.add_views(id, missing).await?;
+    Ok(())
+}
+
+async fn copy(source: &dyn Storage, dest: &dyn Storage, id: &str) -> Result<String> {
+    let meta = source.get_meta(id).await?;
+
+    if dest.exists(id).await? {
+        // Left over from an interrupted run or mirrored by dual-write
+        let source_hash = hash_content(source, id, None).await?;
+        match hash_content(dest, id, None).await {
+            Ok(hash) if hash == source_hash => {
+                dest.set_meta(id, &meta).await?;
+                copy_views(source, dest, id).await?;
+                return Ok(source_hash);
+            }
+            _ => dest.delete(id).await?,
+        }
+    }
+
+    let mut writer = dest.new(id, meta.key()).await?;
+    let source_hash = hash_content(source, id, Some(&mut *writer)).await?;
+    drop(writer);
+    dest.set_meta(id, &meta).await?;
+
+    let dest_hash = hash_content(dest, id, None).await?;
+    if dest_hash != source_hash {
+        dest.delete(id).await?;
+        return Err(format_err!("Content hash mismatch after copy"));
+    }
+    copy_views(source, dest, id).await?;
+
+    Ok(source_hash)
+}
+
+fn read_journal(journal: &Path) -> Result<HashSet<String>> {
+    if !journal.exists() {
+        return Ok(HashSet::new());
+    }
+    let done = std::fs::read_to_string(journal)?
+        .lines()
+        .filter_map(|line| line.split(' ').next())
+        .map(|id| id.to_string())
+        .collect();
+    Ok(done)
+}
+
+/// Copy all pastes from `source` to `dest`, skipping the ones already recorded in `journal`
+pub async fn migrate(
+    source: &dyn Storage,
+    dest: &dyn Storage,
+    journal: &Path,
+) -> Result<MigrationSummary> {
+    let done = read_journal(journal)?;
+    let mut journal_file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal)?;
+    let mut summary = MigrationSummary::default();
+
+    let metas = source.get_all_meta().await?;
+    info!(
+        "Migrating {} pastes, {} already done.",
+        metas.len(),
+        done.len()
+    );
+    for (id, _meta) in metas {
+        if done.contains(&id) && dest.exists(&id).await? {
+            summary.skipped += 1;
+            continue;
+        }
+
+        match copy(source, dest, &id).await {
+            Ok(hash) => {
+                debug!("Migrated paste {}.", id);
+                writeln!(journal_file, "{} {}", id, hash)?;
+                summary.copied += 1;
+            }
+            Err(err) => {
+                warn!("Failed to migrate paste {}: {}", id, err);
+                summary.failed.push(id);
+            }
+        }
+    }
+    journal_file.sync_all()?;
+
+    info!(
+        "Migration finished: {} copied, {} skipped, {} failed.",
+        summary.copied,
+        summary.skipped,
+        summary.failed.len()
+    );
+    Ok(summary)
+}
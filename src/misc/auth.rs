@@ -7,6 +7,16 @@ use actix_web_httpauth::extractors::basic::{BasicAuth, Config};
 use actix_web_httpauth::extractors::AuthenticationError;
 use blake2::{Blake2b, Digest};
 use log::warn;
+use std::collections::HashMap;
+
+/// Whether the credentials are those of an admin in the config
+pub fn is_admin(admins: &HashMap<String, String>, credentials: &BasicAuth) -> bool {
+    let password_hash = match credentials.password() {
+        Some(p) => format!("{:x}", Blake2b::digest(p.as_bytes())),
+        None => return false,
+    };
+    admins.get(credentials.user_id().as_ref()) == Some(&password_hash)
+}
 
 pub async fn validator(
     req: ServiceRequest,
@@ -17,28 +27,15 @@ pub async fn validator(
         .unwrap()
         .config
         .admins;
-    let password_hash = match credentials.password() {
-        Some(p) => format!("{:x}", Blake2b::digest(p.as_bytes())),
-        None => {
-            return Err(AuthenticationError::from(Config::default()).into());
-        }
-    };
+    if is_admin(admins, &credentials) {
+        return Ok(req);
+    }
 
-    let username = credentials.user_id().to_string();
-    match admins.get(&username) {
-        Some(local_hash) => {
-            if local_hash == &password_hash {
-                return Ok(req);
-            } else {
-                warn!(
-                    "{:?} attempt to access admin, but wrong password.",
-                    req.connection_info().realip_remote_addr()
-                );
-                return Err(AuthenticationError::from(Config::default()).into());
-            }
-        }
-        None => {
-            return Err(AuthenticationError::from(Config::default()).into());
-        }
+    if credentials.password().is_some() && admins.contains_key(credentials.user_id().as_ref()) {
+        warn!(
+            "{:?} attempt to access admin, but wrong password.",
+            req.connection_info().realip_remote_addr()
+        );
     }
+    Err(AuthenticationError::from(Config::default()).into())
 }
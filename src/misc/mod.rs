@@ -1,19 +1,21 @@
 pub mod auth;
 use anyhow::{format_err, Result};
+use percent_encoding::percent_decode_str;
 use std::collections::HashMap;
 
+/// Parse `a=b&c=d` pairs, as in the query string of a URL
 pub fn parse_query_string(s: &str) -> Result<HashMap<String, String>> {
     let mut args: HashMap<String, String> = HashMap::new();
 
     if !s.is_empty() {
-        let args_str: Vec<&str> = s.split(',').collect();
+        let args_str: Vec<&str> = s.split('&').collect();
         // Generate arg pairs
         for x in args_str {
-            let arg: Vec<&str> = x.split('=').collect();
+            let arg: Vec<&str> = x.splitn(2, '=').collect();
             if arg.len() != 2 {
                 return Err(format_err!("Invalid query string"));
             } else {
-                args.insert(arg[0].to_string(), arg[1].to_string());
+                args.insert(decode(arg[0])?, decode(arg[1])?);
             }
         }
     }
@@ -21,6 +23,11 @@ pub fn parse_query_string(s: &str) -> Result<HashMap<String, String>> {
     Ok(args)
 }
 
+fn decode(s: &str) -> Result<String> {
+    let s = s.replace('+', " ");
+    Ok(percent_decode_str(&s).decode_utf8()?.into_owned())
+}
+
 #[macro_export]
 macro_rules! skip_fail {
     ($res:expr) => {
@@ -0,0 +1,104 @@
+//! Abuse reports waiting for moderators, kept as `base_dir/reports/{id}.json` per reported paste.
+use anyhow::Result;
+use async_std::path::{Path, PathBuf};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+pub const REPORTS_DIR: &str = "reports";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Report {
+    pub time: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Open reports of one paste
+#[derive(Serialize)]
+pub struct ReportedPaste {
+    pub id: String,
+    pub reports: Vec<Report>,
+}
+
+#[derive(Clone)]
+pub struct Reports {
+    dir: PathBuf,
+    // Adding a report reads and rewrites the whole file
+    lock: Arc<Mutex<()>>,
+}
+
+impl Reports {
+    /// Keep reports in `base_dir/reports`
+    pub fn new(base: &Path) -> Reports {
+        Reports {
+            dir: base.join(REPORTS_DIR),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id.to_owned() + ".json")
+    }
+
+    async fn read(&self, id: &str) -> Result<Vec<Report>> {
+        let path = self.path(id);
+        if !path.is_file().await {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_slice(&fs::read(path).await?)?)
+    }
+
+    /// Add a report, returns how many addresses have reported the paste. Reporting again from the
+    /// same address only updates the reason, and reports without an address don't count.
+    pub async fn add(&self, id: &str, report: Report) -> Result<usize> {
+        let _guard = self.lock.lock().await;
+        let mut reports = self.read(id).await?;
+        match reports
+            .iter_mut()
+            .find(|r| r.ip.is_some() && r.ip == report.ip)
+        {
+            Some(old) => *old = report,
+            None => reports.push(report),
+        }
+
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(self.path(id), serde_json::to_vec(&reports)?).await?;
+        Ok(reports.iter().filter(|r| r.ip.is_some()).count())
+    }
+
+    /// All reported pastes, most reported first
+    pub async fn list(&self) -> Result<Vec<ReportedPaste>> {
+        let mut pastes = Vec::new();
+        if !self.dir.is_dir().await {
+            return Ok(pastes);
+        }
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = name.strip_suffix(".json") {
+                let reports = self.read(id).await?;
+                pastes.push(ReportedPaste {
+                    id: id.to_string(),
+                    reports,
+                });
+            }
+        }
+        pastes.sort_by_key(|p| std::cmp::Reverse(p.reports.len()));
+        Ok(pastes)
+    }
+
+    /// Close all reports of a paste, returns false if there were none
+    pub async fn dismiss(&self, id: &str) -> Result<bool> {
+        let _guard = self.lock.lock().await;
+        let path = self.path(id);
+        if !path.is_file().await {
+            return Ok(false);
+        }
+        fs::remove_file(path).await?;
+        Ok(true)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::api;
 use crate::PasteState;
 
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
@@ -31,16 +32,21 @@ pub async fn render(
     debug!("GET audio paste with id {}.", &id);
 
     // Get paste name
-    let res = data.storage.inner.exists(&id);
+    let res = data.storage.inner.exists(&id).await;
     if res.is_err() | !res.unwrap() {
         return HttpResponse::NotFound().body("404 Paste Not Found");
     }
 
-    let name = match data.storage.inner.get_meta(&id) {
-        Ok(meta) => match meta.name {
-            Some(n) => n,
-            None => "untitled".to_string(),
-        },
+    let name = match data.storage.inner.get_meta(&id).await {
+        Ok(meta) => {
+            if let Some(err) = api::get::moderated(&meta) {
+                return api::get::text_error(err);
+            }
+            match meta.name {
+                Some(n) => n,
+                None => "untitled".to_string(),
+            }
+        }
         Err(_e) => return HttpResponse::InternalServerError().body("Internal Server Error"),
     };
 
@@ -1,3 +1,4 @@
+use crate::api;
 use crate::storage::Response;
 use crate::PasteState;
 
@@ -49,11 +50,17 @@ pub async fn render(
         }
     };
 
-    let name = match data.storage.inner.get_meta(&id) {
-        Ok(meta) => match meta.name {
-            Some(n) => n,
-            None => "untitled".to_string(),
-        },
+    let name = match data.storage.inner.get_meta(&id).await {
+        Ok(mut meta) => {
+            if let Some(err) = api::get::moderated(&meta) {
+                return api::get::text_error(err);
+            }
+            api::get::record_view(&data, &id, &mut meta).await;
+            match meta.name {
+                Some(n) => n,
+                None => "untitled".to_string(),
+            }
+        }
         Err(_e) => return HttpResponse::InternalServerError().body("Internal Server Error"),
     };
 
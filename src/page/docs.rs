@@ -0,0 +1,108 @@
+use crate::api::openapi;
+use crate::PasteState;
+
+use actix_web::{web, HttpResponse, Responder};
+use serde_json::Value;
+use yarte::Template;
+
+struct Parameter {
+    name: String,
+    location: String,
+    description: String,
+}
+
+struct Endpoint {
+    method: String,
+    path: String,
+    summary: String,
+    description: String,
+    parameters: Vec<Parameter>,
+    /// Status codes of the replies
+    statuses: String,
+}
+
+struct Section {
+    name: String,
+    description: String,
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Template)]
+#[template(path = "docs")]
+struct DocsTemplate {
+    title: String,
+    slogan: String,
+    sections: Vec<Section>,
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// Operations of the OpenAPI document, by tag
+fn sections(spec: &Value) -> Vec<Section> {
+    let mut sections: Vec<Section> = spec["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| Section {
+            name: text(&tag["name"]),
+            description: text(&tag["description"]),
+            endpoints: Vec::new(),
+        })
+        .collect();
+
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        for (method, operation) in item.as_object().unwrap() {
+            let tag = text(&operation["tags"][0]);
+            let section = match sections.iter_mut().find(|s| s.name == tag) {
+                Some(section) => section,
+                None => continue,
+            };
+            let parameters = operation["parameters"]
+                .as_array()
+                .map(|params| {
+                    params
+                        .iter()
+                        .map(|param| Parameter {
+                            name: text(&param["name"]),
+                            location: text(&param["in"]),
+                            description: text(&param["description"]),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let statuses = operation["responses"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            section.endpoints.push(Endpoint {
+                method: method.to_uppercase(),
+                path: path.clone(),
+                summary: text(&operation["summary"]),
+                description: text(&operation["description"]),
+                parameters,
+                statuses,
+            });
+        }
+    }
+    sections
+}
+
+pub async fn render(data: web::Data<PasteState>) -> impl Responder {
+    let spec = openapi::spec(&data.config.site.url);
+    let ctx = DocsTemplate {
+        title: data.config.site.name.clone(),
+        slogan: data.config.site.slogan.clone(),
+        sections: sections(&spec),
+    };
+
+    let content = ctx.call().unwrap();
+
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(content)
+}
@@ -1,4 +1,5 @@
 pub mod audio;
 pub mod code;
+pub mod docs;
 pub mod form;
 pub mod index;
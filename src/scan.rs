@@ -0,0 +1,260 @@
+//! Content scanning of uploads.
+//!
+//! Content is scanned while it's being written to storage, so pastes of any size are fine. Every
+//! stage sees all written bytes in order and reports the rules that matched once the upload is
+//! done. Regex rules are matched line by line, hash lists are compared against the SHA-256 of
+//! the whole content.
+use crate::storage::{ScanAction, ScanFinding};
+
+use anyhow::{format_err, Result};
+use regex::bytes::RegexSet;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+
+// Longer lines are matched in pieces
+const MAX_LINE: usize = 64 * 1024;
+
+#[derive(Deserialize, Clone, Default)]
+pub struct ScanConfig {
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    #[serde(default)]
+    pub hash_lists: Vec<HashListConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RuleConfig {
+    pub name: String,
+    /// Regex matched against every line
+    pub pattern: String,
+    pub action: ScanAction,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HashListConfig {
+    pub name: String,
+    /// File with one hex SHA-256 per line
+    pub path: String,
+    pub action: ScanAction,
+}
+
+/// Kind of rules, started once per upload
+trait Stage: Send + Sync {
+    fn start(&self) -> Box<dyn Scanner>;
+}
+
+/// Scans the content of one upload
+trait Scanner: Send {
+    fn feed(&mut self, chunk: &[u8]);
+    fn finish(self: Box<Self>) -> Vec<ScanFinding>;
+}
+
+struct RegexStage {
+    set: Arc<RegexSet>,
+    rules: Arc<Vec<ScanFinding>>,
+}
+
+impl Stage for RegexStage {
+    fn start(&self) -> Box<dyn Scanner> {
+        Box::new(RegexScanner {
+            set: self.set.clone(),
+            rules: self.rules.clone(),
+            line: Vec::new(),
+            matched: vec![false; self.rules.len()],
+        })
+    }
+}
+
+struct RegexScanner {
+    set: Arc<RegexSet>,
+    rules: Arc<Vec<ScanFinding>>,
+    // Incomplete last line
+    line: Vec<u8>,
+    matched: Vec<bool>,
+}
+
+impl RegexScanner {
+    fn check(&mut self, text: &[u8]) {
+        for i in self.set.matches(text).iter() {
+            self.matched[i] = true;
+        }
+    }
+}
+
+impl Scanner for RegexScanner {
+    fn feed(&mut self, chunk: &[u8]) {
+        self.line.extend_from_slice(chunk);
+        if let Some(end) = self.line.iter().rposition(|b| *b == b'\n') {
+            let rest = self.line.split_off(end + 1);
+            let lines = std::mem::replace(&mut self.line, rest);
+            for line in lines.split(|b| *b == b'\n') {
+                self.check(line);
+            }
+        }
+        if self.line.len() > MAX_LINE {
+            let line = std::mem::take(&mut self.line);
+            self.check(&line);
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<ScanFinding> {
+        let line = std::mem::take(&mut self.line);
+        self.check(&line);
+        self.rules
+            .iter()
+            .zip(&self.matched)
+            .filter(|(_rule, matched)| **matched)
+            .map(|(rule, _matched)| rule.clone())
+            .collect()
+    }
+}
+
+struct HashStage {
+    hashes: Arc<HashMap<Vec<u8>, ScanFinding>>,
+}
+
+impl Stage for HashStage {
+    fn start(&self) -> Box<dyn Scanner> {
+        Box::new(HashScanner {
+            hashes: self.hashes.clone(),
+            hasher: Sha256::new(),
+        })
+    }
+}
+
+struct HashScanner {
+    hashes: Arc<HashMap<Vec<u8>, ScanFinding>>,
+    hasher: Sha256,
+}
+
+impl Scanner for HashScanner {
+    fn feed(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<ScanFinding> {
+        let hash = self.hasher.finalize();
+        self.hashes.get(&hash[..]).cloned().into_iter().collect()
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Compiled scan rules, shared by all uploads. Finds nothing unless rules are configured.
+#[derive(Clone, Default)]
+pub struct ContentScanner {
+    stages: Arc<Vec<Box<dyn Stage>>>,
+}
+
+impl ContentScanner {
+    pub fn new(config: &ScanConfig) -> Result<ContentScanner> {
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+
+        if !config.rules.is_empty() {
+            let set = RegexSet::new(config.rules.iter().map(|r| &r.pattern))?;
+            let rules = config
+                .rules
+                .iter()
+                .map(|r| ScanFinding {
+                    rule: r.name.clone(),
+                    action: r.action,
+                })
+                .collect();
+            stages.push(Box::new(RegexStage {
+                set: Arc::new(set),
+                rules: Arc::new(rules),
+            }));
+        }
+
+        let mut hashes = HashMap::new();
+        for list in &config.hash_lists {
+            let content = std::fs::read_to_string(&list.path)
+                .map_err(|err| format_err!("Can't read hash list {}: {}", list.path, err))?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // Also accept sha256sum output
+                let hex = line.split_whitespace().next().unwrap_or(line);
+                let hash = parse_hex(hex)
+                    .ok_or_else(|| format_err!("Bad hash {} in {}", hex, list.path))?;
+                hashes.insert(
+                    hash,
+                    ScanFinding {
+                        rule: list.name.clone(),
+                        action: list.action,
+                    },
+                );
+            }
+        }
+        if !hashes.is_empty() {
+            stages.push(Box::new(HashStage {
+                hashes: Arc::new(hashes),
+            }));
+        }
+
+        Ok(ContentScanner {
+            stages: Arc::new(stages),
+        })
+    }
+
+    /// Scan everything written through `inner`
+    pub fn wrap<W: AsyncWrite + Unpin>(&self, inner: W) -> ScanningWriter<W> {
+        ScanningWriter {
+            inner,
+            scanners: self.stages.iter().map(|stage| stage.start()).collect(),
+        }
+    }
+}
+
+pub struct ScanningWriter<W> {
+    inner: W,
+    scanners: Vec<Box<dyn Scanner>>,
+}
+
+impl<W> ScanningWriter<W> {
+    /// Rules that matched everything written so far
+    pub fn finish(self) -> Vec<ScanFinding> {
+        self.scanners
+            .into_iter()
+            .flat_map(|scanner| scanner.finish())
+            .collect()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ScanningWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            for scanner in self.scanners.iter_mut() {
+                scanner.feed(&buf[..n]);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,256 @@
+//! This storage backend wraps two backends during a migration. Everything is read from and written
+//! to the primary one, while mutations are also mirrored to the secondary one on a best-effort basis.
+use crate::storage::{
+    CacheStats, Inconsistency, MetaQuery, PasteMeta, PasteWriter, Response, Storage, StorageBox,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use log::warn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+
+// Bytes the secondary writer may lag behind before we wait for it
+const MAX_PENDING: usize = 1024 * 1024;
+
+#[derive(Clone)]
+pub struct DualWriteStorage {
+    primary: StorageBox,
+    secondary: StorageBox,
+}
+
+impl DualWriteStorage {
+    pub fn new(primary: StorageBox, secondary: StorageBox) -> DualWriteStorage {
+        DualWriteStorage { primary, secondary }
+    }
+
+    /// Whether the paste was copied to the secondary storage already. Pastes not copied yet will
+    /// be picked up by the migration later.
+    async fn mirrored(&self, id: &str) -> bool {
+        best_effort(id, self.secondary.inner.exists(id).await).unwrap_or(false)
+    }
+}
+
+fn best_effort<T>(id: &str, res: Result<T>) -> Option<T> {
+    match res {
+        Ok(val) => Some(val),
+        Err(err) => {
+            warn!(
+                "Failed to mirror paste {} to secondary storage: {}",
+                id, err
+            );
+            None
+        }
+    }
+}
+
+/// Copies everything written to the primary writer into the secondary one
+struct TeeWriter {
+    id: String,
+    primary: PasteWriter,
+    secondary: Option<PasteWriter>,
+    pending: Vec<u8>,
+}
+
+impl TeeWriter {
+    fn drop_secondary(&mut self, err: io::Error) {
+        warn!(
+            "Failed to mirror paste {} to secondary storage: {}",
+            self.id, err
+        );
+        self.secondary = None;
+        self.pending.clear();
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while !self.pending.is_empty() {
+            let secondary = match self.secondary.as_mut() {
+                Some(s) => s,
+                None => break,
+            };
+            match Pin::new(secondary).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => {
+                    self.drop_secondary(io::ErrorKind::WriteZero.into());
+                }
+                Poll::Ready(Ok(n)) => {
+                    self.pending.drain(..n);
+                }
+                Poll::Ready(Err(err)) => self.drop_secondary(err),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    }
+
+    fn poll_secondary<F>(&mut self, cx: &mut Context<'_>, f: F) -> Poll<()>
+    where
+        F: FnOnce(Pin<&mut PasteWriter>, &mut Context<'_>) -> Poll<io::Result<()>>,
+    {
+        if self.poll_drain(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if let Some(secondary) = self.secondary.as_mut() {
+            match f(Pin::new(secondary), cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(err)) => self.drop_secondary(err),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+impl AsyncWrite for TeeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Don't let the secondary lag behind too much
+        if this.pending.len() >= MAX_PENDING && this.poll_drain(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let res = Pin::new(&mut this.primary).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if this.secondary.is_some() {
+                this.pending.extend_from_slice(&buf[..n]);
+                let _ = this.poll_drain(cx);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this
+            .poll_secondary(cx, |w, cx| w.poll_flush(cx))
+            .is_pending()
+        {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.primary).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this
+            .poll_secondary(cx, |w, cx| w.poll_shutdown(cx))
+            .is_pending()
+        {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.primary).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Storage for DualWriteStorage {
+    async fn exists(&self, id: &str) -> Result<bool> {
+        self.primary.inner.exists(id).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Response> {
+        self.primary.inner.get(id).await
+    }
+
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        self.primary.inner.get_meta(id).await
+    }
+
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        self.primary.inner.get_all_meta().await
+    }
+
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.primary.inner.list_meta(query).await
+    }
+
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.primary.inner.views(id).await
+    }
+
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        let primary = self.primary.inner.new(id, key).await?;
+        let secondary = best_effort(id, self.secondary.inner.new(id, key).await);
+        Ok(Box::new(TeeWriter {
+            id: id.to_string(),
+            primary,
+            secondary,
+            pending: Vec::new(),
+        }))
+    }
+
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.primary.inner.set_meta(id, meta).await?;
+        if self.mirrored(id).await {
+            best_effort(id, self.secondary.inner.set_meta(id, meta).await);
+        }
+        Ok(())
+    }
+
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        let views = self.primary.inner.add_views(id, count).await?;
+        if self.mirrored(id).await {
+            best_effort(id, self.secondary.inner.add_views(id, count).await);
+        }
+        Ok(views)
+    }
+
+    async fn update_size(&self, id: &str) -> Result<()> {
+        self.primary.inner.update_size(id).await?;
+        if self.mirrored(id).await {
+            best_effort(id, self.secondary.inner.update_size(id).await);
+        }
+        Ok(())
+    }
+
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
+        let primary = self.primary.inner.update(id).await?;
+        let secondary = if self.mirrored(id).await {
+            best_effort(id, self.secondary.inner.update(id).await)
+        } else {
+            None
+        };
+        Ok(Box::new(TeeWriter {
+            id: id.to_string(),
+            primary,
+            secondary,
+            pending: Vec::new(),
+        }))
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.primary.inner.delete(id).await?;
+        if self.mirrored(id).await {
+            best_effort(id, self.secondary.inner.delete(id).await);
+        }
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        let deleted = self.primary.inner.cleanup().await?;
+        for id in &deleted {
+            if self.mirrored(id).await {
+                best_effort(id, self.secondary.inner.delete(id).await);
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.primary.inner.next_expiry().await
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        self.primary.inner.fsck(repair).await
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.primary.inner.cache_stats()
+    }
+}
@@ -0,0 +1,271 @@
+//! This storage backend wraps any other backend, and keeps small pastes and metadata in an in-process
+//! LRU cache with a fixed byte budget. Useful when there's no Redis around.
+use crate::storage::{
+    CacheStats, Inconsistency, MetaQuery, PasteMeta, PasteWriter, Response, Storage, StorageBox,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use log::debug;
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Hash, PartialEq, Eq)]
+enum CacheKey {
+    Content(String),
+    Meta(String),
+}
+
+enum CacheValue {
+    Content(Vec<u8>),
+    Meta(Box<PasteMeta>),
+}
+
+impl CacheValue {
+    // Rough memory footprint, good enough for budgeting
+    fn size(&self) -> u64 {
+        let extra = match self {
+            Self::Content(vec) => vec.len(),
+            Self::Meta(meta) => {
+                std::mem::size_of::<PasteMeta>()
+                    + meta.name.as_ref().map(|n| n.len()).unwrap_or(0)
+                    + meta.key().len()
+            }
+        };
+        (std::mem::size_of::<Self>() + extra) as u64
+    }
+}
+
+// Invalidations are counted per stripe of ids, which keeps the counters bounded
+const STRIPES: usize = 64;
+
+fn stripe(id: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    (hasher.finish() % STRIPES as u64) as usize
+}
+
+struct Cache {
+    lru: LruCache<CacheKey, CacheValue>,
+    bytes: u64,
+    budget: u64,
+    // Bumped whenever a paste changes, so reads that started before don't fill in old values
+    generations: [u64; STRIPES],
+}
+
+impl Cache {
+    fn get(&mut self, key: &CacheKey) -> Option<&CacheValue> {
+        self.lru.get(key)
+    }
+
+    fn put(&mut self, key: CacheKey, value: CacheValue) {
+        let size = value.size();
+        // Don't let a single entry flush the whole cache
+        if size > self.budget / 4 {
+            self.remove(&key);
+            return;
+        }
+
+        self.bytes += size;
+        if let Some(old) = self.lru.put(key, value) {
+            self.bytes -= old.size();
+        }
+        while self.bytes > self.budget {
+            match self.lru.pop_lru() {
+                Some((_k, v)) => self.bytes -= v.size(),
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(old) = self.lru.pop(key) {
+            self.bytes -= old.size();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryCachedStorage {
+    backend: StorageBox,
+    cache: Arc<Mutex<Cache>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl MemoryCachedStorage {
+    pub fn new(backend: StorageBox, budget: u64) -> MemoryCachedStorage {
+        MemoryCachedStorage {
+            backend,
+            cache: Arc::new(Mutex::new(Cache {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+                budget,
+                generations: [0; STRIPES],
+            })),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<CacheValue> {
+        let mut cache = self.cache.lock().unwrap();
+        let res = match cache.get(key) {
+            Some(CacheValue::Content(vec)) => Some(CacheValue::Content(vec.clone())),
+            Some(CacheValue::Meta(meta)) => Some(CacheValue::Meta(meta.clone())),
+            None => None,
+        };
+        match res {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        res
+    }
+
+    /// Take before reading from the backend, and hand to `fill`
+    fn generation(&self, id: &str) -> u64 {
+        self.cache.lock().unwrap().generations[stripe(id)]
+    }
+
+    /// Cache what was read from the backend, unless the paste changed in the meantime
+    fn fill(&self, id: &str, key: CacheKey, value: CacheValue, generation: u64) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generations[stripe(id)] == generation {
+            cache.put(key, value);
+        }
+    }
+
+    fn invalidate(&self, id: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.generations[stripe(id)] += 1;
+        cache.remove(&CacheKey::Content(id.to_string()));
+        cache.remove(&CacheKey::Meta(id.to_string()));
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryCachedStorage {
+    async fn exists(&self, id: &str) -> Result<bool> {
+        if self
+            .cache
+            .lock()
+            .unwrap()
+            .lru
+            .contains(&CacheKey::Meta(id.to_string()))
+        {
+            return Ok(true);
+        }
+        self.backend.inner.exists(id).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Response> {
+        let key = CacheKey::Content(id.to_string());
+        if let Some(CacheValue::Content(vec)) = self.lookup(&key) {
+            debug!("Paste {} hit memory cache.", id);
+            return Ok(Response::Content(vec));
+        }
+
+        let generation = self.generation(id);
+        let result = self.backend.inner.get(id).await?;
+        if let Response::Content(vec) = &result {
+            self.fill(id, key, CacheValue::Content(vec.clone()), generation);
+        }
+        Ok(result)
+    }
+
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        let key = CacheKey::Meta(id.to_string());
+        if let Some(CacheValue::Meta(meta)) = self.lookup(&key) {
+            return Ok(*meta);
+        }
+
+        let generation = self.generation(id);
+        let meta = self.backend.inner.get_meta(id).await?;
+        self.fill(id, key, CacheValue::Meta(Box::new(meta.clone())), generation);
+        Ok(meta)
+    }
+
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.inner.get_all_meta().await
+    }
+
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.inner.list_meta(query).await
+    }
+
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.backend.inner.views(id).await
+    }
+
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        self.invalidate(id);
+        self.backend.inner.new(id, key).await
+    }
+
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.backend.inner.set_meta(id, meta).await?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.generations[stripe(id)] += 1;
+        cache.put(
+            CacheKey::Meta(id.to_string()),
+            CacheValue::Meta(Box::new(meta.clone())),
+        );
+        Ok(())
+    }
+
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        self.backend.inner.add_views(id, count).await
+    }
+
+    async fn update_size(&self, id: &str) -> Result<()> {
+        self.invalidate(id);
+        self.backend.inner.update_size(id).await
+    }
+
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
+        self.invalidate(id);
+        self.backend.inner.update(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.invalidate(id);
+        self.backend.inner.delete(id).await
+    }
+
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        let deleted = self.backend.inner.cleanup().await?;
+        for id in &deleted {
+            self.invalidate(id);
+        }
+        Ok(deleted)
+    }
+
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.backend.inner.next_expiry().await
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        let problems = self.backend.inner.fsck(repair).await?;
+        if repair {
+            for problem in &problems {
+                self.invalidate(problem.id());
+            }
+        }
+        Ok(problems)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let cache = self.cache.lock().unwrap();
+        Some(CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: cache.lru.len(),
+            bytes: cache.bytes,
+            budget: cache.budget,
+        })
+    }
+}
@@ -0,0 +1,390 @@
+//! On-disk format of `PasteMeta`.
+//!
+//! Every record starts with `MAGIC` and a version number, followed by the bincode encoding of that
+//! version's layout. Records written before versioning (version 1) have no header at all, which is
+//! fine since their first byte is the length of the create time string and never `MAGIC`.
+//!
+//! When changing `PasteMeta`, copy its current layout into a new `PasteMetaVn` struct, bump
+//! `CURRENT_VERSION`, and convert the old struct in `decode`.
+use crate::storage::{LegalHold, Moderation, PasteMeta, ScanResult};
+
+use anyhow::{format_err, Result};
+use chrono::prelude::*;
+use serde::Deserialize;
+
+const MAGIC: u8 = 0xff;
+pub const CURRENT_VERSION: u8 = 8;
+
+/// Layout before versioning, and of version 2 which only added the header
+#[derive(Deserialize)]
+struct PasteMetaV1 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+}
+
+impl From<PasteMetaV1> for PasteMeta {
+    fn from(m: PasteMetaV1) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: None,
+            hold: None,
+            moderation: None,
+            scan: None,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+}
+
+/// Layout of version 3, before legal holds
+#[derive(Deserialize)]
+struct PasteMetaV3 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+    expire_after_inactive: Option<u32>,
+}
+
+impl From<PasteMetaV3> for PasteMeta {
+    fn from(m: PasteMetaV3) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: m.expire_after_inactive,
+            hold: None,
+            moderation: None,
+            scan: None,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+}
+
+/// Layout of version 4, before moderation
+#[derive(Deserialize)]
+struct PasteMetaV4 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+    expire_after_inactive: Option<u32>,
+    hold: Option<LegalHold>,
+}
+
+impl From<PasteMetaV4> for PasteMeta {
+    fn from(m: PasteMetaV4) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: m.expire_after_inactive,
+            hold: m.hold,
+            moderation: None,
+            scan: None,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+}
+
+/// Layout of version 5, before content scanning
+#[derive(Deserialize)]
+struct PasteMetaV5 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+    expire_after_inactive: Option<u32>,
+    hold: Option<LegalHold>,
+    moderation: Option<Moderation>,
+}
+
+impl From<PasteMetaV5> for PasteMeta {
+    fn from(m: PasteMetaV5) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: m.expire_after_inactive,
+            hold: m.hold,
+            moderation: m.moderation,
+            scan: None,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+}
+
+/// Layout of version 6, before view counts and revisions
+#[derive(Deserialize)]
+struct PasteMetaV6 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+    expire_after_inactive: Option<u32>,
+    hold: Option<LegalHold>,
+    moderation: Option<Moderation>,
+    scan: Option<ScanResult>,
+}
+
+impl From<PasteMetaV6> for PasteMeta {
+    fn from(m: PasteMetaV6) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: m.expire_after_inactive,
+            hold: m.hold,
+            moderation: m.moderation,
+            scan: m.scan,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+}
+
+/// Layout of version 7, which still counted views in the record
+#[derive(Deserialize)]
+struct PasteMetaV7 {
+    create_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    name: Option<String>,
+    size: u64,
+    key: String,
+    expire_after_inactive: Option<u32>,
+    hold: Option<LegalHold>,
+    moderation: Option<Moderation>,
+    scan: Option<ScanResult>,
+    views: u64,
+    revision: u32,
+    modify_time: Option<DateTime<Utc>>,
+}
+
+impl From<PasteMetaV7> for PasteMeta {
+    fn from(m: PasteMetaV7) -> Self {
+        PasteMeta {
+            create_time: m.create_time,
+            expire_time: m.expire_time,
+            atime: m.atime,
+            name: m.name,
+            size: m.size,
+            key: m.key,
+            expire_after_inactive: m.expire_after_inactive,
+            hold: m.hold,
+            moderation: m.moderation,
+            scan: m.scan,
+            revision: m.revision,
+            modify_time: m.modify_time,
+        }
+    }
+}
+
+/// Views counted in a version 7 record, to be moved to the view counter when it's upgraded
+pub fn legacy_views(bin: &[u8]) -> Option<u64> {
+    match bin.get(..2) {
+        Some([MAGIC, 7]) => Some(bincode::deserialize::<PasteMetaV7>(&bin[2..]).ok()?.views),
+        _ => None,
+    }
+}
+
+pub fn encode(meta: &PasteMeta) -> Result<Vec<u8>> {
+    let mut bin = vec![MAGIC, CURRENT_VERSION];
+    bincode::serialize_into(&mut bin, meta)?;
+    Ok(bin)
+}
+
+/// Decode a record of any known version, also returns the version it was stored in
+pub fn decode(bin: &[u8]) -> Result<(PasteMeta, u8)> {
+    if bin.first() != Some(&MAGIC) {
+        let meta: PasteMetaV1 = bincode::deserialize(bin)?;
+        return Ok((meta.into(), 1));
+    }
+
+    let version = *bin
+        .get(1)
+        .ok_or_else(|| format_err!("Truncated metadata record"))?;
+    let body = &bin[2..];
+    let meta = match version {
+        2 => bincode::deserialize::<PasteMetaV1>(body)?.into(),
+        3 => bincode::deserialize::<PasteMetaV3>(body)?.into(),
+        4 => bincode::deserialize::<PasteMetaV4>(body)?.into(),
+        5 => bincode::deserialize::<PasteMetaV5>(body)?.into(),
+        6 => bincode::deserialize::<PasteMetaV6>(body)?.into(),
+        7 => bincode::deserialize::<PasteMetaV7>(body)?.into(),
+        CURRENT_VERSION => bincode::deserialize(body)?,
+        _ => return Err(format_err!("Unknown metadata version {}", version)),
+    };
+
+    Ok((meta, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ModerationState, ScanAction};
+
+    fn fixture_meta() -> PasteMeta {
+        PasteMeta {
+            create_time: Utc.with_ymd_and_hms(2021, 2, 7, 12, 51, 55).unwrap(),
+            expire_time: Some(Utc.with_ymd_and_hms(2021, 2, 7, 13, 51, 55).unwrap()),
+            atime: None,
+            name: Some("paste1".to_string()),
+            size: 12,
+            key: "nbzethtnq1".to_string(),
+            expire_after_inactive: None,
+            hold: None,
+            moderation: None,
+            scan: None,
+            revision: 1,
+            modify_time: None,
+        }
+    }
+
+    fn assert_fixture(meta: &PasteMeta) {
+        let expected = fixture_meta();
+        assert_eq!(meta.create_time, expected.create_time);
+        assert_eq!(meta.expire_time, expected.expire_time);
+        assert_eq!(meta.atime, expected.atime);
+        assert_eq!(meta.name, expected.name);
+        assert_eq!(meta.size, expected.size);
+        assert!(meta.validate("nbzethtnq1"));
+    }
+
+    #[test]
+    fn decode_v1() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v1.bin")).unwrap();
+        assert_eq!(version, 1);
+        assert_fixture(&meta);
+    }
+
+    #[test]
+    fn decode_v2() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v2.bin")).unwrap();
+        assert_eq!(version, 2);
+        assert_fixture(&meta);
+    }
+
+    #[test]
+    fn decode_v3() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v3.bin")).unwrap();
+        assert_eq!(version, 3);
+        assert_fixture(&meta);
+        assert_eq!(meta.expire_after_inactive, Some(30));
+    }
+
+    #[test]
+    fn decode_v4() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v4.bin")).unwrap();
+        assert_eq!(version, 4);
+        assert_fixture(&meta);
+        let hold = meta.hold.unwrap();
+        assert_eq!(hold.by, "admin1");
+        assert_eq!(
+            hold.time,
+            Utc.with_ymd_and_hms(2021, 2, 8, 9, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_v5() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v5.bin")).unwrap();
+        assert_eq!(version, 5);
+        assert_fixture(&meta);
+        let moderation = meta.moderation.unwrap();
+        assert_eq!(moderation.state, ModerationState::Quarantined);
+        assert_eq!(moderation.by, None);
+        assert_eq!(
+            moderation.time,
+            Utc.with_ymd_and_hms(2021, 2, 9, 18, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_v6() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v6.bin")).unwrap();
+        assert_eq!(version, 6);
+        assert_fixture(&meta);
+        let scan = meta.scan.unwrap();
+        assert_eq!(
+            scan.time,
+            Utc.with_ymd_and_hms(2021, 2, 10, 7, 15, 0).unwrap()
+        );
+        assert_eq!(scan.findings.len(), 1);
+        assert_eq!(scan.findings[0].rule, "aws-key");
+        assert_eq!(scan.findings[0].action, ScanAction::Flag);
+        assert_eq!(meta.revision, 1);
+    }
+
+    #[test]
+    fn decode_v7() {
+        let (meta, version) = decode(include_bytes!("fixtures/meta_v7.bin")).unwrap();
+        assert_eq!(version, 7);
+        assert_fixture(&meta);
+        assert_eq!(legacy_views(include_bytes!("fixtures/meta_v7.bin")), Some(42));
+        assert_eq!(meta.revision, 3);
+        assert_eq!(
+            meta.modify_time,
+            Some(Utc.with_ymd_and_hms(2021, 2, 11, 10, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn decode_v8() {
+        let bin = include_bytes!("fixtures/meta_v8.bin");
+        let (meta, version) = decode(bin).unwrap();
+        assert_eq!(version, 8);
+        assert_fixture(&meta);
+        assert_eq!(legacy_views(bin), None);
+        assert_eq!(meta.revision, 3);
+        assert_eq!(
+            meta.modify_time,
+            Some(Utc.with_ymd_and_hms(2021, 2, 11, 10, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bin = encode(&fixture_meta()).unwrap();
+        let (meta, version) = decode(&bin).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+        assert_fixture(&meta);
+    }
+
+    #[test]
+    fn unknown_version() {
+        assert!(decode(&[MAGIC, CURRENT_VERSION + 1, 0, 0]).is_err());
+    }
+}
@@ -1,21 +1,33 @@
+use crate::storage::dualwritestorage::DualWriteStorage;
+use crate::storage::memorycachedstorage::MemoryCachedStorage;
 use crate::storage::rediscachedstorage::RedisCachedStorage;
+use crate::storage::redisstorage::RedisStorage;
 use crate::storage::simplestorage::SimpleStorage;
+use crate::storage::trashstorage::{Trash, TrashStorage};
 
-use anyhow::Result;
-use async_std::path::Path;
+use anyhow::{format_err, Result};
+use async_std::path::PathBuf;
 use async_trait::async_trait;
 use chrono::prelude::*;
 use dyn_clone::DynClone;
 use serde::{Deserialize, Serialize};
-use tokio::fs::File;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use bytes::BytesMut;
+use futures::Stream;
+use std::pin::Pin;
+use tokio::io::AsyncWrite;
+
+/// Where new paste content is written to. Callers should flush it when done.
+pub type PasteWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Content of large pastes, read piece by piece
+pub type PasteStream = Pin<Box<dyn Stream<Item = std::io::Result<BytesMut>> + Send>>;
 
 pub enum Response {
     Content(Vec<u8>),
-    Stream(FramedRead<File, BytesCodec>),
+    Stream(PasteStream),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PasteMeta {
     pub create_time: DateTime<Utc>,
     pub expire_time: Option<DateTime<Utc>>,
@@ -23,29 +35,242 @@ pub struct PasteMeta {
     pub name: Option<String>,
     pub size: u64,
     key: String,
+    /// Days without access after which the paste expires
+    pub expire_after_inactive: Option<u32>,
+    pub hold: Option<LegalHold>,
+    /// Set when moderators have taken the paste offline
+    pub moderation: Option<Moderation>,
+    /// Rules the content matched when last uploaded
+    pub scan: Option<ScanResult>,
+    /// Starts at 1, counts up with every modification
+    pub revision: u32,
+    pub modify_time: Option<DateTime<Utc>>,
+}
+
+/// Keeps a paste from expiring or being changed by its owner
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegalHold {
+    /// Admin who placed the hold
+    pub by: String,
+    pub time: DateTime<Utc>,
+}
+
+/// How a moderated paste is kept from being served
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationState {
+    /// Waiting for review, served as 451
+    Quarantined,
+    /// Taken down for good, served as 410
+    Hidden,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Moderation {
+    pub state: ModerationState,
+    /// Admin who did it, none if done automatically for reports or content rules
+    pub by: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+/// What to do with content matching a scan rule, from mildest to harshest
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanAction {
+    /// Only record the match
+    Flag,
+    Quarantine,
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanFinding {
+    pub rule: String,
+    pub action: ScanAction,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanResult {
+    pub time: DateTime<Utc>,
+    pub findings: Vec<ScanFinding>,
+}
+
+/// A problem found when checking a storage backend for consistency
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Inconsistency {
+    /// Content file exists, but there's no metadata for it
+    OrphanFile { id: String },
+    /// Metadata exists, but the content file is gone
+    MissingContent { id: String },
+    /// Recorded size doesn't match the actual content
+    WrongSize { id: String, recorded: u64, actual: u64 },
+    /// Metadata or content can't be read
+    Unreadable { id: String, reason: String },
+}
+
+impl Inconsistency {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::OrphanFile { id }
+            | Self::MissingContent { id }
+            | Self::WrongSize { id, .. }
+            | Self::Unreadable { id, .. } => id,
+        }
+    }
+}
+
+impl std::fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OrphanFile { id } => write!(f, "{}: content file without metadata", id),
+            Self::MissingContent { id } => write!(f, "{}: metadata without content file", id),
+            Self::WrongSize {
+                id,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "{}: recorded size {} but content is {} bytes",
+                id, recorded, actual
+            ),
+            Self::Unreadable { id, reason } => write!(f, "{}: unreadable ({})", id, reason),
+        }
+    }
+}
+
+/// Filters for listing pastes, all of them optional
+#[derive(Deserialize, Default)]
+pub struct MetaQuery {
+    /// Part of the paste id or name
+    pub search: Option<String>,
+    /// Only pastes expiring before this time, by expire time or inactivity
+    pub expire_before: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl MetaQuery {
+    pub fn matches(&self, id: &str, meta: &PasteMeta) -> bool {
+        if let Some(s) = &self.search {
+            let in_name = meta.name.as_ref().is_some_and(|n| n.contains(s.as_str()));
+            if !id.contains(s.as_str()) && !in_name {
+                return false;
+            }
+        }
+        if let Some(t) = self.expire_before {
+            if meta.expires_at().is_none_or(|e| e >= t) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Filter and page through metadata in memory, newest first. For stores without indexes.
+    pub fn apply(&self, metas: Vec<(String, PasteMeta)>) -> Vec<(String, PasteMeta)> {
+        let mut metas: Vec<(String, PasteMeta)> = metas
+            .into_iter()
+            .filter(|(id, meta)| self.matches(id, meta))
+            .collect();
+        metas.sort_by_key(|m| std::cmp::Reverse(m.1.create_time));
+        metas
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Counters of caching backends
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget: u64,
 }
 
 impl PasteMeta {
     pub fn validate(&self, key: &str) -> bool {
         key == self.key
     }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// When the paste should be gone, by its expire time or for being left alone too long. Never
+    /// while it's on hold.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        if self.hold.is_some() {
+            return None;
+        }
+        let inactive = self.expire_after_inactive.map(|days| {
+            self.atime.unwrap_or(self.create_time) + chrono::Duration::days(days as i64)
+        });
+        match (self.expire_time, inactive) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
 }
 
 #[async_trait]
-pub trait Storage: DynClone + Send {
+pub trait Storage: DynClone + Send + Sync {
     // Non-mutating methods
-    fn exists(&self, id: &str) -> Result<bool>;
+    async fn exists(&self, id: &str) -> Result<bool>;
     async fn get(&self, id: &str) -> Result<Response>;
-    fn get_meta(&self, id: &str) -> Result<PasteMeta>;
-    fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>>;
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta>;
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>>;
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        Ok(query.apply(self.get_all_meta().await?))
+    }
+    // Times the content was served, kept apart from the metadata
+    async fn views(&self, id: &str) -> Result<u64>;
 
     // Mutating methods
-    async fn new(&self, id: &str, key: &str) -> Result<File>;
-    fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()>;
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter>;
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()>;
+    // Count views without touching the metadata, returns the views so far
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64>;
     async fn update_size(&self, id: &str) -> Result<()>;
-    async fn update(&self, id: &str) -> Result<File>;
+    async fn update(&self, id: &str) -> Result<PasteWriter>;
     async fn delete(&self, id: &str) -> Result<()>;
     async fn cleanup(&self) -> Result<Vec<String>>; // Delete expired pastes
+    // When `cleanup` has something to do next, None if unknown or never
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(None)
+    }
+    // Check content and metadata consistency, fix them if `repair` is set
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>>;
+
+    // Only caching backends have something to say here
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+/// Where and how pastes are kept, shared by the main and migration config sections
+#[derive(Deserialize, Clone)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: Backend,
+    pub base_dir: String,
+    pub redis_address: Option<String>,
+    /// Metadata store of the filesystem backend, see `metastore::open`
+    pub meta_store: Option<String>,
+}
+
+/// Where pastes are kept
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Content on disk and metadata in sled, optionally cached in Redis
+    #[default]
+    Filesystem,
+    /// Everything in Redis
+    Redis,
 }
 
 pub struct StorageBox {
@@ -53,18 +278,40 @@ pub struct StorageBox {
 }
 
 impl StorageBox {
-    pub async fn new(base_dir: &Path, redis_addr: Option<String>) -> Result<Self> {
-        match redis_addr {
-            Some(addr) => {
-                return Ok(StorageBox {
-                    inner: Box::new(RedisCachedStorage::new(base_dir, &addr).await?),
-                });
+    pub async fn new(config: &StorageConfig) -> Result<Self> {
+        let base_dir = PathBuf::from(&config.base_dir);
+        let meta_store = config.meta_store.as_deref();
+        let inner: Box<dyn Storage> = match (config.backend, &config.redis_address) {
+            (Backend::Redis, Some(addr)) => Box::new(RedisStorage::new(addr).await?),
+            (Backend::Redis, None) => {
+                return Err(format_err!("Redis backend requires redis_address"));
             }
-            None => {
-                return Ok(StorageBox {
-                    inner: Box::new(SimpleStorage::new(base_dir)?),
-                });
+            (Backend::Filesystem, Some(addr)) => {
+                Box::new(RedisCachedStorage::new(&base_dir, meta_store, addr).await?)
             }
+            (Backend::Filesystem, None) => Box::new(SimpleStorage::new(&base_dir, meta_store)?),
+        };
+        Ok(StorageBox { inner })
+    }
+
+    /// Serve from `primary`, while mirroring all changes to `secondary`
+    pub fn dual_write(primary: StorageBox, secondary: StorageBox) -> Self {
+        StorageBox {
+            inner: Box::new(DualWriteStorage::new(primary, secondary)),
+        }
+    }
+
+    /// Keep up to `budget` bytes of `backend` pastes and metadata in memory
+    pub fn memory_cached(backend: StorageBox, budget: u64) -> Self {
+        StorageBox {
+            inner: Box::new(MemoryCachedStorage::new(backend, budget)),
+        }
+    }
+
+    /// Move pastes deleted from `backend` into `trash` instead of dropping them
+    pub fn trashed(backend: StorageBox, trash: Trash) -> Self {
+        StorageBox {
+            inner: Box::new(TrashStorage::new(backend, trash)),
         }
     }
 }
@@ -77,5 +324,15 @@ impl Clone for StorageBox {
     }
 }
 
+pub mod dualwritestorage;
+pub mod memorycachedstorage;
+pub mod meta;
+pub mod metastore;
+#[cfg(feature = "postgres")]
+pub mod postgresmetastore;
 pub mod rediscachedstorage;
+pub mod redisstorage;
 pub mod simplestorage;
+pub mod sledmetastore;
+pub mod sqlitemetastore;
+pub mod trashstorage;
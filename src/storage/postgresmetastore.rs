@@ -0,0 +1,156 @@
+use crate::storage::meta;
+use crate::storage::metastore::MetaStore;
+use crate::storage::{MetaQuery, PasteMeta};
+
+use anyhow::Result;
+use chrono::prelude::*;
+use log::error;
+use postgres::{Client, NoTls};
+use std::sync::{Arc, Mutex};
+
+// Full records are kept in `meta`, other columns only exist to be indexed. `expire_time` is
+// `PasteMeta::expires_at`, which also accounts for inactivity. View counts have a table of their
+// own, so counting a view doesn't rewrite the record.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pastes (
+    id TEXT PRIMARY KEY,
+    create_time BIGINT NOT NULL,
+    expire_time BIGINT,
+    name TEXT,
+    size BIGINT NOT NULL,
+    meta BYTEA NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pastes_create_time ON pastes (create_time);
+CREATE INDEX IF NOT EXISTS pastes_expire_time ON pastes (expire_time) WHERE expire_time IS NOT NULL;
+CREATE INDEX IF NOT EXISTS pastes_empty ON pastes (size) WHERE size = 0;
+CREATE TABLE IF NOT EXISTS views (
+    id TEXT PRIMARY KEY,
+    count BIGINT NOT NULL
+);
+";
+
+/// Metadata in a PostgreSQL database, which can be shared by several rspb instances
+#[derive(Clone)]
+pub struct PostgresMetaStore {
+    client: Arc<Mutex<Client>>,
+}
+
+impl PostgresMetaStore {
+    pub fn new(url: &str) -> Result<PostgresMetaStore> {
+        let mut client = Client::connect(url, NoTls)?;
+        client.batch_execute(SCHEMA)?;
+
+        Ok(PostgresMetaStore {
+            client: Arc::new(Mutex::new(client)),
+        })
+    }
+}
+
+impl MetaStore for PostgresMetaStore {
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.get_raw(id)?.is_some())
+    }
+
+    fn get_raw(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let mut client = self.client.lock().unwrap();
+        let row = client.query_opt("SELECT meta FROM pastes WHERE id = $1", &[&id])?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn entries(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut client = self.client.lock().unwrap();
+        let rows = client.query("SELECT id, meta FROM pastes", &[])?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn query(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        let mut client = self.client.lock().unwrap();
+        let rows = client.query(
+            "SELECT id, meta FROM pastes
+             WHERE ($1::TEXT IS NULL OR strpos(id, $1) > 0 OR strpos(name, $1) > 0)
+               AND ($2::BIGINT IS NULL OR expire_time < $2)
+             ORDER BY create_time DESC
+             LIMIT $3 OFFSET $4",
+            &[
+                &query.search,
+                &query.expire_before.map(|t| t.timestamp_millis()),
+                &query.limit.map(|l| l as i64),
+                &(query.offset.unwrap_or(0) as i64),
+            ],
+        )?;
+
+        let mut metas = Vec::new();
+        for row in rows {
+            let id: String = row.get(0);
+            let bin: Vec<u8> = row.get(1);
+            match meta::decode(&bin) {
+                Ok((meta, _version)) => metas.push((id, meta)),
+                Err(err) => error!("Bad metadata of paste {}: {}; skipped.", id, err),
+            }
+        }
+        Ok(metas)
+    }
+
+    fn expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let mut client = self.client.lock().unwrap();
+        let rows = client.query(
+            "SELECT id FROM pastes WHERE expire_time <= $1 OR size = 0",
+            &[&now.timestamp_millis()],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        let mut client = self.client.lock().unwrap();
+        let row = client.query_one("SELECT MIN(expire_time) FROM pastes", &[])?;
+        let ms: Option<i64> = row.get(0);
+        Ok(ms.and_then(|ms| Utc.timestamp_millis_opt(ms).single()))
+    }
+
+    fn insert(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        let mut client = self.client.lock().unwrap();
+        client.execute(
+            "INSERT INTO pastes (id, create_time, expire_time, name, size, meta)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (id) DO UPDATE SET
+                create_time = excluded.create_time,
+                expire_time = excluded.expire_time,
+                name = excluded.name,
+                size = excluded.size,
+                meta = excluded.meta",
+            &[
+                &id,
+                &meta.create_time.timestamp_millis(),
+                &meta.expires_at().map(|t| t.timestamp_millis()),
+                &meta.name,
+                &(meta.size as i64),
+                &meta::encode(meta)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let mut client = self.client.lock().unwrap();
+        client.execute("DELETE FROM pastes WHERE id = $1", &[&id])?;
+        client.execute("DELETE FROM views WHERE id = $1", &[&id])?;
+        Ok(())
+    }
+
+    fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        let mut client = self.client.lock().unwrap();
+        let row = client.query_one(
+            "INSERT INTO views (id, count) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET count = views.count + excluded.count
+             RETURNING count",
+            &[&id, &(count as i64)],
+        )?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    fn views(&self, id: &str) -> Result<u64> {
+        let mut client = self.client.lock().unwrap();
+        let row = client.query_opt("SELECT count FROM views WHERE id = $1", &[&id])?;
+        Ok(row.map_or(0, |row| row.get::<_, i64>(0)) as u64)
+    }
+}
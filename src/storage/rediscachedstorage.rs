@@ -2,15 +2,34 @@ use crate::storage::simplestorage::SimpleStorage;
 /// This storage backend still utilizes filesystem, but attempts to speed things up by caching small pastes
 /// and their metadata into redis.
 use crate::storage::Storage;
-use crate::storage::{PasteMeta, Response};
+use crate::storage::{Inconsistency, MetaQuery, PasteMeta, PasteWriter, Response};
 
 use anyhow::{format_err, Result};
 use async_std::path::Path;
 use async_trait::async_trait;
+use chrono::prelude::*;
 use log::{debug, info, warn};
 use redis::aio::MultiplexedConnection;
 use redis::AsyncCommands;
-use tokio::fs::File;
+use std::collections::HashMap;
+
+// Generations only need to outlive the reads that started before they were bumped
+const GENERATION_TTL: usize = 3600;
+
+// Fill the cache only if the generation of the paste is still the one taken before reading
+const FILL_CONTENT: &str = r"
+if (redis.call('GET', KEYS[1]) or '0') ~= ARGV[1] then return 0 end
+redis.call('SET', KEYS[2], ARGV[2])
+if ARGV[3] ~= '' then redis.call('EXPIREAT', KEYS[2], ARGV[3]) end
+return 1
+";
+const FILL_META: &str = r"
+if (redis.call('GET', KEYS[1]) or '0') ~= ARGV[1] then return 0 end
+redis.call('DEL', KEYS[2])
+for i = 3, #ARGV, 2 do redis.call('HSET', KEYS[2], ARGV[i], ARGV[i + 1]) end
+if ARGV[2] ~= '' then redis.call('EXPIREAT', KEYS[2], ARGV[2]) end
+return 1
+";
 
 pub struct RedisCachedStorage {
     con: MultiplexedConnection,
@@ -19,24 +38,143 @@ pub struct RedisCachedStorage {
 
 impl RedisCachedStorage {
     async fn delete_in_redis(&self, id: &str) -> Result<()> {
-        // First check if content is in redis
         let mut con = self.con.clone();
         let content_redis_location = String::from(id) + ".content";
         let highlight_redis_location = String::from(id) + ".highlight";
+        let meta_redis_location = String::from(id) + ".meta";
+        self.bump_generation(id).await;
         con.del::<&str, ()>(&content_redis_location)
             .await
             .unwrap_or(());
         con.del::<&str, ()>(&highlight_redis_location)
             .await
             .unwrap_or(()); // It can fail
+        con.del::<&str, ()>(&meta_redis_location)
+            .await
+            .unwrap_or(());
 
         Ok(())
     }
+
+    /// Take before reading from the backend, and hand to the fill. None if Redis can't tell.
+    async fn generation(&self, id: &str) -> Option<u64> {
+        let mut con = self.con.clone();
+        let generation_redis_location = String::from(id) + ".generation";
+        let generation: Option<u64> = con.get(&generation_redis_location).await.ok()?;
+        Some(generation.unwrap_or(0))
+    }
+
+    /// Keep reads that started before a change from filling in old values
+    async fn bump_generation(&self, id: &str) {
+        let mut con = self.con.clone();
+        let generation_redis_location = String::from(id) + ".generation";
+        let res = redis::pipe()
+            .atomic()
+            .incr(&generation_redis_location, 1)
+            .ignore()
+            .expire(&generation_redis_location, GENERATION_TTL)
+            .ignore()
+            .query_async::<_, ()>(&mut con)
+            .await;
+        if let Err(err) = res {
+            warn!("Failed to write to Redis: {}", err);
+        }
+    }
+
+    /// Cache content read from the backend, unless the paste changed in the meantime
+    async fn fill_content(&self, id: &str, content: &[u8], generation: u64) {
+        let mut con = self.con.clone();
+        let content_redis_location = String::from(id) + ".content";
+        let generation_redis_location = String::from(id) + ".generation";
+        // Expire together with the paste
+        let expire = match self.get_meta(id).await.ok().and_then(|m| m.expires_at()) {
+            Some(t) => t.timestamp().max(0).to_string(),
+            None => String::new(),
+        };
+        let res = redis::Script::new(FILL_CONTENT)
+            .key(&generation_redis_location)
+            .key(&content_redis_location)
+            .arg(generation)
+            .arg(content)
+            .arg(expire)
+            .invoke_async::<_, ()>(&mut con)
+            .await;
+        if let Err(err) = res {
+            warn!("Failed to write to Redis: {}", err);
+        }
+    }
+
+    /// Write metadata into a Redis hash, one JSON encoded field per PasteMeta field. With a
+    /// `generation`, only if the paste didn't change since it was taken.
+    async fn cache_meta(&self, id: &str, meta: &PasteMeta, generation: Option<u64>) {
+        let mut con = self.con.clone();
+        let meta_redis_location = String::from(id) + ".meta";
+        let generation_redis_location = String::from(id) + ".generation";
+        let fields: Vec<(String, String)> = match serde_json::to_value(meta) {
+            Ok(serde_json::Value::Object(map)) => map
+                .into_iter()
+                .map(|(k, v)| (k, v.to_string()))
+                .collect(),
+            _ => return,
+        };
+        // Let Redis forget about it when the paste expires
+        let expire = meta.expires_at().map(|t| t.timestamp().max(0));
+
+        let res = match generation {
+            Some(generation) => {
+                let script = redis::Script::new(FILL_META);
+                let mut invocation = script.key(&generation_redis_location);
+                invocation
+                    .key(&meta_redis_location)
+                    .arg(generation)
+                    .arg(expire.map(|t| t.to_string()).unwrap_or_default());
+                for (k, v) in &fields {
+                    invocation.arg(k).arg(v);
+                }
+                invocation.invoke_async::<_, ()>(&mut con).await
+            }
+            None => {
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .del(&meta_redis_location)
+                    .ignore()
+                    .hset_multiple(&meta_redis_location, &fields)
+                    .ignore();
+                if let Some(t) = expire {
+                    pipe.expire_at(&meta_redis_location, t as usize).ignore();
+                }
+                pipe.query_async::<_, ()>(&mut con).await
+            }
+        };
+        if let Err(err) = res {
+            warn!("Failed to write metadata to Redis: {}", err);
+        }
+    }
+
+    async fn cached_meta(&self, id: &str) -> Option<PasteMeta> {
+        let mut con = self.con.clone();
+        let meta_redis_location = String::from(id) + ".meta";
+        let fields: HashMap<String, String> = con.hgetall(&meta_redis_location).await.ok()?;
+        if fields.is_empty() {
+            return None;
+        }
+
+        let mut map = serde_json::Map::new();
+        for (k, v) in fields {
+            map.insert(k, serde_json::from_str(&v).ok()?);
+        }
+        // Entries written by an older PasteMeta may miss fields, treat them as a miss
+        serde_json::from_value(serde_json::Value::Object(map)).ok()
+    }
 }
 
 impl RedisCachedStorage {
-    pub async fn new(base: &Path, redis_addr: &str) -> Result<RedisCachedStorage> {
-        let backend = SimpleStorage::new(base)?;
+    pub async fn new(
+        base: &Path,
+        meta_store: Option<&str>,
+        redis_addr: &str,
+    ) -> Result<RedisCachedStorage> {
+        let backend = SimpleStorage::new(base, meta_store)?;
         let client = redis::Client::open(redis_addr)?;
         info!("Connecting to Redis instance on {}", redis_addr);
         let con = client
@@ -61,8 +199,13 @@ impl Clone for RedisCachedStorage {
 
 #[async_trait]
 impl Storage for RedisCachedStorage {
-    fn exists(&self, id: &str) -> Result<bool> {
-        self.backend.exists(id)
+    async fn exists(&self, id: &str) -> Result<bool> {
+        let mut con = self.con.clone();
+        let meta_redis_location = String::from(id) + ".meta";
+        if let Ok(true) = con.exists(&meta_redis_location).await {
+            return Ok(true);
+        }
+        self.backend.exists(id).await
     }
 
     async fn get(&self, id: &str) -> Result<Response> {
@@ -86,18 +229,14 @@ impl Storage for RedisCachedStorage {
             };
             return Ok(Response::Content(result));
         } else {
+            let generation = self.generation(id).await;
             let result = self.backend.get(id).await?;
             match result {
                 Response::Content(vec) => {
                     debug!("Paste {} miss cache, attemping to add to cache...", id);
-                    // Try to write to the redis cache
-                    let res = con
-                        .set::<&str, &[u8], ()>(&content_redis_location, &vec)
-                        .await;
-                    match res {
-                        Ok(_ok) => (),
-                        Err(err) => warn!("Failed to write to Redis: {}", err.to_string()),
-                    };
+                    if let Some(generation) = generation {
+                        self.fill_content(id, &vec, generation).await;
+                    }
 
                     return Ok(Response::Content(vec));
                 }
@@ -109,30 +248,60 @@ impl Storage for RedisCachedStorage {
         }
     }
 
-    fn get_meta(&self, id: &str) -> Result<PasteMeta> {
-        self.backend.get_meta(id)
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        if let Some(meta) = self.cached_meta(id).await {
+            debug!("Metadata of paste {} hit cache.", id);
+            return Ok(meta);
+        }
+
+        let generation = self.generation(id).await;
+        let meta = self.backend.get_meta(id).await?;
+        if let Some(generation) = generation {
+            self.cache_meta(id, &meta, Some(generation)).await;
+        }
+        Ok(meta)
+    }
+
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.get_all_meta().await
+    }
+
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.list_meta(query).await
     }
 
-    fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
-        self.backend.get_all_meta()
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.backend.views(id).await
     }
 
-    async fn new(&self, id: &str, key: &str) -> Result<File> {
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        self.delete_in_redis(id).await?;
         self.backend.new(id, key).await
     }
 
-    fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
-        self.backend.set_meta(id, meta)
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.backend.set_meta(id, meta).await?;
+        self.bump_generation(id).await;
+        self.cache_meta(id, meta, None).await;
+        Ok(())
+    }
+
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        self.backend.add_views(id, count).await
     }
 
     async fn update_size(&self, id: &str) -> Result<()> {
-        self.backend.update_size(id).await
+        // Content read while it was being written may have been cached
+        self.delete_in_redis(id).await?;
+        self.backend.update_size(id).await?;
+        let meta = self.backend.get_meta(id).await?;
+        self.cache_meta(id, &meta, None).await;
+        Ok(())
     }
 
-    async fn update(&self, id: &str) -> Result<File> {
-        let res = self.backend.update(id).await?;
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
         self.delete_in_redis(id).await?;
-        Ok(res)
+        self.backend.update(id).await
     }
 
     async fn delete(&self, id: &str) -> Result<()> {
@@ -151,4 +320,21 @@ impl Storage for RedisCachedStorage {
 
         Ok(deleted)
     }
+
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.backend.next_expiry().await
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        let problems = self.backend.fsck(repair).await?;
+
+        // Cached content may be stale for repaired pastes
+        if repair {
+            for problem in &problems {
+                self.delete_in_redis(problem.id()).await?;
+            }
+        }
+
+        Ok(problems)
+    }
 }
@@ -0,0 +1,427 @@
+//! This storage backend keeps everything in Redis, nothing touches the filesystem.
+//!
+//! Metadata lives in `paste:{id}:meta`, and content is split into `paste:{id}:chunk:{n}` keys so
+//! large pastes can still be streamed. Views are counted in `paste:{id}:views`. Expire time
+//! (including inactivity, see `PasteMeta::expires_at`) is mapped to native key expiry. `cleanup`
+//! only drops what Redis expired from the index, so the ids are reported, and pastes whose content
+//! never arrived.
+use crate::storage::meta;
+use crate::storage::{Inconsistency, PasteMeta, PasteWriter, Response, Storage};
+
+use anyhow::{format_err, Result};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use chrono::prelude::*;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use log::{debug, info};
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, RedisResult};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+
+// In bytes
+const CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_STREAM_FILE_SIZE: u64 = 5 * 1024 * 1024;
+// Set of all paste ids, so they can be listed without scanning the keyspace
+const INDEX_KEY: &str = "paste:index";
+// Pastes still without content after this long were abandoned while being written
+const EMPTY_GRACE_MINUTES: i64 = 60;
+
+fn meta_key(id: &str) -> String {
+    format!("paste:{}:meta", id)
+}
+
+fn views_key(id: &str) -> String {
+    format!("paste:{}:views", id)
+}
+
+fn chunk_key(id: &str, n: usize) -> String {
+    format!("paste:{}:chunk:{}", id, n)
+}
+
+#[derive(Clone)]
+pub struct RedisStorage {
+    con: MultiplexedConnection,
+}
+
+impl RedisStorage {
+    pub async fn new(redis_addr: &str) -> Result<RedisStorage> {
+        let client = redis::Client::open(redis_addr)?;
+        info!("Connecting to Redis instance on {}", redis_addr);
+        let con = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|err| format_err!("Failed to establish Redis connection: {}", err))?;
+
+        Ok(RedisStorage { con })
+    }
+
+    async fn chunk_count(&self, id: &str) -> Result<usize> {
+        let mut con = self.con.clone();
+        let mut n = 0;
+        while con.exists(chunk_key(id, n)).await? {
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    async fn content_size(&self, id: &str) -> Result<u64> {
+        let mut con = self.con.clone();
+        let mut size = 0;
+        for n in 0..self.chunk_count(id).await? {
+            let len: u64 = con.strlen(chunk_key(id, n)).await?;
+            size += len;
+        }
+        Ok(size)
+    }
+
+    async fn delete_chunks(&self, id: &str) -> Result<()> {
+        let mut con = self.con.clone();
+        let keys: Vec<String> = (0..self.chunk_count(id).await?)
+            .map(|n| chunk_key(id, n))
+            .collect();
+        if !keys.is_empty() {
+            con.del::<_, ()>(keys).await?;
+        }
+        Ok(())
+    }
+
+    fn writer(&self, id: &str) -> PasteWriter {
+        Box::new(RedisWriter {
+            con: self.con.clone(),
+            id: id.to_string(),
+            next_chunk: 0,
+            buf: Vec::new(),
+            pending: None,
+        })
+    }
+}
+
+/// Buffers content and writes it out one chunk key at a time
+struct RedisWriter {
+    con: MultiplexedConnection,
+    id: String,
+    next_chunk: usize,
+    buf: Vec<u8>,
+    pending: Option<BoxFuture<'static, RedisResult<()>>>,
+}
+
+impl RedisWriter {
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(fut) = self.pending.as_mut() {
+            match fut.as_mut().poll(cx) {
+                Poll::Ready(res) => {
+                    self.pending = None;
+                    res.map_err(|err| io::Error::other(err.to_string()))?;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn write_chunk(&mut self) {
+        let mut con = self.con.clone();
+        let key = chunk_key(&self.id, self.next_chunk);
+        let chunk = std::mem::take(&mut self.buf);
+        self.next_chunk += 1;
+        self.pending = Some(Box::pin(async move { con.set(key, chunk).await }));
+    }
+}
+
+impl AsyncWrite for RedisWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            // Wait for the previous chunk before starting the next one
+            Poll::Pending if this.buf.len() >= CHUNK_SIZE => return Poll::Pending,
+            Poll::Pending => (),
+        }
+        if this.buf.len() >= CHUNK_SIZE {
+            this.write_chunk();
+            if let Poll::Ready(Err(err)) = this.poll_pending(cx) {
+                return Poll::Ready(Err(err));
+            }
+        }
+
+        let len = buf.len().min(CHUNK_SIZE - this.buf.len());
+        this.buf.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match this.poll_pending(cx) {
+                Poll::Ready(Ok(())) => (),
+                other => return other,
+            }
+            if this.buf.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.write_chunk();
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn exists(&self, id: &str) -> Result<bool> {
+        let mut con = self.con.clone();
+        Ok(con.exists(meta_key(id)).await?)
+    }
+
+    async fn get(&self, id: &str) -> Result<Response> {
+        let size = self.get_meta(id).await?.size;
+        let chunks = self.chunk_count(id).await?;
+        if chunks == 0 {
+            return Err(format_err!("Internal Error"));
+        }
+
+        let mut con = self.con.clone();
+        if size < MAX_STREAM_FILE_SIZE {
+            let keys: Vec<String> = (0..chunks).map(|n| chunk_key(id, n)).collect();
+            let parts: Vec<Vec<u8>> = redis::cmd("MGET")
+                .arg(keys)
+                .query_async(&mut con)
+                .await?;
+            Ok(Response::Content(parts.concat()))
+        } else {
+            let id = id.to_string();
+            let stream = futures::stream::iter(0..chunks).then(move |n| {
+                let mut con = con.clone();
+                let key = chunk_key(&id, n);
+                async move {
+                    let part: Vec<u8> = con
+                        .get(key)
+                        .await
+                        .map_err(|err| io::Error::other(err.to_string()))?;
+                    Ok(BytesMut::from(&part[..]))
+                }
+            });
+            Ok(Response::Stream(Box::pin(stream)))
+        }
+    }
+
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        let mut con = self.con.clone();
+        let bin: Option<Vec<u8>> = con.get(meta_key(id)).await?;
+        let bin = match bin {
+            Some(bin) => bin,
+            None => return Err(format_err!("Paste not found".to_string())),
+        };
+        let meta = meta::decode(&bin)?.0;
+        // Upgraded records count their views apart
+        if let Some(views) = meta::legacy_views(&bin) {
+            self.set_meta(id, &meta).await?;
+            self.add_views(id, views).await?;
+        }
+        Ok(meta)
+    }
+
+    async fn views(&self, id: &str) -> Result<u64> {
+        let mut con = self.con.clone();
+        let views: Option<u64> = con.get(views_key(id)).await?;
+        Ok(views.unwrap_or(0))
+    }
+
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        let mut con = self.con.clone();
+        let ids: Vec<String> = con.smembers(INDEX_KEY).await?;
+        let mut metas = Vec::new();
+        for id in ids {
+            let bin: Option<Vec<u8>> = con.get(meta_key(&id)).await?;
+            // Expired by Redis if missing, `cleanup` takes it off the index
+            if let Some(bin) = bin {
+                metas.push((id, meta::decode(&bin)?.0));
+            }
+        }
+
+        Ok(metas)
+    }
+
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        if self.exists(id).await? {
+            return Err(format_err!("A paste with this id already exists"));
+        }
+
+        // Clear leftovers of an expired paste with the same id
+        self.delete_chunks(id).await?;
+        self.con.clone().del::<_, ()>(views_key(id)).await?;
+        self.set_meta(
+            id,
+            &PasteMeta {
+                create_time: Utc::now(),
+                expire_time: None,
+                atime: None,
+                name: None,
+                size: 0, // Set it to 0 for now
+                key: key.to_string(),
+                expire_after_inactive: None,
+                hold: None,
+                moderation: None,
+                scan: None,
+                revision: 1,
+                modify_time: None,
+            },
+        )
+        .await?;
+
+        Ok(self.writer(id))
+    }
+
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        let mut con = self.con.clone();
+        let mut keys: Vec<String> = (0..self.chunk_count(id).await?)
+            .map(|n| chunk_key(id, n))
+            .collect();
+        keys.push(meta_key(id));
+        keys.push(views_key(id));
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set(meta_key(id), meta::encode(meta)?)
+            .ignore()
+            .sadd(INDEX_KEY, id)
+            .ignore();
+        for key in &keys {
+            match meta.expires_at() {
+                Some(t) => pipe.expire_at(key, t.timestamp().max(0) as usize).ignore(),
+                None => pipe.persist(key).ignore(),
+            };
+        }
+        pipe.query_async::<_, ()>(&mut con).await?;
+
+        Ok(())
+    }
+
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        let mut con = self.con.clone();
+        let (views, ttl): (u64, i64) = redis::pipe()
+            .atomic()
+            .incr(views_key(id), count)
+            .pttl(meta_key(id))
+            .query_async(&mut con)
+            .await?;
+        // Expires with the paste
+        if ttl > 0 {
+            con.pexpire::<_, ()>(views_key(id), ttl as usize).await?;
+        }
+        Ok(views)
+    }
+
+    async fn update_size(&self, id: &str) -> Result<()> {
+        let mut meta = self.get_meta(id).await?;
+        meta.size = self.content_size(id).await?;
+        self.set_meta(id, &meta).await
+    }
+
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
+        self.delete_chunks(id).await?;
+        Ok(self.writer(id))
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        let mut con = self.con.clone();
+        self.delete_chunks(id).await?;
+        con.del::<_, ()>(&[meta_key(id), views_key(id)]).await?;
+        con.srem::<_, _, ()>(INDEX_KEY, id).await?;
+
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        debug!("Begin deleting expired pastes...");
+        let mut con = self.con.clone();
+        let mut deleted = Vec::new();
+        let abandoned = Utc::now() - chrono::Duration::minutes(EMPTY_GRACE_MINUTES);
+
+        let ids: Vec<String> = con.smembers(INDEX_KEY).await?;
+        for id in ids {
+            let bin: Option<Vec<u8>> = con.get(meta_key(&id)).await?;
+            let expired = match bin {
+                // Redis expired it already, only the index entry is left
+                None => true,
+                Some(bin) => {
+                    let meta = meta::decode(&bin)?.0;
+                    meta.size == 0 && meta.create_time < abandoned
+                }
+            };
+            if expired {
+                self.delete(&id).await?;
+                deleted.push(id);
+            }
+        }
+
+        debug!("Finish deleting expired pastes.");
+        Ok(deleted)
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        debug!("Begin checking storage consistency...");
+        let mut con = self.con.clone();
+        let mut problems = Vec::new();
+
+        for (id, mut meta) in self.get_all_meta().await? {
+            if self.chunk_count(&id).await? == 0 {
+                if repair {
+                    self.delete(&id).await?;
+                }
+                problems.push(Inconsistency::MissingContent { id });
+                continue;
+            }
+
+            let actual = self.content_size(&id).await?;
+            if actual != meta.size {
+                problems.push(Inconsistency::WrongSize {
+                    id: id.clone(),
+                    recorded: meta.size,
+                    actual,
+                });
+                if repair {
+                    meta.size = actual;
+                    self.set_meta(&id, &meta).await?;
+                }
+            }
+        }
+
+        // Content without metadata
+        let first_chunks: Vec<String> = {
+            let mut iter = con.scan_match::<_, String>("paste:*:chunk:0").await?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        for key in first_chunks {
+            let id = key
+                .trim_start_matches("paste:")
+                .trim_end_matches(":chunk:0")
+                .to_string();
+            if self.exists(&id).await? {
+                continue;
+            }
+            if repair {
+                self.delete_chunks(&id).await?;
+            }
+            problems.push(Inconsistency::OrphanFile { id });
+        }
+
+        debug!("Finish checking storage consistency.");
+        Ok(problems)
+    }
+}
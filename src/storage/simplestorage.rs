@@ -1,11 +1,14 @@
+use crate::api::new::is_paste_id;
 use crate::skip_fail;
-use crate::storage::{PasteMeta, Response, Storage};
+use crate::storage::meta::{self, CURRENT_VERSION};
+use crate::storage::metastore::{self, MetaStore};
+use crate::storage::{Inconsistency, MetaQuery, PasteMeta, PasteWriter, Response, Storage};
 
 use anyhow::{format_err, Result};
 use async_std::path::{Path, PathBuf};
 use async_trait::async_trait;
 use chrono::prelude::*;
-use log::{debug, error};
+use log::{debug, error, warn};
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
@@ -13,30 +16,60 @@ use tokio_util::codec::{BytesCodec, FramedRead};
 
 // In bytes
 const MAX_STREAM_FILE_SIZE: u64 = 5 * 1024 * 1024;
+const QUARANTINE_DIR: &str = "quarantine";
 
 #[derive(Clone)]
 pub struct SimpleStorage {
     base_dir: PathBuf,
-    db: sled::Db,
+    meta: Box<dyn MetaStore>,
 }
 
 impl SimpleStorage {
-    pub fn new(base: &Path) -> Result<SimpleStorage> {
-        let mut db_path = PathBuf::from(base);
-        db_path.push("pastebin.db");
-        let db = sled::open(&db_path)?;
+    pub fn new(base: &Path, meta_store: Option<&str>) -> Result<SimpleStorage> {
+        let meta = metastore::open(meta_store, base)?;
 
         Ok(SimpleStorage {
             base_dir: PathBuf::from(base),
-            db,
+            meta,
         })
     }
+
+    /// Decode a stored record, upgrading it if it's in an old format
+    fn decode(&self, id: &str, bin: &[u8]) -> Result<PasteMeta> {
+        let (meta, version) = meta::decode(bin)?;
+        if version != CURRENT_VERSION {
+            debug!("Upgrading metadata of paste {} from version {}.", id, version);
+            self.meta.insert(id, &meta)?;
+            if let Some(views) = meta::legacy_views(bin) {
+                self.meta.add_views(id, views)?;
+            }
+        }
+
+        Ok(meta)
+    }
+
+    /// Move whatever is left of a broken paste out of the way, so it can be inspected later
+    async fn quarantine(&self, id: &str, raw_meta: Option<&[u8]>) -> Result<()> {
+        let quarantine_dir = self.base_dir.join(QUARANTINE_DIR);
+        fs::create_dir_all(&quarantine_dir).await?;
+
+        let paste_path = self.base_dir.join(id);
+        if paste_path.is_file().await {
+            fs::rename(&paste_path, quarantine_dir.join(id)).await?;
+        }
+        if let Some(bin) = raw_meta {
+            fs::write(quarantine_dir.join(id.to_owned() + ".meta"), bin).await?;
+        }
+        self.meta.remove(id)?;
+
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Storage for SimpleStorage {
-    fn exists(&self, id: &str) -> Result<bool> {
-        Ok(self.db.contains_key(id)?)
+    async fn exists(&self, id: &str) -> Result<bool> {
+        self.meta.contains(id)
     }
 
     async fn get(&self, id: &str) -> Result<Response> {
@@ -50,41 +83,44 @@ impl Storage for SimpleStorage {
         let mut file = File::open(&paste_path).await?;
 
         // Check file size
-        if self.get_meta(id)?.size < MAX_STREAM_FILE_SIZE {
+        if self.get_meta(id).await?.size < MAX_STREAM_FILE_SIZE {
             let mut content: Vec<u8> = Vec::new();
             file.read_to_end(&mut content).await?;
 
             Ok(Response::Content(content))
         } else {
             let stream = FramedRead::new(file, BytesCodec::new());
-            Ok(Response::Stream(stream))
+            Ok(Response::Stream(Box::pin(stream)))
         }
     }
 
-    fn get_meta(&self, id: &str) -> Result<PasteMeta> {
-        let meta: PasteMeta = match self.db.get(&id)? {
-            Some(bin) => bincode::deserialize(&bin)?,
-            None => {
-                return Err(format_err!("Paste not found".to_string()));
-            }
-        };
-
-        Ok(meta)
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        match self.meta.get_raw(id)? {
+            Some(bin) => self.decode(id, &bin),
+            None => Err(format_err!("Paste not found".to_string())),
+        }
     }
 
-    fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
         let mut metas = Vec::new();
-        for id_u8 in self.db.iter().keys() {
-            let id = String::from_utf8(id_u8?.to_vec())?;
-            let meta = self.get_meta(&id)?;
+        for (id, bin) in self.meta.entries()? {
+            let meta = self.decode(&id, &bin)?;
             metas.push((id, meta));
         }
 
         Ok(metas)
     }
 
-    async fn new(&self, id: &str, key: &str) -> Result<File> {
-        if self.exists(id)? {
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.meta.query(query)
+    }
+
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.meta.views(id)
+    }
+
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        if self.exists(id).await? {
             return Err(format_err!("A paste with this id already exists"));
         }
 
@@ -102,31 +138,39 @@ impl Storage for SimpleStorage {
                 name: None,
                 size: 0, // Set it to 0 for now
                 key: key.to_string(),
+                expire_after_inactive: None,
+                hold: None,
+                moderation: None,
+                scan: None,
+                revision: 1,
+                modify_time: None,
             },
-        )?;
+        )
+        .await?;
 
-        Ok(content_file)
+        Ok(Box::new(content_file))
     }
 
-    fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
-        let meta_bin = bincode::serialize(&meta)?;
-        self.db.insert(&id, meta_bin)?;
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.meta.insert(id, meta)
+    }
 
-        Ok(())
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        self.meta.add_views(id, count)
     }
 
     async fn update_size(&self, id: &str) -> Result<()> {
-        let mut meta = self.get_meta(id)?;
+        let mut meta = self.get_meta(id).await?;
 
         let paste_path = self.base_dir.join(id);
         let file_meta = fs::metadata(&paste_path).await?;
 
         meta.size = file_meta.len();
-        self.set_meta(id, &meta)?;
+        self.set_meta(id, &meta).await?;
         Ok(())
     }
 
-    async fn update(&self, id: &str) -> Result<File> {
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
         let path = self.base_dir.join(id);
         fs::remove_file(&path).await?;
         let highlight_path = self.base_dir.join(id.to_owned() + ".highlight");
@@ -134,7 +178,7 @@ impl Storage for SimpleStorage {
             fs::remove_file(&highlight_path).await?;
         }
         let f = File::create(&path).await?;
-        Ok(f)
+        Ok(Box::new(f))
     }
 
     async fn delete(&self, id: &str) -> Result<()> {
@@ -143,7 +187,7 @@ impl Storage for SimpleStorage {
 
         // Actually delete them
         fs::remove_file(paste_path).await?;
-        self.db.remove(&id)?;
+        self.meta.remove(id)?;
 
         Ok(())
     }
@@ -152,26 +196,115 @@ impl Storage for SimpleStorage {
         debug!("Begin deleting expired pastes...");
         let mut deleted = Vec::new();
 
-        // Go though all expire times
-        for id_u8 in self.db.iter().keys() {
-            let id = skip_fail!(String::from_utf8(skip_fail!(id_u8).to_vec()));
-            let meta = skip_fail!(self.get_meta(&id));
-            if let Some(exp_time) = meta.expire_time {
-                if Utc::now() >= exp_time {
-                    // It's expired, delete it!
-                    skip_fail!(self.delete(&id).await);
-                    deleted.push(id.clone());
+        // Expired and empty pastes
+        for id in self.meta.expired(Utc::now())? {
+            skip_fail!(self.delete(&id).await);
+            deleted.push(id);
+        }
+
+        debug!("Finish deleting expired pastes.");
+        Ok(deleted)
+    }
+
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.meta.next_expiry()
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        debug!("Begin checking storage consistency...");
+        let mut problems = Vec::new();
+
+        // Check every metadata entry against its content file
+        for (id, bin) in self.meta.entries()? {
+            let mut meta = match meta::decode(&bin) {
+                Ok((meta, _version)) => meta,
+                Err(err) => {
+                    if repair {
+                        self.quarantine(&id, Some(&bin)).await?;
+                    }
+                    problems.push(Inconsistency::Unreadable {
+                        id,
+                        reason: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            let paste_path = self.base_dir.join(&id);
+            if !paste_path.exists().await {
+                if repair {
+                    self.meta.remove(&id)?;
+                }
+                problems.push(Inconsistency::MissingContent { id });
+                continue;
+            }
+
+            let actual = match fs::metadata(&paste_path).await {
+                Ok(m) if m.is_file() => m.len(),
+                Ok(_m) => {
+                    if repair {
+                        self.quarantine(&id, Some(&bin)).await?;
+                    }
+                    problems.push(Inconsistency::Unreadable {
+                        id,
+                        reason: "Content is not a regular file".to_string(),
+                    });
+                    continue;
+                }
+                Err(err) => {
+                    if repair {
+                        self.quarantine(&id, Some(&bin)).await?;
+                    }
+                    problems.push(Inconsistency::Unreadable {
+                        id,
+                        reason: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if actual != meta.size {
+                problems.push(Inconsistency::WrongSize {
+                    id: id.clone(),
+                    recorded: meta.size,
+                    actual,
+                });
+                if repair {
+                    meta.size = actual;
+                    self.set_meta(&id, &meta).await?;
+                }
+            }
+        }
+
+        // Then look for content files nobody knows about
+        let mut entries = fs::read_dir(&self.base_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    warn!("Skipping file with non UTF-8 name {:?}", name);
+                    continue;
                 }
+            };
+            // Highlight files belong to their paste. Anything not named like a paste, like
+            // databases or logs kept next to the pastes, is none of our business.
+            let id = name.strip_suffix(".highlight").unwrap_or(&name);
+            if !is_paste_id(id)
+                || entry.file_type().await?.is_dir()
+                || self.meta.owns(&entry.path())
+            {
+                continue;
+            }
+            if self.exists(id).await? {
+                continue;
             }
-            // Delete empty paste
-            if meta.size == 0 {
-                // Delete empty paste
-                skip_fail!(self.delete(&id).await);
-                deleted.push(id);
+
+            if repair {
+                fs::remove_file(entry.path()).await?;
             }
+            problems.push(Inconsistency::OrphanFile { id: name });
         }
 
-        debug!("Finish deleting expired pastes.");
-        Ok(deleted)
+        debug!("Finish checking storage consistency.");
+        Ok(problems)
     }
 }
@@ -0,0 +1,208 @@
+use crate::storage::meta;
+use crate::storage::metastore::MetaStore;
+use crate::storage::{MetaQuery, PasteMeta};
+
+use anyhow::Result;
+use async_std::path::{Path, PathBuf};
+use chrono::prelude::*;
+use log::{error, info};
+
+pub const DB_DIR: &str = "pastebin.db";
+// Keys are big-endian `PasteMeta::expires_at` in milliseconds followed by paste id, so they sort by time
+const EXPIRY_TREE: &str = "expiry";
+// Ids of pastes without content
+const EMPTY_TREE: &str = "empty";
+// Big-endian view count by paste id
+const VIEWS_TREE: &str = "views";
+
+fn expiry_key(time: DateTime<Utc>, id: &str) -> Vec<u8> {
+    let mut key = time.timestamp_millis().max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn count(bin: Option<&[u8]>) -> u64 {
+    let mut count = [0u8; 8];
+    if let Some(bin) = bin.filter(|bin| bin.len() == 8) {
+        count.copy_from_slice(bin);
+    }
+    u64::from_be_bytes(count)
+}
+
+fn expiry_time(key: &[u8]) -> Option<DateTime<Utc>> {
+    let mut ts = [0u8; 8];
+    ts.copy_from_slice(key.get(..8)?);
+    Utc.timestamp_millis_opt(i64::from_be_bytes(ts)).single()
+}
+
+/// Metadata in an embedded sled database, with secondary trees indexing expiry and empty pastes,
+/// and one counting views. Other lookups iterate everything.
+#[derive(Clone)]
+pub struct SledMetaStore {
+    db: sled::Db,
+    expiry: sled::Tree,
+    empty: sled::Tree,
+    views: sled::Tree,
+}
+
+impl SledMetaStore {
+    pub fn new(base: &Path) -> Result<SledMetaStore> {
+        let mut db_path = PathBuf::from(base);
+        db_path.push(DB_DIR);
+        let db = sled::open(&db_path)?;
+
+        let indexed = db
+            .tree_names()
+            .iter()
+            .any(|name| name == EXPIRY_TREE.as_bytes());
+        let store = SledMetaStore {
+            expiry: db.open_tree(EXPIRY_TREE)?,
+            empty: db.open_tree(EMPTY_TREE)?,
+            views: db.open_tree(VIEWS_TREE)?,
+            db,
+        };
+        // Databases from older versions have no index yet
+        if !indexed {
+            info!("Building expiry index...");
+            for (id, meta) in store.decoded()? {
+                store.index(&id, &meta)?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn index(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        if let Some(t) = meta.expires_at() {
+            self.expiry.insert(expiry_key(t, id), &[])?;
+        }
+        if meta.size == 0 {
+            self.empty.insert(id, &[])?;
+        }
+        Ok(())
+    }
+
+    fn unindex(&self, id: &str) -> Result<()> {
+        if let Some(bin) = self.db.get(id)? {
+            if let Ok((meta, _version)) = meta::decode(&bin) {
+                if let Some(t) = meta.expires_at() {
+                    self.expiry.remove(expiry_key(t, id))?;
+                }
+            }
+        }
+        self.empty.remove(id)?;
+        Ok(())
+    }
+
+    fn decoded(&self) -> Result<Vec<(String, PasteMeta)>> {
+        let mut metas = Vec::new();
+        for (id, bin) in self.entries()? {
+            match meta::decode(&bin) {
+                Ok((meta, _version)) => metas.push((id, meta)),
+                Err(err) => error!("Bad metadata of paste {}: {}; skipped.", id, err),
+            }
+        }
+        Ok(metas)
+    }
+}
+
+impl MetaStore for SledMetaStore {
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.db.contains_key(id)?)
+    }
+
+    fn get_raw(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(id)?.map(|bin| bin.to_vec()))
+    }
+
+    fn entries(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        for entry in self.db.iter() {
+            let (id_u8, bin) = entry?;
+            let id = String::from_utf8_lossy(&id_u8).to_string();
+            entries.push((id, bin.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn query(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        // Only pastes in the expiry index can match
+        if let Some(t) = query.expire_before {
+            let end = t.timestamp_millis().max(0).to_be_bytes();
+            let mut metas = Vec::new();
+            for entry in self.expiry.range(..&end[..]) {
+                let (key, _) = entry?;
+                let id = String::from_utf8_lossy(&key[8..]).to_string();
+                if let Some(Ok((meta, _version))) = self.get_raw(&id)?.map(|bin| meta::decode(&bin))
+                {
+                    // Skip stale entries, expired() cleans them up
+                    if meta.expires_at().map(|t| expiry_key(t, &id)).as_deref() == Some(&key) {
+                        metas.push((id, meta));
+                    }
+                }
+            }
+            return Ok(query.apply(metas));
+        }
+
+        Ok(query.apply(self.decoded()?))
+    }
+
+    fn expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let mut expired = Vec::new();
+        let end = (now.timestamp_millis().max(0) + 1).to_be_bytes();
+        for entry in self.expiry.range(..&end[..]) {
+            let (key, _) = entry?;
+            let id = String::from_utf8_lossy(&key[8..]).to_string();
+            // Entries can outlive their paste after a crash, check before trusting them
+            match self.get_raw(&id)?.map(|bin| meta::decode(&bin)) {
+                Some(Ok((meta, _version)))
+                    if meta.expires_at().map(|t| expiry_key(t, &id)).as_deref() == Some(&key) =>
+                {
+                    expired.push(id)
+                }
+                _ => {
+                    self.expiry.remove(key)?;
+                }
+            }
+        }
+        for entry in self.empty.iter() {
+            let (id_u8, _) = entry?;
+            let id = String::from_utf8_lossy(&id_u8).to_string();
+            match self.get_raw(&id)?.map(|bin| meta::decode(&bin)) {
+                Some(Ok((meta, _version))) if meta.size == 0 => expired.push(id),
+                _ => {
+                    self.empty.remove(id_u8)?;
+                }
+            }
+        }
+        Ok(expired)
+    }
+
+    fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.expiry.first()?.and_then(|(key, _)| expiry_time(&key)))
+    }
+
+    fn insert(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.unindex(id)?;
+        self.db.insert(id, meta::encode(meta)?)?;
+        self.index(id, meta)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.unindex(id)?;
+        self.db.remove(id)?;
+        self.views.remove(id)?;
+        Ok(())
+    }
+
+    fn add_views(&self, id: &str, n: u64) -> Result<u64> {
+        let views = self
+            .views
+            .update_and_fetch(id, |old| Some((count(old) + n).to_be_bytes().to_vec()))?;
+        Ok(count(views.as_deref()))
+    }
+
+    fn views(&self, id: &str) -> Result<u64> {
+        Ok(count(self.views.get(id)?.as_deref()))
+    }
+}
@@ -0,0 +1,188 @@
+use crate::storage::meta;
+use crate::storage::metastore::MetaStore;
+use crate::storage::{MetaQuery, PasteMeta};
+
+use anyhow::Result;
+use chrono::prelude::*;
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Full records are kept in `meta`, other columns only exist to be indexed. `expire_time` is
+// `PasteMeta::expires_at`, which also accounts for inactivity. View counts have a table of their
+// own, so counting a view doesn't rewrite the record.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pastes (
+    id TEXT PRIMARY KEY,
+    create_time INTEGER NOT NULL,
+    expire_time INTEGER,
+    name TEXT,
+    size INTEGER NOT NULL,
+    meta BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pastes_create_time ON pastes (create_time);
+CREATE INDEX IF NOT EXISTS pastes_expire_time ON pastes (expire_time) WHERE expire_time IS NOT NULL;
+CREATE INDEX IF NOT EXISTS pastes_empty ON pastes (size) WHERE size = 0;
+CREATE TABLE IF NOT EXISTS views (
+    id TEXT PRIMARY KEY,
+    count INTEGER NOT NULL
+);
+";
+
+/// Metadata in a SQLite database
+#[derive(Clone)]
+pub struct SqliteMetaStore {
+    con: Arc<Mutex<Connection>>,
+    path: PathBuf,
+}
+
+impl SqliteMetaStore {
+    pub fn new(path: &str) -> Result<SqliteMetaStore> {
+        let con = Connection::open(path)?;
+        con.execute_batch(SCHEMA)?;
+
+        // Compared against other paths in `owns`, however the database was configured
+        let path = std::fs::canonicalize(path)?;
+        Ok(SqliteMetaStore {
+            con: Arc::new(Mutex::new(con)),
+            path,
+        })
+    }
+}
+
+impl MetaStore for SqliteMetaStore {
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.get_raw(id)?.is_some())
+    }
+
+    fn get_raw(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let con = self.con.lock().unwrap();
+        let bin = con
+            .query_row("SELECT meta FROM pastes WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(bin)
+    }
+
+    fn entries(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let con = self.con.lock().unwrap();
+        let mut stmt = con.prepare("SELECT id, meta FROM pastes")?;
+        let entries = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    fn query(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        let con = self.con.lock().unwrap();
+        let mut stmt = con.prepare(
+            "SELECT id, meta FROM pastes
+             WHERE (?1 IS NULL OR instr(id, ?1) > 0 OR instr(name, ?1) > 0)
+               AND (?2 IS NULL OR expire_time < ?2)
+             ORDER BY create_time DESC
+             LIMIT ?3 OFFSET ?4",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                query.search,
+                query.expire_before.map(|t| t.timestamp_millis()),
+                query.limit.map_or(-1, |l| l as i64),
+                query.offset.unwrap_or(0) as i64,
+            ],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+        )?;
+
+        let mut metas = Vec::new();
+        for row in rows {
+            let (id, bin) = row?;
+            match meta::decode(&bin) {
+                Ok((meta, _version)) => metas.push((id, meta)),
+                Err(err) => error!("Bad metadata of paste {}: {}; skipped.", id, err),
+            }
+        }
+        Ok(metas)
+    }
+
+    fn expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let con = self.con.lock().unwrap();
+        let mut stmt = con.prepare("SELECT id FROM pastes WHERE expire_time <= ?1 OR size = 0")?;
+        let ids = stmt
+            .query_map([now.timestamp_millis()], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        let con = self.con.lock().unwrap();
+        let ms: Option<i64> =
+            con.query_row("SELECT MIN(expire_time) FROM pastes", [], |row| row.get(0))?;
+        Ok(ms.and_then(|ms| Utc.timestamp_millis_opt(ms).single()))
+    }
+
+    fn insert(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        let con = self.con.lock().unwrap();
+        con.execute(
+            "INSERT INTO pastes (id, create_time, expire_time, name, size, meta)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (id) DO UPDATE SET
+                create_time = excluded.create_time,
+                expire_time = excluded.expire_time,
+                name = excluded.name,
+                size = excluded.size,
+                meta = excluded.meta",
+            params![
+                id,
+                meta.create_time.timestamp_millis(),
+                meta.expires_at().map(|t| t.timestamp_millis()),
+                meta.name,
+                meta.size as i64,
+                meta::encode(meta)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let con = self.con.lock().unwrap();
+        con.execute("DELETE FROM pastes WHERE id = ?1", [id])?;
+        con.execute("DELETE FROM views WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        let con = self.con.lock().unwrap();
+        con.execute(
+            "INSERT INTO views (id, count) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET count = views.count + excluded.count",
+            params![id, count as i64],
+        )?;
+        let views: i64 =
+            con.query_row("SELECT count FROM views WHERE id = ?1", [id], |row| row.get(0))?;
+        Ok(views as u64)
+    }
+
+    fn views(&self, id: &str) -> Result<u64> {
+        let con = self.con.lock().unwrap();
+        let views: Option<i64> = con
+            .query_row("SELECT count FROM views WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        Ok(views.unwrap_or(0) as u64)
+    }
+
+    fn owns(&self, path: &Path) -> bool {
+        let path = match std::fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(_err) => return false,
+        };
+        // Journal files are named after the database, next to it
+        match (path.file_name(), self.path.file_name()) {
+            (Some(name), Some(db)) => {
+                path.parent() == self.path.parent()
+                    && name.to_string_lossy().starts_with(&*db.to_string_lossy())
+            }
+            _ => false,
+        }
+    }
+}
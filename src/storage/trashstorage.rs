@@ -0,0 +1,326 @@
+//! This storage backend wraps any other backend, and moves deleted and expired pastes into a trash
+//! directory instead of dropping them. Admins can restore them until the grace period is over, after
+//! which `cleanup` purges them for good.
+use crate::skip_fail;
+use crate::storage::{
+    CacheStats, Inconsistency, MetaQuery, PasteMeta, PasteWriter, Response, Storage, StorageBox,
+};
+
+use anyhow::{format_err, Result};
+use async_std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub const TRASH_DIR: &str = "trash";
+
+/// Why a paste ended up in the trash
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashReason {
+    Deleted,
+    Expired,
+}
+
+/// Stored next to the trashed content as `{id}.json`
+#[derive(Serialize, Deserialize)]
+struct TrashRecord {
+    deleted_time: DateTime<Utc>,
+    reason: TrashReason,
+    meta: PasteMeta,
+    #[serde(default)]
+    views: u64,
+}
+
+/// A trashed paste, as shown to admins
+#[derive(Serialize)]
+pub struct TrashedPaste {
+    pub id: String,
+    pub deleted_time: DateTime<Utc>,
+    pub purge_time: DateTime<Utc>,
+    pub reason: TrashReason,
+    pub name: Option<String>,
+    pub size: u64,
+}
+
+#[derive(Clone)]
+pub struct Trash {
+    dir: PathBuf,
+    grace: chrono::Duration,
+}
+
+impl Trash {
+    /// Keep trashed pastes in `base_dir/trash` for `grace_days`
+    pub fn new(base: &Path, grace_days: u32) -> Trash {
+        Trash {
+            dir: base.join(TRASH_DIR),
+            grace: chrono::Duration::days(grace_days as i64),
+        }
+    }
+
+    fn content_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id.to_owned() + ".json")
+    }
+
+    /// Copy a paste from `storage` into the trash
+    async fn put(&self, storage: &dyn Storage, id: &str, reason: TrashReason) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let meta = storage.get_meta(id).await?;
+        let views = storage.views(id).await?;
+
+        let mut file = fs::File::create(self.content_path(id)).await?;
+        match storage.get(id).await? {
+            Response::Content(vec) => file.write_all(&vec).await?,
+            Response::Stream(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    file.write_all(&chunk?).await?;
+                }
+            }
+        }
+        file.flush().await?;
+
+        // Written last, so half trashed pastes never show up
+        let record = TrashRecord {
+            deleted_time: Utc::now(),
+            reason,
+            meta,
+            views,
+        };
+        fs::write(self.record_path(id), serde_json::to_vec(&record)?).await?;
+        Ok(())
+    }
+
+    /// Whether a paste with this id is in the trash. Its id must not be handed out again until it's
+    /// purged, or deleting the new paste would overwrite it.
+    pub async fn contains(&self, id: &str) -> bool {
+        self.record_path(id).is_file().await
+    }
+
+    async fn record(&self, id: &str) -> Result<Option<TrashRecord>> {
+        let path = self.record_path(id);
+        if !path.is_file().await {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path).await?)?))
+    }
+
+    async fn records(&self) -> Result<Vec<(String, TrashRecord)>> {
+        let mut records = Vec::new();
+        if !self.dir.is_dir().await {
+            return Ok(records);
+        }
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = match name.strip_suffix(".json") {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            match self.record(&id).await {
+                Ok(Some(record)) => records.push((id, record)),
+                Ok(None) => (),
+                Err(err) => warn!("Skipping unreadable trash record of paste {}: {}", id, err),
+            }
+        }
+        Ok(records)
+    }
+
+    /// All trashed pastes, most recently deleted first
+    pub async fn list(&self) -> Result<Vec<TrashedPaste>> {
+        let mut pastes: Vec<TrashedPaste> = self
+            .records()
+            .await?
+            .into_iter()
+            .map(|(id, record)| TrashedPaste {
+                id,
+                deleted_time: record.deleted_time,
+                purge_time: record.deleted_time + self.grace,
+                reason: record.reason,
+                name: record.meta.name,
+                size: record.meta.size,
+            })
+            .collect();
+        pastes.sort_by_key(|p| std::cmp::Reverse(p.deleted_time));
+        Ok(pastes)
+    }
+
+    /// Put a trashed paste back into `storage`, returns false if there's no such paste in the trash
+    pub async fn restore(&self, storage: &dyn Storage, id: &str) -> Result<bool> {
+        let mut record = match self.record(id).await? {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        if storage.exists(id).await? {
+            return Err(format_err!("A paste with this id already exists"));
+        }
+
+        let mut writer = storage.new(id, record.meta.key()).await?;
+        let mut content = fs::File::open(self.content_path(id)).await?;
+        tokio::io::copy(&mut content, &mut writer).await?;
+        writer.flush().await?;
+
+        // Expired pastes would be trashed again right away
+        let now = Utc::now();
+        if record.meta.expire_time.is_some_and(|t| t <= now) {
+            record.meta.expire_time = None;
+        }
+        record.meta.atime = Some(now);
+        storage.set_meta(id, &record.meta).await?;
+        storage.add_views(id, record.views).await?;
+        storage.update_size(id).await?;
+
+        self.remove(id).await?;
+        info!("Restored paste {} from trash.", id);
+        Ok(true)
+    }
+
+    /// Drop a paste from the trash for good, returns false if there's no such paste in the trash
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let record_path = self.record_path(id);
+        if !record_path.is_file().await {
+            return Ok(false);
+        }
+        fs::remove_file(record_path).await?;
+        let content_path = self.content_path(id);
+        if content_path.is_file().await {
+            fs::remove_file(content_path).await?;
+        }
+        Ok(true)
+    }
+
+    /// Drop pastes whose grace period is over
+    async fn purge(&self) -> Result<Vec<String>> {
+        let now = Utc::now();
+        let mut purged = Vec::new();
+        for (id, record) in self.records().await? {
+            if record.deleted_time + self.grace <= now {
+                skip_fail!(self.remove(&id).await);
+                purged.push(id);
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn next_purge(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .records()
+            .await?
+            .iter()
+            .map(|(_id, record)| record.deleted_time + self.grace)
+            .min())
+    }
+}
+
+#[derive(Clone)]
+pub struct TrashStorage {
+    backend: StorageBox,
+    trash: Trash,
+}
+
+impl TrashStorage {
+    pub fn new(backend: StorageBox, trash: Trash) -> TrashStorage {
+        TrashStorage { backend, trash }
+    }
+
+    async fn trash_and_delete(&self, id: &str, reason: TrashReason) -> Result<()> {
+        self.trash.put(&*self.backend.inner, id, reason).await?;
+        self.backend.inner.delete(id).await
+    }
+}
+
+#[async_trait]
+impl Storage for TrashStorage {
+    async fn exists(&self, id: &str) -> Result<bool> {
+        self.backend.inner.exists(id).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Response> {
+        self.backend.inner.get(id).await
+    }
+
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        self.backend.inner.get_meta(id).await
+    }
+
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.inner.get_all_meta().await
+    }
+
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.backend.inner.list_meta(query).await
+    }
+
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.backend.inner.views(id).await
+    }
+
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        self.backend.inner.new(id, key).await
+    }
+
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.backend.inner.set_meta(id, meta).await
+    }
+
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        self.backend.inner.add_views(id, count).await
+    }
+
+    async fn update_size(&self, id: &str) -> Result<()> {
+        self.backend.inner.update_size(id).await
+    }
+
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
+        self.backend.inner.update(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.trash_and_delete(id, TrashReason::Deleted).await
+    }
+
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        let mut deleted = Vec::new();
+
+        // Trash expired pastes before the backend gets to them
+        let query = MetaQuery {
+            expire_before: Some(Utc::now()),
+            ..MetaQuery::default()
+        };
+        for (id, _meta) in self.backend.inner.list_meta(&query).await? {
+            skip_fail!(self.trash_and_delete(&id, TrashReason::Expired).await);
+            deleted.push(id);
+        }
+        deleted.extend(self.backend.inner.cleanup().await?);
+
+        for id in self.trash.purge().await? {
+            debug!("Purged paste {} from trash.", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        let next = self.backend.inner.next_expiry().await?;
+        let purge = self.trash.next_purge().await?;
+        Ok(match (next, purge) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        self.backend.inner.fsck(repair).await
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.inner.cache_stats()
+    }
+}
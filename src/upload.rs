@@ -0,0 +1,208 @@
+//! Resumable uploads of large pastes. Content received so far is kept as `base_dir/uploads/{id}`,
+//! and the settings of the paste as `{id}.json`, until the upload is finished. Uploads nobody has
+//! added to for a while are dropped.
+use crate::api::new::gen_random_chars;
+use crate::api::PasteOptions;
+
+use anyhow::Result;
+use async_std::path::{Path, PathBuf};
+use chrono::prelude::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs;
+
+pub const UPLOADS_DIR: &str = "uploads";
+// Whoever knows the id can add to the upload
+const ID_LEN: usize = 24;
+
+/// Stored next to the content as `{id}.json`
+#[derive(Serialize, Deserialize)]
+pub struct UploadRecord {
+    pub create_time: DateTime<Utc>,
+    /// Last time content arrived
+    pub update_time: DateTime<Utc>,
+    /// Size announced by the client, if it knew
+    pub length: Option<u64>,
+    pub options: PasteOptions,
+}
+
+#[derive(Clone)]
+pub struct Uploads {
+    dir: PathBuf,
+    expire: chrono::Duration,
+    // Uploads a chunk is being written to
+    busy: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Keeps other requests off an upload while a chunk is written to it, until dropped
+pub struct UploadLock {
+    busy: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Uploads {
+    /// Keep uploads in `base_dir/uploads`, dropped after `expire_hours` without new content
+    pub fn new(base: &Path, expire_hours: u32) -> Uploads {
+        Uploads {
+            dir: base.join(UPLOADS_DIR),
+            expire: chrono::Duration::hours(expire_hours as i64),
+            busy: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn content_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id.to_owned() + ".json")
+    }
+
+    /// Start an upload, returns its id
+    pub async fn create(&self, length: Option<u64>, options: PasteOptions) -> Result<String> {
+        fs::create_dir_all(&self.dir).await?;
+        let mut id = gen_random_chars(ID_LEN);
+        while self.record_path(&id).is_file().await {
+            id = gen_random_chars(ID_LEN);
+        }
+
+        let now = Utc::now();
+        let record = UploadRecord {
+            create_time: now,
+            update_time: now,
+            length,
+            options,
+        };
+        fs::write(self.content_path(&id), b"").await?;
+        self.save(&id, &record).await?;
+        Ok(id)
+    }
+
+    async fn save(&self, id: &str, record: &UploadRecord) -> Result<()> {
+        fs::write(self.record_path(id), serde_json::to_vec(record)?).await?;
+        Ok(())
+    }
+
+    /// Settings of an upload, `None` if there's no such upload
+    pub async fn record(&self, id: &str) -> Result<Option<UploadRecord>> {
+        // Ids end up in paths
+        if !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Ok(None);
+        }
+        let path = self.record_path(id);
+        if !path.is_file().await {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path).await?)?))
+    }
+
+    /// Bytes received so far
+    pub async fn offset(&self, id: &str) -> Result<u64> {
+        Ok(fs::metadata(self.content_path(id)).await?.len())
+    }
+
+    /// When the upload is dropped unless more content arrives
+    pub fn expire_time(&self, record: &UploadRecord) -> DateTime<Utc> {
+        record.update_time + self.expire
+    }
+
+    /// Take the upload for writing, `None` if another request already has it
+    pub fn lock(&self, id: &str) -> Option<UploadLock> {
+        if !self.busy.lock().unwrap().insert(id.to_string()) {
+            return None;
+        }
+        Some(UploadLock {
+            busy: self.busy.clone(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Open the content to add a chunk to its end
+    pub async fn append(&self, id: &str) -> Result<fs::File> {
+        Ok(fs::OpenOptions::new()
+            .append(true)
+            .open(self.content_path(id))
+            .await?)
+    }
+
+    /// Record that content arrived, which puts off expiry
+    pub async fn touch(&self, id: &str, record: &mut UploadRecord) -> Result<()> {
+        record.update_time = Utc::now();
+        self.save(id, record).await
+    }
+
+    /// Drop an upload, returns false if there's no such upload
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let record_path = self.record_path(id);
+        if !record_path.is_file().await {
+            return Ok(false);
+        }
+        fs::remove_file(record_path).await?;
+        let content_path = self.content_path(id);
+        if content_path.is_file().await {
+            fs::remove_file(content_path).await?;
+        }
+        Ok(true)
+    }
+
+    /// Drop uploads nobody added to in time, returns their ids
+    pub async fn purge(&self) -> Result<Vec<String>> {
+        let mut purged = Vec::new();
+        if !self.dir.is_dir().await {
+            return Ok(purged);
+        }
+
+        let now = Utc::now();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = match name.strip_suffix(".json") {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            let expired = match self.record(&id).await {
+                Ok(Some(record)) => self.expire_time(&record) <= now,
+                Ok(None) => false,
+                Err(err) => {
+                    warn!("Dropping unreadable upload {}: {}", id, err);
+                    true
+                }
+            };
+            if !expired {
+                continue;
+            }
+            // Uploads still being written to aren't abandoned
+            let _lock = match self.lock(&id) {
+                Some(lock) => lock,
+                None => continue,
+            };
+            self.remove(&id).await?;
+            purged.push(id);
+        }
+        Ok(purged)
+    }
+
+    /// Drop abandoned uploads every `interval`
+    pub async fn run(self, interval: Duration) {
+        loop {
+            actix_web::rt::time::delay_for(interval).await;
+            match self.purge().await {
+                Ok(purged) => {
+                    for id in &purged {
+                        info!("Dropped abandoned upload {}.", id);
+                    }
+                }
+                Err(err) => warn!("Failed to drop abandoned uploads: {}", err),
+            }
+        }
+    }
+}
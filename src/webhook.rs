@@ -0,0 +1,390 @@
+//! Outgoing webhooks for paste lifecycle events.
+//!
+//! Every event is written to a queue directory before anything is sent, so pending deliveries
+//! survive restarts. A single worker sends them in order, retrying failed ones with exponential
+//! backoff. Payloads are signed with HMAC-SHA256 of the webhook secret, the hex digest is sent in
+//! the `X-Rspb-Signature` header as `sha256=<digest>`.
+use crate::api::new::gen_random_chars;
+
+use anyhow::{format_err, Result};
+use async_std::path::{Path, PathBuf};
+use chrono::prelude::*;
+use futures::future;
+use hmac::{Hmac, KeyInit, Mac};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::Notify;
+
+pub const QUEUE_DIR: &str = "webhooks";
+const TIMEOUT: Duration = Duration::from_secs(10);
+// Gives up after about eight hours
+const MAX_ATTEMPTS: u32 = 16;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Created,
+    Modified,
+    Deleted,
+    Expired,
+}
+
+impl Event {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Modified => "modified",
+            Self::Deleted => "deleted",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key for signing payloads
+    pub secret: String,
+    /// Events to send, all of them if unset
+    pub events: Option<Vec<Event>>,
+}
+
+/// Body of a webhook request
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    event: Event,
+    id: String,
+    time: DateTime<Utc>,
+}
+
+/// A queued request, stored as `{queue_dir}/{delivery id}.json`
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    payload: Payload,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Does nothing unless there are webhooks configured
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    hooks: Arc<Vec<WebhookConfig>>,
+    dir: PathBuf,
+    wakeup: Arc<Notify>,
+}
+
+impl Webhooks {
+    /// Queue deliveries in `base_dir/webhooks`
+    pub fn new(hooks: Vec<WebhookConfig>, base: &Path) -> Webhooks {
+        Webhooks {
+            hooks: Arc::new(hooks),
+            dir: base.join(QUEUE_DIR),
+            wakeup: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.hooks.is_empty()
+    }
+
+    /// Queue `event` for every webhook interested in it. Failures are logged, but never fail the
+    /// operation itself.
+    pub async fn fire(&self, event: Event, id: &str) {
+        let hooks = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.events.as_ref().is_none_or(|e| e.contains(&event)));
+        for hook in hooks {
+            let delivery = Delivery {
+                url: hook.url.clone(),
+                payload: Payload {
+                    event,
+                    id: id.to_string(),
+                    time: Utc::now(),
+                },
+                attempts: 0,
+                next_attempt: Utc::now(),
+            };
+            if let Err(err) = self.enqueue(&delivery).await {
+                error!("Failed to queue webhook for paste {}: {}", id, err);
+            }
+        }
+        self.wakeup.notify();
+    }
+
+    async fn enqueue(&self, delivery: &Delivery) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        // Names sort in the order events happened
+        let name = format!(
+            "{:020}-{}.json",
+            delivery.payload.time.timestamp_nanos_opt().unwrap_or(0),
+            gen_random_chars(6)
+        );
+        fs::write(self.dir.join(name), serde_json::to_vec(delivery)?).await?;
+        Ok(())
+    }
+
+    async fn send(
+        client: &awc::Client,
+        hook: &WebhookConfig,
+        name: &str,
+        delivery: &Delivery,
+    ) -> Result<()> {
+        let body = serde_json::to_vec(&delivery.payload)?;
+
+        let res = client
+            .post(&hook.url)
+            .content_type("application/json")
+            .header("X-Rspb-Event", delivery.payload.event.as_str())
+            .header("X-Rspb-Delivery", name.trim_end_matches(".json"))
+            .header(
+                "X-Rspb-Signature",
+                format!("sha256={}", sign(&hook.secret, &body)),
+            )
+            .send_body(body)
+            .await
+            .map_err(|err| format_err!("{}", err))?;
+        if !res.status().is_success() {
+            return Err(format_err!("Webhook answered with {}", res.status()));
+        }
+        Ok(())
+    }
+
+    /// Try all due deliveries, returns when the next one is due
+    async fn deliver_due(&self, client: &awc::Client) -> Result<Option<DateTime<Utc>>> {
+        if !self.dir.is_dir().await {
+            return Ok(None);
+        }
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+
+        let mut next: Option<DateTime<Utc>> = None;
+        for name in names {
+            let path = self.dir.join(&name);
+            let mut delivery: Delivery = match serde_json::from_slice(&fs::read(&path).await?) {
+                Ok(delivery) => delivery,
+                Err(err) => {
+                    error!("Dropping unreadable webhook delivery {}: {}", name, err);
+                    fs::remove_file(&path).await?;
+                    continue;
+                }
+            };
+            let hook = match self.hooks.iter().find(|hook| hook.url == delivery.url) {
+                Some(hook) => hook,
+                None => {
+                    warn!(
+                        "Dropping webhook delivery {}, {} is no longer configured.",
+                        name, delivery.url
+                    );
+                    fs::remove_file(&path).await?;
+                    continue;
+                }
+            };
+            if delivery.next_attempt > Utc::now() {
+                next = Some(next.map_or(delivery.next_attempt, |t| t.min(delivery.next_attempt)));
+                continue;
+            }
+
+            match Self::send(client, hook, &name, &delivery).await {
+                Ok(()) => {
+                    debug!("Delivered webhook {} to {}.", name, delivery.url);
+                    fs::remove_file(&path).await?;
+                }
+                Err(err) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                    error!("Giving up webhook {} to {}: {}", name, delivery.url, err);
+                    fs::remove_file(&path).await?;
+                }
+                Err(err) => {
+                    delivery.attempts += 1;
+                    let backoff = (5i64 << delivery.attempts).min(MAX_BACKOFF_SECS);
+                    delivery.next_attempt = Utc::now() + chrono::Duration::seconds(backoff);
+                    warn!(
+                        "Webhook {} to {} failed, retrying in {}s: {}",
+                        name, delivery.url, backoff, err
+                    );
+                    fs::write(&path, serde_json::to_vec(&delivery)?).await?;
+                    next =
+                        Some(next.map_or(delivery.next_attempt, |t| t.min(delivery.next_attempt)));
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    /// Deliver queued events forever, must be spawned on the actix runtime
+    pub async fn run(self) {
+        let client = awc::Client::builder().timeout(TIMEOUT).finish();
+        loop {
+            let delay = match self.deliver_due(&client).await {
+                Ok(Some(t)) => (t - Utc::now()).to_std().unwrap_or_default(),
+                // Nothing queued, wait for the next event
+                Ok(None) => Duration::from_secs(3600),
+                Err(err) => {
+                    warn!("Failed to process webhook queue: {}", err);
+                    Duration::from_secs(60)
+                }
+            };
+
+            let sleep = actix_web::rt::time::delay_for(delay);
+            let notified = self.wakeup.notified();
+            futures::pin_mut!(sleep, notified);
+            future::select(sleep, notified).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use bytes::Bytes;
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::sync::Mutex;
+
+    /// What a webhook receiver got, and the status it answers with
+    #[derive(Default)]
+    struct Receiver {
+        requests: Mutex<Vec<(String, String, Bytes)>>,
+        status: AtomicU16,
+    }
+
+    async fn receive(receiver: web::Data<Receiver>, req: HttpRequest, body: Bytes) -> HttpResponse {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        receiver.requests.lock().unwrap().push((
+            header("X-Rspb-Event"),
+            header("X-Rspb-Signature"),
+            body,
+        ));
+        let status = receiver.status.load(Ordering::SeqCst);
+        HttpResponse::build(StatusCode::from_u16(status).unwrap()).finish()
+    }
+
+    fn receiver(status: u16) -> (test::TestServer, web::Data<Receiver>) {
+        let receiver = web::Data::new(Receiver::default());
+        receiver.status.store(status, Ordering::SeqCst);
+        let data = receiver.clone();
+        let srv = test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/hook", web::post().to(receive))
+        });
+        (srv, receiver)
+    }
+
+    fn hook(srv: &test::TestServer, events: Option<Vec<Event>>) -> WebhookConfig {
+        WebhookConfig {
+            url: srv.url("/hook"),
+            secret: "secret".to_string(),
+            events,
+        }
+    }
+
+    async fn queued(base: &Path) -> Vec<Delivery> {
+        let mut deliveries = Vec::new();
+        let mut entries = fs::read_dir(base.join(QUEUE_DIR)).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            deliveries
+                .push(serde_json::from_slice(&fs::read(entry.path()).await.unwrap()).unwrap());
+        }
+        deliveries
+    }
+
+    #[test]
+    fn sign_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn deliver_signed() {
+        let (srv, receiver) = receiver(200);
+        let dir = tempfile::tempdir().unwrap();
+        let base = PathBuf::from(dir.path());
+        let webhooks = Webhooks::new(vec![hook(&srv, None)], &base);
+
+        webhooks.fire(Event::Created, "abcdef").await;
+        webhooks.fire(Event::Deleted, "abcdef").await;
+        // Queued on disk, where a restarted server picks them up
+        assert_eq!(queued(&base).await.len(), 2);
+        let webhooks = Webhooks::new(vec![hook(&srv, None)], &base);
+
+        let client = awc::Client::default();
+        assert_eq!(webhooks.deliver_due(&client).await.unwrap(), None);
+        assert!(queued(&base).await.is_empty());
+
+        let requests = receiver.requests.lock().unwrap();
+        let events: Vec<&str> = requests.iter().map(|r| r.0.as_str()).collect();
+        assert_eq!(events, ["created", "deleted"]);
+        for (event, signature, body) in requests.iter() {
+            let payload: Payload = serde_json::from_slice(body).unwrap();
+            assert_eq!(payload.event.as_str(), event);
+            assert_eq!(payload.id, "abcdef");
+            assert_eq!(signature, &format!("sha256={}", sign("secret", body)));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn only_wanted_events() {
+        let (srv, receiver) = receiver(200);
+        let dir = tempfile::tempdir().unwrap();
+        let base = PathBuf::from(dir.path());
+        let webhooks = Webhooks::new(vec![hook(&srv, Some(vec![Event::Deleted]))], &base);
+
+        webhooks.fire(Event::Created, "abcdef").await;
+        webhooks.fire(Event::Deleted, "abcdef").await;
+        webhooks.deliver_due(&awc::Client::default()).await.unwrap();
+        let requests = receiver.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "deleted");
+    }
+
+    #[actix_rt::test]
+    async fn retry_failed() {
+        let (srv, receiver) = receiver(500);
+        let dir = tempfile::tempdir().unwrap();
+        let base = PathBuf::from(dir.path());
+        let webhooks = Webhooks::new(vec![hook(&srv, None)], &base);
+        let client = awc::Client::default();
+
+        webhooks.fire(Event::Expired, "abcdef").await;
+        let next = webhooks.deliver_due(&client).await.unwrap().unwrap();
+        assert!(next > Utc::now());
+        let deliveries = queued(&base).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].attempts, 1);
+        assert_eq!(deliveries[0].next_attempt, next);
+
+        // Not tried again before it's due
+        receiver.status.store(200, Ordering::SeqCst);
+        assert_eq!(webhooks.deliver_due(&client).await.unwrap(), Some(next));
+        assert_eq!(receiver.requests.lock().unwrap().len(), 1);
+    }
+}
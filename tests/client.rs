@@ -0,0 +1,521 @@
+//! rspb-client against an in-process server.
+use rspb_client::types::ModerationState;
+use rspb_client::{Client, Error, ListQuery, PasteOptions};
+
+use actix_web::http::Method;
+use actix_web::{test, App};
+use tempfile::TempDir;
+
+mod common;
+
+/// Server on a fresh base_dir, removed with the returned TempDir
+async fn start() -> (test::TestServer, Client, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let state = common::state(common::config(dir.path(), "upload_max_mib = 1")).await;
+    let srv = test::start(move || App::new().data(state.clone()).configure(rspb::routes));
+    let client = Client::new(&srv.url(""));
+    (srv, client, dir)
+}
+
+fn api_status<T>(res: Result<T, Error>) -> u16 {
+    match res {
+        Err(Error::Api { status, .. }) => status,
+        Err(err) => panic!("not an API error: {}", err),
+        Ok(_) => panic!("request succeeded"),
+    }
+}
+
+#[actix_rt::test]
+async fn create_get_head() {
+    let (_srv, client, _dir) = start().await;
+
+    let options = PasteOptions {
+        name: Some("hello.txt".to_string()),
+        expire_after: Some(60),
+        ..Default::default()
+    };
+    let info = client.create("hello world", &options).await.unwrap();
+    assert_eq!(info.id.len(), 6);
+    assert!(info.expire_time.is_some());
+    assert_eq!(info.redactions, None);
+
+    let content = client.get(&info.id).await.unwrap();
+    assert_eq!(&content[..], b"hello world");
+
+    let head = client.head(&info.id).await.unwrap();
+    assert_eq!(head.name.as_deref(), Some("hello.txt"));
+    assert_eq!(head.size, 11);
+}
+
+#[actix_rt::test]
+async fn create_redacted() {
+    let (_srv, client, _dir) = start().await;
+
+    let options = PasteOptions {
+        redact: true,
+        ..Default::default()
+    };
+    let info = client.create("password=hunter2\n", &options).await.unwrap();
+    assert_eq!(info.redactions, Some(1));
+    let content = client.get(&info.id).await.unwrap();
+    assert_eq!(&content[..], b"password=[REDACTED]\n");
+}
+
+#[actix_rt::test]
+async fn create_empty() {
+    let (_srv, client, _dir) = start().await;
+
+    let res = client.create("", &PasteOptions::default()).await;
+    assert_eq!(api_status(res), 400);
+}
+
+#[actix_rt::test]
+async fn modify() {
+    let (_srv, client, _dir) = start().await;
+    let info = client
+        .create("old", &PasteOptions::default())
+        .await
+        .unwrap();
+
+    let options = PasteOptions {
+        name: Some("new.txt".to_string()),
+        ..Default::default()
+    };
+    client
+        .modify(&info.id, &info.key, Some("new".into()), &options)
+        .await
+        .unwrap();
+    assert_eq!(&client.get(&info.id).await.unwrap()[..], b"new");
+    assert_eq!(
+        client.head(&info.id).await.unwrap().name.as_deref(),
+        Some("new.txt")
+    );
+
+    // Settings only
+    let options = PasteOptions {
+        name: Some("newer.txt".to_string()),
+        ..Default::default()
+    };
+    client
+        .modify(&info.id, &info.key, None, &options)
+        .await
+        .unwrap();
+    assert_eq!(&client.get(&info.id).await.unwrap()[..], b"new");
+    assert_eq!(
+        client.head(&info.id).await.unwrap().name.as_deref(),
+        Some("newer.txt")
+    );
+
+    let res = client
+        .modify(
+            &info.id,
+            "wrongkey",
+            Some("x".into()),
+            &PasteOptions::default(),
+        )
+        .await;
+    assert_eq!(api_status(res), 400);
+}
+
+#[actix_rt::test]
+async fn json_and_raw_bodies() {
+    let (srv, client, _dir) = start().await;
+    let url = srv.url("/");
+    let json = ("Content-Type", "application/json");
+
+    // Nothing is created from bad JSON or an empty body
+    let (status, _body) = common::send(&url, Method::POST, &[json], r#"{"content": "#).await;
+    assert_eq!(status, 400);
+    let raw = ("Content-Type", "text/plain");
+    let (status, _body) = common::send(&url, Method::POST, &[raw], "").await;
+    assert_eq!(status, 400);
+    let admin = Client::new(&srv.url("")).admin("admin", "pw");
+    let pastes = admin.admin_list(&ListQuery::default()).await.unwrap();
+    assert!(pastes.is_empty());
+
+    let body = r#"{"content": "hello", "name": "hello.txt"}"#;
+    let (status, body) = common::send(&url, Method::POST, &[json], body).await;
+    assert_eq!(status, 200);
+    let reply: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let id = reply["info"]["id"].as_str().unwrap();
+    let key = reply["info"]["key"].as_str().unwrap();
+    let paste_url = srv.url(&format!("/{}", id));
+
+    // Settings only leave the content alone
+    let headers = [json, ("Key", key)];
+    let body = r#"{"name": "renamed.txt"}"#;
+    let (status, _body) = common::send(&paste_url, Method::PUT, &headers, body).await;
+    assert_eq!(status, 200);
+    assert_eq!(&client.get(id).await.unwrap()[..], b"hello");
+    let meta = client.meta(id, Some(key)).await.unwrap();
+    assert_eq!(meta.name.as_deref(), Some("renamed.txt"));
+
+    let (status, _body) = common::send(&paste_url, Method::PUT, &headers, "{").await;
+    assert_eq!(status, 400);
+    let body = r#"{"content": ""}"#;
+    let (status, _body) = common::send(&paste_url, Method::PUT, &headers, body).await;
+    assert_eq!(status, 400);
+    assert_eq!(&client.get(id).await.unwrap()[..], b"hello");
+    let private = client.meta(id, Some(key)).await.unwrap().private.unwrap();
+    assert_eq!(private.revision, 2);
+}
+
+#[actix_rt::test]
+async fn malformed_multipart_modify() {
+    let (srv, client, _dir) = start().await;
+    let info = client
+        .create("kept", &PasteOptions::default())
+        .await
+        .unwrap();
+    let url = srv.url(&format!("/{}", info.id));
+
+    let forms = [
+        common::multipart(&[("content", "new"), ("bogus", "x")]),
+        common::multipart(&[("expire_after", "0")]),
+    ];
+    for (content_type, body) in &forms {
+        let headers = [("Content-Type", *content_type), ("Key", info.key.as_str())];
+        let (status, _body) = common::send(&url, Method::PUT, &headers, body).await;
+        assert_eq!(status, 400);
+        assert_eq!(&client.get(&info.id).await.unwrap()[..], b"kept");
+    }
+}
+
+#[actix_rt::test]
+async fn plain_text() {
+    let (srv, client, _dir) = start().await;
+    let text = ("Accept", "text/plain");
+    let curl = [("User-Agent", "curl/8.0.1"), ("Accept", "*/*")];
+    let lines = |body: &[u8]| {
+        let body = std::str::from_utf8(body).unwrap();
+        body.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let (content_type, body) = common::multipart(&[("c", "hello")]);
+    let headers = [("Content-Type", content_type), text];
+    let (status, body) = common::send(&srv.url("/"), Method::POST, &headers, &body).await;
+    assert_eq!(status, 200);
+    let reply = lines(&body);
+    let id = reply[0]
+        .strip_prefix("http://localhost/")
+        .unwrap()
+        .to_string();
+    let key = reply[1].clone();
+    assert_eq!(reply[2..], ["never"]);
+    assert_eq!(&client.get(&id).await.unwrap()[..], b"hello");
+
+    // Expiry and redactions, as curl sends them
+    let url = srv.url("/?redact=true&expire_after=60");
+    let (status, body) = common::send(&url, Method::POST, &curl, "password=hunter2\n").await;
+    assert_eq!(status, 200);
+    let reply = lines(&body);
+    assert!(reply[2].parse::<chrono::DateTime<chrono::Utc>>().is_ok());
+    assert_eq!(reply[3..], ["Redacted 1 secrets."]);
+
+    let paste_url = srv.url(&format!("/{}", id));
+    let headers = [("Key", key.as_str()), text];
+    let (status, body) = common::send(&paste_url, Method::PUT, &headers, "hi").await;
+    assert_eq!((status, &body[..]), (200, &b"OK\n"[..]));
+    assert_eq!(&client.get(&id).await.unwrap()[..], b"hi");
+
+    // Errors too
+    let headers = [("Key", "wrongkey"), curl[0], curl[1]];
+    let (status, body) = common::send(&paste_url, Method::DELETE, &headers, "").await;
+    assert_eq!(
+        (status, &body[..]),
+        (403, &b"Error: Forbidden: Bad Key.\n"[..])
+    );
+    let headers = [("Key", key.as_str()), text];
+    let (status, body) = common::send(&paste_url, Method::DELETE, &headers, "").await;
+    assert_eq!((status, &body[..]), (200, &b"OK\n"[..]));
+    let meta_url = srv.url(&format!("/{}/meta", id));
+    let (status, body) = common::send(&meta_url, Method::GET, &curl, "").await;
+    assert_eq!(
+        (status, &body[..]),
+        (404, &b"Error: Paste Not Found.\n"[..])
+    );
+
+    // JSON when asked for, and always from API v1
+    let json = [curl[0], ("Accept", "application/json")];
+    let (status, body) = common::send(&meta_url, Method::GET, &json, "").await;
+    assert_eq!(status, 404);
+    assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+    let url = srv.url(&format!("/api/v1/pastes/{}", id));
+    let (status, body) = common::send(&url, Method::GET, &[text], "").await;
+    assert_eq!(status, 404);
+    assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+}
+
+#[actix_rt::test]
+async fn delete() {
+    let (_srv, client, _dir) = start().await;
+    let info = client
+        .create("bye", &PasteOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(api_status(client.delete(&info.id, "wrongkey").await), 403);
+    client.delete(&info.id, &info.key).await.unwrap();
+    assert_eq!(api_status(client.get(&info.id).await), 404);
+    assert_eq!(api_status(client.head(&info.id).await), 404);
+}
+
+#[actix_rt::test]
+async fn admin_list() {
+    let (_srv, client, _dir) = start().await;
+    let options = PasteOptions {
+        name: Some("findme".to_string()),
+        ..Default::default()
+    };
+    let info = client.create("x", &options).await.unwrap();
+    client.create("y", &PasteOptions::default()).await.unwrap();
+
+    assert_eq!(
+        api_status(client.admin_list(&ListQuery::default()).await),
+        401
+    );
+
+    let admin = client.admin("admin", "pw");
+    assert_eq!(
+        admin.admin_list(&ListQuery::default()).await.unwrap().len(),
+        2
+    );
+    let query = ListQuery {
+        search: Some("findme".to_string()),
+        ..Default::default()
+    };
+    let found = admin.admin_list(&query).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, info.id);
+    assert_eq!(found[0].size, 1);
+}
+
+#[actix_rt::test]
+async fn admin_moderation() {
+    let (srv, client, _dir) = start().await;
+    let info = client
+        .create("spam", &PasteOptions::default())
+        .await
+        .unwrap();
+    let admin = client.clone().admin("admin", "pw");
+
+    let hold = admin.hold(&info.id).await.unwrap();
+    assert_eq!(hold.by, "admin");
+    assert_eq!(api_status(admin.hold(&info.id).await), 400);
+    assert_eq!(api_status(client.delete(&info.id, &info.key).await), 423);
+    admin.release(&info.id).await.unwrap();
+
+    let res = srv
+        .post(format!("/{}/report", info.id))
+        .send_form(&[("reason", "spam")])
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let reports = admin.reports().await.unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].id, info.id);
+    assert_eq!(reports[0].reports[0].reason.as_deref(), Some("spam"));
+    assert!(reports[0].moderation.is_none());
+    admin.dismiss(&info.id).await.unwrap();
+    assert!(admin.reports().await.unwrap().is_empty());
+
+    let moderation = admin
+        .moderate(&info.id, ModerationState::Hidden)
+        .await
+        .unwrap();
+    assert_eq!(moderation.state, ModerationState::Hidden);
+    assert_eq!(moderation.by.as_deref(), Some("admin"));
+    assert_eq!(api_status(client.get(&info.id).await), 410);
+    assert_eq!(&admin.admin_get(&info.id).await.unwrap()[..], b"spam");
+    admin.reinstate(&info.id).await.unwrap();
+    assert_eq!(&client.get(&info.id).await.unwrap()[..], b"spam");
+
+    admin.admin_delete(&info.id).await.unwrap();
+    assert_eq!(api_status(client.get(&info.id).await), 404);
+    assert_eq!(api_status(admin.admin_get(&info.id).await), 404);
+    // No trash on this server to restore from
+    assert_eq!(api_status(admin.restore(&info.id).await), 400);
+}
+
+#[actix_rt::test]
+async fn meta() {
+    let (_srv, client, _dir) = start().await;
+    let options = PasteOptions {
+        name: Some("notes.md".to_string()),
+        ..Default::default()
+    };
+    let info = client.create("# notes", &options).await.unwrap();
+    client.get(&info.id).await.unwrap();
+
+    let meta = client.meta(&info.id, None).await.unwrap();
+    assert_eq!(meta.id, info.id);
+    assert_eq!(meta.name.as_deref(), Some("notes.md"));
+    assert_eq!(meta.size, 7);
+    assert_eq!(meta.content_type, "text/markdown");
+    assert!(meta.atime.is_some());
+    assert!(meta.private.is_none());
+
+    client
+        .modify(
+            &info.id,
+            &info.key,
+            Some("# more notes".into()),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+    let private = client
+        .meta(&info.id, Some(&info.key))
+        .await
+        .unwrap()
+        .private
+        .unwrap();
+    assert_eq!(private.views, 1);
+    assert_eq!(private.revision, 2);
+    assert!(private.modify_time.is_some());
+
+    let admin = client.clone().admin("admin", "pw");
+    assert!(admin.meta(&info.id, None).await.unwrap().private.is_some());
+    let wrong = client.clone().admin("admin", "wrong");
+    assert_eq!(api_status(wrong.meta(&info.id, None).await), 401);
+    assert_eq!(
+        api_status(client.meta(&info.id, Some("wrongkey")).await),
+        403
+    );
+    assert_eq!(api_status(client.meta("zzzzzz", None).await), 404);
+}
+
+#[actix_rt::test]
+async fn expire_after_inactive() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = common::state(common::config(dir.path(), "")).await;
+    let storage = state.storage.clone();
+    let srv = test::start(move || App::new().data(state.clone()).configure(rspb::routes));
+    let client = Client::new(&srv.url(""));
+    let options = PasteOptions {
+        expire_after_inactive: Some(1),
+        ..Default::default()
+    };
+    let idle = client.create("idle", &options).await.unwrap();
+    let viewed = client.create("viewed", &options).await.unwrap();
+
+    // Both were last seen two days ago, a day past the window
+    let two_days_ago = chrono::Utc::now() - chrono::Duration::days(2);
+    for id in &[&idle.id, &viewed.id] {
+        let mut meta = storage.inner.get_meta(id).await.unwrap();
+        assert!(meta.expires_at().is_some());
+        meta.create_time = two_days_ago;
+        meta.atime = Some(two_days_ago);
+        storage.inner.set_meta(id, &meta).await.unwrap();
+    }
+    // A view starts the window over
+    assert_eq!(&client.get(&viewed.id).await.unwrap()[..], b"viewed");
+    let meta = storage.inner.get_meta(&viewed.id).await.unwrap();
+    assert!(meta.expires_at().unwrap() > chrono::Utc::now() + chrono::Duration::hours(23));
+
+    assert_eq!(storage.inner.cleanup().await.unwrap(), [idle.id.as_str()]);
+    assert_eq!(api_status(client.get(&idle.id).await), 404);
+    assert_eq!(&client.get(&viewed.id).await.unwrap()[..], b"viewed");
+}
+
+#[actix_rt::test]
+async fn view_keeps_newer_meta() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = common::state(common::config(dir.path(), "")).await;
+    let srv = {
+        let state = state.clone();
+        test::start(move || App::new().data(state.clone()).configure(rspb::routes))
+    };
+    let client = Client::new(&srv.url(""));
+    let info = client
+        .create("hello", &PasteOptions::default())
+        .await
+        .unwrap();
+    let storage = &state.storage.inner;
+
+    // A view that read the metadata just before the paste was renamed
+    let mut stale = storage.get_meta(&info.id).await.unwrap();
+    stale.atime = None;
+    let options = PasteOptions {
+        name: Some("renamed.txt".to_string()),
+        ..Default::default()
+    };
+    client
+        .modify(&info.id, &info.key, None, &options)
+        .await
+        .unwrap();
+    rspb::api::get::record_view(&state, &info.id, &mut stale).await;
+
+    let meta = storage.get_meta(&info.id).await.unwrap();
+    assert_eq!(meta.name.as_deref(), Some("renamed.txt"));
+    assert_eq!(meta.atime, stale.atime);
+    assert_eq!(storage.views(&info.id).await.unwrap(), 1);
+}
+
+#[actix_rt::test]
+async fn resumable_upload() {
+    let (_srv, client, _dir) = start().await;
+    let options = PasteOptions {
+        name: Some("big.txt".to_string()),
+        ..Default::default()
+    };
+    let upload = client.start_upload(Some(11), &options).await.unwrap();
+    assert_eq!(upload.offset, 0);
+    assert_eq!(upload.length, Some(11));
+
+    let status = client.upload_chunk(&upload.id, 0, "hello ").await.unwrap();
+    assert_eq!(status.offset, 6);
+    // Resending a chunk that already arrived
+    assert_eq!(
+        api_status(client.upload_chunk(&upload.id, 0, "hello ").await),
+        409
+    );
+    assert_eq!(api_status(client.finish_upload(&upload.id).await), 400);
+    assert_eq!(
+        api_status(client.upload_chunk(&upload.id, 6, "world and more").await),
+        400
+    );
+
+    let offset = client.upload_status(&upload.id).await.unwrap().offset;
+    client
+        .upload_chunk(&upload.id, offset, "world")
+        .await
+        .unwrap();
+    let info = client.finish_upload(&upload.id).await.unwrap();
+    assert_eq!(client.get(&info.id).await.unwrap(), "hello world");
+    assert_eq!(
+        client.head(&info.id).await.unwrap().name.as_deref(),
+        Some("big.txt")
+    );
+    assert_eq!(api_status(client.upload_status(&upload.id).await), 404);
+
+    let upload = client.start_upload(None, &options).await.unwrap();
+    client.cancel_upload(&upload.id).await.unwrap();
+    assert_eq!(api_status(client.upload_status(&upload.id).await), 404);
+    assert_eq!(
+        api_status(client.upload_status("elsewhere.json").await),
+        404
+    );
+}
+
+#[actix_rt::test]
+async fn upload_too_large() {
+    let (_srv, client, _dir) = start().await;
+    let options = PasteOptions::default();
+    let max = 1024 * 1024;
+    assert_eq!(
+        api_status(client.start_upload(Some(max + 1), &options).await),
+        413
+    );
+
+    // Without a length, chunks still stop at the limit
+    let upload = client.start_upload(None, &options).await.unwrap();
+    let chunk = vec![b'a'; max as usize];
+    let status = client.upload_chunk(&upload.id, 0, chunk).await.unwrap();
+    assert_eq!(status.offset, max);
+    assert_eq!(
+        api_status(client.upload_chunk(&upload.id, max, "a").await),
+        413
+    );
+    assert_eq!(client.upload_status(&upload.id).await.unwrap().offset, max);
+}
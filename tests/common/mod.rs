@@ -0,0 +1,67 @@
+//! Setup shared by the integration tests.
+#![allow(dead_code)]
+use rspb::storage::StorageBox;
+use rspb::{Config, PasteState};
+
+use actix_web::http::Method;
+use bytes::Bytes;
+use std::path::Path;
+
+/// BLAKE2b of "pw", the password of `admin`
+pub const ADMIN_HASH: &str = "6bddc3a7e69780937eb8b5fc52a605ec0a498c218c97588494ed74df7d5e4240f3c666b50d3c019c2fa76f3973935e64e17ccb321e4285f142b7b0cd44dca643";
+
+/// Basic auth of `admin`
+pub const ADMIN_AUTH: &str = "Basic YWRtaW46cHc=";
+
+/// Config of a server on `base_dir`, with the top-level settings in `extra` added
+pub fn config(base_dir: &Path, extra: &str) -> Config {
+    toml::from_str(&format!(
+        r#"
+        base_dir = "{dir}"
+        bind_address = "127.0.0.1:0"
+        {extra}
+        [admins]
+        admin = "{hash}"
+        [site]
+        name = "t"
+        slogan = "t"
+        description = "t"
+        url = "http://localhost"
+        "#,
+        dir = base_dir.display(),
+        extra = extra,
+        hash = ADMIN_HASH
+    ))
+    .unwrap()
+}
+
+/// State of a server with `config`, on the storage backend it names
+pub async fn state(config: Config) -> PasteState {
+    let storage = StorageBox::new(&config.storage).await.unwrap();
+    PasteState::new(config, storage).unwrap()
+}
+
+/// Send `body` to `url` with `headers`, returns the status and the reply. Every request gets its
+/// own connection, so replies sent before the body was read don't affect the next one.
+pub async fn send(url: &str, method: Method, headers: &[(&str, &str)], body: &str) -> (u16, Bytes) {
+    let mut req = awc::Client::new().request(method, url);
+    for (name, value) in headers {
+        req = req.set_header(*name, *value);
+    }
+    let mut res = req.send_body(body.to_string()).await.unwrap();
+    let body = res.body().await.unwrap();
+    (res.status().as_u16(), body)
+}
+
+/// Content type and body of a multipart form with `fields`
+pub fn multipart(fields: &[(&str, &str)]) -> (&'static str, String) {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body.push_str(&format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            name, value
+        ));
+    }
+    body.push_str("--boundary--\r\n");
+    ("multipart/form-data; boundary=boundary", body)
+}
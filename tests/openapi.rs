@@ -0,0 +1,101 @@
+//! The OpenAPI document against the routes of the app.
+//!
+//! Every documented operation has to reach the resource it's documented under, and other methods
+//! on documented paths have to be refused.
+use actix_web::dev::Service;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{test, App};
+use futures::TryFutureExt;
+use std::collections::BTreeMap;
+
+mod common;
+
+const ROUTE: &str = "x-route";
+const METHODS: [Method; 6] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+/// Documented methods of every path
+fn documented() -> BTreeMap<String, Vec<Method>> {
+    let spec = rspb::api::openapi::spec("http://localhost");
+    let mut paths = BTreeMap::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        let methods = item
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|method| method.to_uppercase().parse().unwrap())
+            .collect();
+        paths.insert(path.clone(), methods);
+    }
+    paths
+}
+
+/// A path the template matches, with every parameter filled in
+fn fill(template: &str) -> String {
+    template
+        .split('/')
+        .map(|part| match part.starts_with('{') {
+            true => "abcdef",
+            false => part,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[actix_rt::test]
+async fn routes_match_spec() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = common::state(common::config(dir.path(), "")).await;
+    // Tells which resource a request was routed to
+    let mut app = test::init_service(
+        App::new()
+            .wrap_fn(|req, srv| {
+                let route = req.match_pattern();
+                srv.call(req).map_ok(move |mut res| {
+                    if let Some(route) = route {
+                        let value = HeaderValue::from_str(&route).unwrap();
+                        res.headers_mut()
+                            .insert(HeaderName::from_static(ROUTE), value);
+                    }
+                    res
+                })
+            })
+            .data(state)
+            .configure(rspb::routes),
+    )
+    .await;
+
+    let documented = documented();
+    assert!(documented.len() > 20, "no paths in the OpenAPI document");
+    for (path, methods) in &documented {
+        for method in METHODS.iter() {
+            let req = test::TestRequest::with_uri(&fill(path))
+                .method(method.clone())
+                .header("Authorization", common::ADMIN_AUTH)
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            let route = res.headers().get(ROUTE).map(|r| r.to_str().unwrap());
+            let refused = res.status() == StatusCode::METHOD_NOT_ALLOWED;
+
+            if methods.contains(method) {
+                assert_eq!(route, Some(path.as_str()), "{} {} not routed", method, path);
+                assert!(!refused, "{} {} not allowed", method, path);
+            } else {
+                // Undocumented, unless it's routed somewhere else
+                assert!(
+                    refused || route != Some(path.as_str()),
+                    "{} {} is not documented",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+}
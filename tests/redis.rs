@@ -0,0 +1,131 @@
+//! Redis backed storage, against the Redis at `RSPB_TEST_REDIS` (`redis://127.0.0.1/` if unset).
+//!
+//! These need a running Redis, run them with `cargo test --test redis -- --ignored`. Every test
+//! works on fresh random ids, so a Redis in use by nothing else is all it takes.
+use rspb::api::new::{gen_random_chars, ID_LEN};
+use rspb::storage::rediscachedstorage::RedisCachedStorage;
+use rspb::storage::redisstorage::RedisStorage;
+use rspb::storage::{Response, Storage};
+
+use async_std::path::PathBuf;
+use chrono::{Duration, Utc};
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+
+fn redis_url() -> String {
+    std::env::var("RSPB_TEST_REDIS").unwrap_or_else(|_| "redis://127.0.0.1/".to_string())
+}
+
+/// Filesystem storage cached in Redis, on a fresh base_dir
+async fn cached() -> (RedisCachedStorage, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = RedisCachedStorage::new(&PathBuf::from(dir.path()), None, &redis_url())
+        .await
+        .unwrap();
+    (storage, dir)
+}
+
+async fn create(storage: &dyn Storage, content: &str) -> String {
+    let id = gen_random_chars(ID_LEN);
+    let mut writer = storage.new(&id, "key").await.unwrap();
+    writer.write_all(content.as_bytes()).await.unwrap();
+    writer.flush().await.unwrap();
+    storage.update_size(&id).await.unwrap();
+    id
+}
+
+async fn content(storage: &dyn Storage, id: &str) -> String {
+    match storage.get(id).await.unwrap() {
+        Response::Content(vec) => String::from_utf8(vec).unwrap(),
+        Response::Stream(_stream) => panic!("small paste streamed"),
+    }
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn cached_meta_and_content() {
+    let (storage, _dir) = cached().await;
+    // Shares the Redis but not the files, so it only finds what's cached
+    let (other, _other_dir) = cached().await;
+
+    let id = create(&storage, "hello").await;
+    let mut meta = storage.get_meta(&id).await.unwrap();
+    meta.name = Some("hello.txt".to_string());
+    storage.set_meta(&id, &meta).await.unwrap();
+    assert_eq!(content(&storage, &id).await, "hello");
+
+    assert!(other.exists(&id).await.unwrap());
+    let cached = other.get_meta(&id).await.unwrap();
+    assert_eq!(cached.name.as_deref(), Some("hello.txt"));
+    assert_eq!(cached.size, 5);
+    assert_eq!(content(&other, &id).await, "hello");
+
+    // Changed content is no longer served from the cache
+    drop(storage.update(&id).await.unwrap());
+    assert!(other.get(&id).await.is_err());
+    assert!(other.get_meta(&id).await.is_err());
+
+    storage.delete(&id).await.unwrap();
+    assert!(!other.exists(&id).await.unwrap());
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn cleanup_reports_expired() {
+    let storage = RedisStorage::new(&redis_url()).await.unwrap();
+    let kept = create(&storage, "kept").await;
+
+    // Expired by Redis the moment its expire time is set
+    let expired = create(&storage, "gone").await;
+    let mut meta = storage.get_meta(&expired).await.unwrap();
+    meta.expire_time = Some(Utc::now() - Duration::minutes(1));
+    storage.set_meta(&expired, &meta).await.unwrap();
+    assert!(!storage.exists(&expired).await.unwrap());
+
+    // Content never arrived, a while ago
+    let abandoned = gen_random_chars(ID_LEN);
+    drop(storage.new(&abandoned, "key").await.unwrap());
+    let mut meta = storage.get_meta(&abandoned).await.unwrap();
+    meta.create_time = Utc::now() - Duration::hours(2);
+    storage.set_meta(&abandoned, &meta).await.unwrap();
+
+    // Still being written
+    let fresh = gen_random_chars(ID_LEN);
+    drop(storage.new(&fresh, "key").await.unwrap());
+
+    let deleted = storage.cleanup().await.unwrap();
+    assert!(deleted.contains(&expired));
+    assert!(deleted.contains(&abandoned));
+    assert!(!deleted.contains(&kept));
+    assert!(!deleted.contains(&fresh));
+    assert!(!storage.exists(&abandoned).await.unwrap());
+    assert_eq!(content(&storage, &kept).await, "kept");
+
+    // Reported once
+    assert!(!storage.cleanup().await.unwrap().contains(&expired));
+    storage.delete(&kept).await.unwrap();
+    storage.delete(&fresh).await.unwrap();
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn cache_skips_stale_fill() {
+    let (storage, _dir) = cached().await;
+    let id = create(&storage, "0").await;
+
+    // Reads racing a modification must not leave the old content cached
+    for n in 1..50 {
+        let modify = async {
+            let mut writer = storage.update(&id).await.unwrap();
+            writer.write_all(n.to_string().as_bytes()).await.unwrap();
+            writer.flush().await.unwrap();
+            storage.update_size(&id).await.unwrap();
+        };
+        let read = async {
+            let _ = storage.get(&id).await;
+        };
+        futures::join!(modify, read);
+        assert_eq!(content(&storage, &id).await, n.to_string());
+    }
+    storage.delete(&id).await.unwrap();
+}
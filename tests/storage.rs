@@ -0,0 +1,705 @@
+//! Storage backends and the tools built on them, against fresh directories.
+use rspb::archive::{self, OnConflict};
+use rspb::audit::{AuditLog, AuditQuery, Operation};
+use rspb::migrate;
+use rspb::storage::memorycachedstorage::MemoryCachedStorage;
+use rspb::storage::simplestorage::SimpleStorage;
+use rspb::storage::trashstorage::{Trash, TrashReason, TrashStorage};
+use rspb::storage::{
+    Inconsistency, LegalHold, MetaQuery, PasteMeta, PasteWriter, Response, Storage, StorageBox,
+};
+
+use anyhow::Result;
+use async_std::path::PathBuf;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+
+/// Filesystem storage in a fresh base_dir, with metadata in `meta_store` if set
+fn filesystem(meta_store: Option<&str>) -> (SimpleStorage, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = SimpleStorage::new(&PathBuf::from(dir.path()), meta_store).unwrap();
+    (storage, dir)
+}
+
+/// Create a paste like the API does
+async fn create(storage: &dyn Storage, id: &str, content: &str) {
+    let mut writer = storage.new(id, "key").await.unwrap();
+    writer.write_all(content.as_bytes()).await.unwrap();
+    writer.flush().await.unwrap();
+    storage.update_size(id).await.unwrap();
+}
+
+async fn content(storage: &dyn Storage, id: &str) -> String {
+    match storage.get(id).await.unwrap() {
+        Response::Content(vec) => String::from_utf8(vec).unwrap(),
+        Response::Stream(_stream) => panic!("small paste streamed"),
+    }
+}
+
+/// The same storage over each metadata store, in fresh directories
+fn meta_stores() -> Vec<(SimpleStorage, TempDir)> {
+    let dir = tempfile::tempdir().unwrap();
+    let db = format!("sqlite:{}/meta.sqlite", dir.path().display());
+    let sqlite = SimpleStorage::new(&PathBuf::from(dir.path()), Some(&db)).unwrap();
+    vec![filesystem(None), (sqlite, dir)]
+}
+
+/// Create a paste created `age` ago, expiring `expire` from now if set
+async fn create_aged(
+    storage: &dyn Storage,
+    id: &str,
+    name: Option<&str>,
+    age: Duration,
+    expire: Option<Duration>,
+) {
+    create(storage, id, id).await;
+    let mut meta = storage.get_meta(id).await.unwrap();
+    meta.name = name.map(str::to_string);
+    meta.create_time = Utc::now() - age;
+    meta.expire_time = expire.map(|expire| Utc::now() + expire);
+    storage.set_meta(id, &meta).await.unwrap();
+}
+
+async fn list(storage: &dyn Storage, query: MetaQuery) -> Vec<String> {
+    let metas = storage.list_meta(&query).await.unwrap();
+    metas.into_iter().map(|(id, _meta)| id).collect()
+}
+
+#[actix_rt::test]
+async fn metastore_queries() {
+    for (storage, _dir) in meta_stores() {
+        let hours = Duration::hours;
+        create_aged(
+            &storage,
+            "aaaaaa",
+            Some("notes.txt"),
+            hours(3),
+            Some(hours(2)),
+        )
+        .await;
+        create_aged(&storage, "bbbbbb", None, hours(2), Some(hours(1))).await;
+        create_aged(&storage, "cccccc", Some("later.txt"), hours(1), None).await;
+        create_aged(&storage, "dddddd", None, hours(4), Some(hours(5))).await;
+
+        let query = |search: Option<&str>, expire_before, limit, offset| MetaQuery {
+            search: search.map(str::to_string),
+            expire_before,
+            limit,
+            offset,
+        };
+        // Newest first
+        assert_eq!(
+            list(&storage, MetaQuery::default()).await,
+            ["cccccc", "bbbbbb", "aaaaaa", "dddddd"]
+        );
+        assert_eq!(
+            list(&storage, query(None, None, Some(2), Some(1))).await,
+            ["bbbbbb", "aaaaaa"]
+        );
+        // By id or name
+        assert_eq!(
+            list(&storage, query(Some("notes"), None, None, None)).await,
+            ["aaaaaa"]
+        );
+        assert_eq!(
+            list(&storage, query(Some("ccc"), None, None, None)).await,
+            ["cccccc"]
+        );
+        assert_eq!(
+            list(&storage, query(Some(".txt"), None, None, None)).await,
+            ["cccccc", "aaaaaa"]
+        );
+        assert_eq!(
+            list(
+                &storage,
+                query(None, Some(Utc::now() + hours(3)), None, None)
+            )
+            .await,
+            ["bbbbbb", "aaaaaa"]
+        );
+
+        // Changes and deletions show up
+        let mut meta = storage.get_meta("cccccc").await.unwrap();
+        meta.name = None;
+        storage.set_meta("cccccc", &meta).await.unwrap();
+        storage.delete("aaaaaa").await.unwrap();
+        assert!(list(&storage, query(Some(".txt"), None, None, None))
+            .await
+            .is_empty());
+        assert_eq!(
+            list(&storage, MetaQuery::default()).await,
+            ["cccccc", "bbbbbb", "dddddd"]
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn view_counts() {
+    for (storage, _dir) in meta_stores() {
+        create(&storage, "aaaaaa", "aaaaaa").await;
+        assert_eq!(storage.views("aaaaaa").await.unwrap(), 0);
+
+        // Concurrent views all count, and leave the metadata alone
+        let mut meta = storage.get_meta("aaaaaa").await.unwrap();
+        meta.name = Some("held.txt".to_string());
+        storage.set_meta("aaaaaa", &meta).await.unwrap();
+        let views = (0..20).map(|_| storage.add_views("aaaaaa", 1));
+        futures::future::try_join_all(views).await.unwrap();
+        assert_eq!(storage.views("aaaaaa").await.unwrap(), 20);
+        let meta = storage.get_meta("aaaaaa").await.unwrap();
+        assert_eq!(meta.name.as_deref(), Some("held.txt"));
+
+        // Writing the metadata keeps the count
+        storage.set_meta("aaaaaa", &meta).await.unwrap();
+        assert_eq!(storage.add_views("aaaaaa", 2).await.unwrap(), 22);
+
+        // A paste created again under the same id starts over
+        storage.delete("aaaaaa").await.unwrap();
+        assert_eq!(storage.views("aaaaaa").await.unwrap(), 0);
+    }
+}
+
+#[actix_rt::test]
+async fn fsck_repair() {
+    let dir = tempfile::tempdir().unwrap();
+    // A database inside base_dir, configured with a path that's not canonical
+    let db = format!("sqlite:{}/./meta.sqlite", dir.path().display());
+    let storage = SimpleStorage::new(&PathBuf::from(dir.path()), Some(&db)).unwrap();
+    let path = |name: &str| dir.path().join(name);
+
+    create(&storage, "aaaaaa", "hello").await;
+    create(&storage, "bbbbbb", "world").await;
+    create(&storage, "cccccc", "gone").await;
+    std::fs::write(path("aaaaaa.highlight"), "<b>hello</b>").unwrap();
+    std::fs::write(path("bbbbbb"), "world, longer").unwrap();
+    std::fs::remove_file(path("cccccc")).unwrap();
+    std::fs::write(path("dddddd"), "orphan").unwrap();
+    std::fs::write(path("audit.log"), "not a paste").unwrap();
+
+    let mut problems = storage.fsck(false).await.unwrap();
+    problems.sort_by(|a, b| a.id().cmp(b.id()));
+    assert_eq!(
+        problems.len(),
+        3,
+        "{:?}",
+        problems.iter().map(|p| p.to_string()).collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        &problems[0],
+        Inconsistency::WrongSize { id, recorded: 5, actual: 13 } if id == "bbbbbb"
+    ));
+    assert!(matches!(&problems[1], Inconsistency::MissingContent { id } if id == "cccccc"));
+    assert!(matches!(&problems[2], Inconsistency::OrphanFile { id } if id == "dddddd"));
+    // Only checking changes nothing
+    assert!(path("dddddd").exists());
+
+    assert_eq!(storage.fsck(true).await.unwrap().len(), 3);
+    assert!(storage.fsck(false).await.unwrap().is_empty());
+    assert_eq!(storage.get_meta("bbbbbb").await.unwrap().size, 13);
+    assert!(!storage.exists("cccccc").await.unwrap());
+    assert!(!path("dddddd").exists());
+    assert!(path("aaaaaa.highlight").exists());
+    assert!(path("audit.log").exists());
+    assert!(path("meta.sqlite").exists());
+    assert_eq!(content(&storage, "aaaaaa").await, "hello");
+}
+
+#[actix_rt::test]
+async fn fsck_healthy() {
+    let (storage, dir) = filesystem(None);
+    create(&storage, "aaaaaa", "hello").await;
+    std::fs::create_dir(dir.path().join("trash")).unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "kept by the admin").unwrap();
+
+    assert!(storage.fsck(true).await.unwrap().is_empty());
+    assert!(dir.path().join("notes.txt").exists());
+    assert_eq!(content(&storage, "aaaaaa").await, "hello");
+}
+
+/// Archive of two named pastes, and a store that already has a different `aaaaaa`
+async fn exported() -> (std::path::PathBuf, SimpleStorage, TempDir, TempDir) {
+    let (source, source_dir) = filesystem(None);
+    create(&source, "aaaaaa", "first").await;
+    create(&source, "bbbbbb", "second").await;
+    let mut meta = source.get_meta("aaaaaa").await.unwrap();
+    meta.name = Some("first.txt".to_string());
+    source.set_meta("aaaaaa", &meta).await.unwrap();
+
+    let path = source_dir.path().join("pastes.tar");
+    assert_eq!(archive::export(&source, &path).await.unwrap().exported, 2);
+
+    let (dest, dest_dir) = filesystem(None);
+    create(&dest, "aaaaaa", "taken").await;
+    (path, dest, source_dir, dest_dir)
+}
+
+#[actix_rt::test]
+async fn export_unreadable() {
+    let (source, dir) = filesystem(None);
+    create(&source, "aaaaaa", "first").await;
+    create(&source, "bbbbbb", "second").await;
+    std::fs::remove_file(dir.path().join("bbbbbb")).unwrap();
+
+    let path = dir.path().join("pastes.tar");
+    let summary = archive::export(&source, &path).await.unwrap();
+    assert_eq!(summary.exported, 1);
+    assert_eq!(summary.failed, ["bbbbbb"]);
+    assert!(!dir.path().join("pastes.spool").exists());
+
+    let (dest, _dest_dir) = filesystem(None);
+    let summary = archive::import(&dest, &path, OnConflict::Skip, &AuditLog::default())
+        .await
+        .unwrap();
+    assert_eq!(summary.imported, 1);
+    assert_eq!(content(&dest, "aaaaaa").await, "first");
+}
+
+#[actix_rt::test]
+async fn import_skip() {
+    let (path, dest, _source_dir, _dest_dir) = exported().await;
+    let summary = archive::import(&dest, &path, OnConflict::Skip, &AuditLog::default())
+        .await
+        .unwrap();
+    assert_eq!((summary.imported, summary.skipped), (1, 1));
+    assert_eq!(content(&dest, "aaaaaa").await, "taken");
+    assert_eq!(content(&dest, "bbbbbb").await, "second");
+}
+
+#[actix_rt::test]
+async fn import_overwrite() {
+    let (path, dest, _source_dir, dest_dir) = exported().await;
+    let audit = AuditLog::open(&dest_dir.path().join("audit.log")).unwrap();
+    let summary = archive::import(&dest, &path, OnConflict::Overwrite, &audit)
+        .await
+        .unwrap();
+    assert_eq!((summary.imported, summary.skipped), (2, 0));
+    assert_eq!(content(&dest, "aaaaaa").await, "first");
+    let meta = dest.get_meta("aaaaaa").await.unwrap();
+    assert_eq!(meta.name.as_deref(), Some("first.txt"));
+    assert_eq!(meta.size, 5);
+    assert!(meta.validate("key"));
+
+    let entries = audit.query(&AuditQuery::default()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        (entries[0].operation, entries[0].id.as_str()),
+        (Operation::Delete, "aaaaaa")
+    );
+}
+
+#[actix_rt::test]
+async fn import_overwrite_held() {
+    let (path, dest, _source_dir, _dest_dir) = exported().await;
+    let mut meta = dest.get_meta("aaaaaa").await.unwrap();
+    meta.hold = Some(LegalHold {
+        by: "admin".to_string(),
+        time: Utc::now(),
+    });
+    dest.set_meta("aaaaaa", &meta).await.unwrap();
+
+    let summary = archive::import(&dest, &path, OnConflict::Overwrite, &AuditLog::default())
+        .await
+        .unwrap();
+    assert_eq!((summary.imported, summary.skipped), (1, 1));
+    assert_eq!(content(&dest, "aaaaaa").await, "taken");
+    assert!(dest.get_meta("aaaaaa").await.unwrap().hold.is_some());
+}
+
+#[actix_rt::test]
+async fn import_rename() {
+    let (path, dest, _source_dir, _dest_dir) = exported().await;
+    let summary = archive::import(&dest, &path, OnConflict::Rename, &AuditLog::default())
+        .await
+        .unwrap();
+    assert_eq!(summary.imported, 2);
+    assert_eq!(summary.renamed.len(), 1);
+    let (old, new) = &summary.renamed[0];
+    assert_eq!(old, "aaaaaa");
+    assert_eq!(content(&dest, "aaaaaa").await, "taken");
+    assert_eq!(content(&dest, new).await, "first");
+    assert_eq!(
+        dest.get_meta(new).await.unwrap().name.as_deref(),
+        Some("first.txt")
+    );
+}
+
+#[actix_rt::test]
+async fn migrate_resume() {
+    let (source, _source_dir) = filesystem(None);
+    let (dest, dest_dir) = filesystem(None);
+    let journal = dest_dir.path().join("journal");
+    create(&source, "aaaaaa", "first").await;
+    create(&source, "bbbbbb", "second").await;
+    // Copied by an earlier run that died before writing its journal
+    create(&dest, "bbbbbb", "second").await;
+
+    let summary = migrate::migrate(&source, &dest, &journal).await.unwrap();
+    assert_eq!((summary.copied, summary.skipped), (2, 0));
+    assert!(summary.failed.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&journal).unwrap().lines().count(),
+        2
+    );
+
+    // Only new pastes, and ones gone from the destination, are copied again
+    create(&source, "cccccc", "third").await;
+    dest.delete("aaaaaa").await.unwrap();
+    let summary = migrate::migrate(&source, &dest, &journal).await.unwrap();
+    assert_eq!((summary.copied, summary.skipped), (2, 1));
+    for (id, expected) in &[
+        ("aaaaaa", "first"),
+        ("bbbbbb", "second"),
+        ("cccccc", "third"),
+    ] {
+        assert_eq!(content(&dest, id).await, *expected);
+        assert_eq!(dest.get_meta(id).await.unwrap().size, expected.len() as u64);
+    }
+}
+
+/// Backend that is down, every call fails
+#[derive(Clone)]
+struct Down;
+
+fn down<T>() -> Result<T> {
+    Err(anyhow::format_err!("Backend is down"))
+}
+
+#[async_trait]
+impl Storage for Down {
+    async fn exists(&self, _id: &str) -> Result<bool> {
+        down()
+    }
+    async fn get(&self, _id: &str) -> Result<Response> {
+        down()
+    }
+    async fn get_meta(&self, _id: &str) -> Result<PasteMeta> {
+        down()
+    }
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        down()
+    }
+    async fn views(&self, _id: &str) -> Result<u64> {
+        down()
+    }
+    async fn new(&self, _id: &str, _key: &str) -> Result<PasteWriter> {
+        down()
+    }
+    async fn set_meta(&self, _id: &str, _meta: &PasteMeta) -> Result<()> {
+        down()
+    }
+    async fn add_views(&self, _id: &str, _count: u64) -> Result<u64> {
+        down()
+    }
+    async fn update_size(&self, _id: &str) -> Result<()> {
+        down()
+    }
+    async fn update(&self, _id: &str) -> Result<PasteWriter> {
+        down()
+    }
+    async fn delete(&self, _id: &str) -> Result<()> {
+        down()
+    }
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        down()
+    }
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        down()
+    }
+    async fn fsck(&self, _repair: bool) -> Result<Vec<Inconsistency>> {
+        down()
+    }
+}
+
+#[actix_rt::test]
+async fn dual_write_secondary_down() {
+    let (primary, _dir) = filesystem(None);
+    let storage = StorageBox::dual_write(
+        StorageBox {
+            inner: Box::new(primary.clone()),
+        },
+        StorageBox {
+            inner: Box::new(Down),
+        },
+    );
+    let storage = &*storage.inner;
+
+    create(storage, "aaaaaa", "hello").await;
+    let mut meta = storage.get_meta("aaaaaa").await.unwrap();
+    meta.name = Some("hello.txt".to_string());
+    storage.set_meta("aaaaaa", &meta).await.unwrap();
+    assert_eq!(storage.add_views("aaaaaa", 1).await.unwrap(), 1);
+    let mut writer = storage.update("aaaaaa").await.unwrap();
+    writer.write_all(b"changed").await.unwrap();
+    writer.flush().await.unwrap();
+    storage.update_size("aaaaaa").await.unwrap();
+
+    assert_eq!(content(&primary, "aaaaaa").await, "changed");
+    let meta = primary.get_meta("aaaaaa").await.unwrap();
+    assert_eq!((meta.name.as_deref(), meta.size), (Some("hello.txt"), 7));
+    storage.delete("aaaaaa").await.unwrap();
+    assert!(!primary.exists("aaaaaa").await.unwrap());
+}
+
+#[actix_rt::test]
+async fn cache_hits_and_invalidation() {
+    let (backend, _dir) = filesystem(None);
+    let cached = MemoryCachedStorage::new(
+        StorageBox {
+            inner: Box::new(backend),
+        },
+        1 << 20,
+    );
+    create(&cached, "aaaaaa", "hello").await;
+
+    assert_eq!(content(&cached, "aaaaaa").await, "hello");
+    assert_eq!(content(&cached, "aaaaaa").await, "hello");
+    cached.get_meta("aaaaaa").await.unwrap();
+    cached.get_meta("aaaaaa").await.unwrap();
+    let stats = cached.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses), (2, 2));
+    assert_eq!(stats.entries, 2);
+
+    // Changed content is read again
+    let mut writer = cached.update("aaaaaa").await.unwrap();
+    writer.write_all(b"changed").await.unwrap();
+    writer.flush().await.unwrap();
+    cached.update_size("aaaaaa").await.unwrap();
+    assert_eq!(content(&cached, "aaaaaa").await, "changed");
+    assert_eq!(cached.get_meta("aaaaaa").await.unwrap().size, 7);
+    assert_eq!(cached.cache_stats().unwrap().misses, 4);
+
+    cached.delete("aaaaaa").await.unwrap();
+    assert!(cached.get("aaaaaa").await.is_err());
+    assert_eq!(cached.cache_stats().unwrap().entries, 0);
+}
+
+/// Holds reads back after they are done with the backend, until let through
+#[derive(Clone)]
+struct Gated {
+    inner: SimpleStorage,
+    read: Arc<Semaphore>,
+    gate: Arc<Semaphore>,
+}
+
+#[async_trait]
+impl Storage for Gated {
+    async fn exists(&self, id: &str) -> Result<bool> {
+        self.inner.exists(id).await
+    }
+    async fn get(&self, id: &str) -> Result<Response> {
+        let res = self.inner.get(id).await;
+        self.read.add_permits(1);
+        self.gate.acquire().await.forget();
+        res
+    }
+    async fn get_meta(&self, id: &str) -> Result<PasteMeta> {
+        self.inner.get_meta(id).await
+    }
+    async fn get_all_meta(&self) -> Result<Vec<(String, PasteMeta)>> {
+        self.inner.get_all_meta().await
+    }
+    async fn list_meta(&self, query: &MetaQuery) -> Result<Vec<(String, PasteMeta)>> {
+        self.inner.list_meta(query).await
+    }
+    async fn views(&self, id: &str) -> Result<u64> {
+        self.inner.views(id).await
+    }
+    async fn new(&self, id: &str, key: &str) -> Result<PasteWriter> {
+        self.inner.new(id, key).await
+    }
+    async fn set_meta(&self, id: &str, meta: &PasteMeta) -> Result<()> {
+        self.inner.set_meta(id, meta).await
+    }
+    async fn add_views(&self, id: &str, count: u64) -> Result<u64> {
+        self.inner.add_views(id, count).await
+    }
+    async fn update_size(&self, id: &str) -> Result<()> {
+        self.inner.update_size(id).await
+    }
+    async fn update(&self, id: &str) -> Result<PasteWriter> {
+        self.inner.update(id).await
+    }
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.inner.delete(id).await
+    }
+    async fn cleanup(&self) -> Result<Vec<String>> {
+        self.inner.cleanup().await
+    }
+    async fn next_expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.inner.next_expiry().await
+    }
+    async fn fsck(&self, repair: bool) -> Result<Vec<Inconsistency>> {
+        self.inner.fsck(repair).await
+    }
+}
+
+#[actix_rt::test]
+async fn cache_skips_stale_fill() {
+    let (backend, _dir) = filesystem(None);
+    let read = Arc::new(Semaphore::new(0));
+    let gate = Arc::new(Semaphore::new(0));
+    let cached = MemoryCachedStorage::new(
+        StorageBox {
+            inner: Box::new(Gated {
+                inner: backend,
+                read: read.clone(),
+                gate: gate.clone(),
+            }),
+        },
+        1 << 20,
+    );
+    create(&cached, "aaaaaa", "old").await;
+
+    // The paste changes between a read of the backend and its cache fill
+    let (old, ()) = futures::join!(content(&cached, "aaaaaa"), async {
+        read.acquire().await.forget();
+        let mut writer = cached.update("aaaaaa").await.unwrap();
+        writer.write_all(b"new").await.unwrap();
+        writer.flush().await.unwrap();
+        cached.update_size("aaaaaa").await.unwrap();
+        gate.add_permits(1);
+    });
+    assert_eq!(old, "old");
+
+    gate.add_permits(1);
+    assert_eq!(content(&cached, "aaaaaa").await, "new");
+}
+
+/// When a paste is due, to the precision of the expiry index
+async fn due(storage: &dyn Storage, id: &str) -> Option<i64> {
+    let meta = storage.get_meta(id).await.unwrap();
+    meta.expires_at().map(|t| t.timestamp_millis())
+}
+
+#[actix_rt::test]
+async fn expiry_index() {
+    for (storage, _dir) in meta_stores() {
+        let minutes = Duration::minutes;
+        let millis = |t: Option<DateTime<Utc>>| t.map(|t| t.timestamp_millis());
+        assert_eq!(storage.next_expiry().await.unwrap(), None);
+
+        create_aged(&storage, "aaaaaa", None, minutes(0), Some(minutes(30))).await;
+        create_aged(&storage, "bbbbbb", None, minutes(0), Some(minutes(10))).await;
+        create_aged(&storage, "cccccc", None, minutes(0), None).await;
+        assert_eq!(
+            millis(storage.next_expiry().await.unwrap()),
+            due(&storage, "bbbbbb").await
+        );
+
+        // Pushing an expiry back moves it in the index
+        let mut meta = storage.get_meta("bbbbbb").await.unwrap();
+        meta.expire_time = Some(Utc::now() + minutes(60));
+        storage.set_meta("bbbbbb", &meta).await.unwrap();
+        assert_eq!(
+            millis(storage.next_expiry().await.unwrap()),
+            due(&storage, "aaaaaa").await
+        );
+
+        // Inactivity counts as well
+        let mut meta = storage.get_meta("cccccc").await.unwrap();
+        meta.expire_after_inactive = Some(1);
+        meta.atime = Some(Utc::now() - Duration::days(2));
+        storage.set_meta("cccccc", &meta).await.unwrap();
+        meta = storage.get_meta("aaaaaa").await.unwrap();
+        meta.expire_time = Some(Utc::now() - minutes(1));
+        storage.set_meta("aaaaaa", &meta).await.unwrap();
+        // And an empty paste
+        drop(storage.new("dddddd", "key").await.unwrap());
+
+        let mut deleted = storage.cleanup().await.unwrap();
+        deleted.sort();
+        assert_eq!(deleted, ["aaaaaa", "cccccc", "dddddd"]);
+        assert!(storage.exists("bbbbbb").await.unwrap());
+        assert!(!storage.exists("aaaaaa").await.unwrap());
+        assert!(storage.cleanup().await.unwrap().is_empty());
+        assert_eq!(
+            millis(storage.next_expiry().await.unwrap()),
+            due(&storage, "bbbbbb").await
+        );
+
+        // Nothing is left due once the last paste is gone
+        storage.delete("bbbbbb").await.unwrap();
+        assert_eq!(storage.next_expiry().await.unwrap(), None);
+    }
+}
+
+#[actix_rt::test]
+async fn trash_restore() {
+    let (backend, dir) = filesystem(None);
+    let trash = Trash::new(&PathBuf::from(dir.path()), 7);
+    let storage = TrashStorage::new(
+        StorageBox {
+            inner: Box::new(backend),
+        },
+        trash.clone(),
+    );
+    create(&storage, "aaaaaa", "deleted").await;
+    create_aged(
+        &storage,
+        "bbbbbb",
+        None,
+        Duration::hours(1),
+        Some(Duration::hours(1)),
+    )
+    .await;
+    let mut meta = storage.get_meta("bbbbbb").await.unwrap();
+    meta.expire_time = Some(Utc::now() - Duration::minutes(1));
+    storage.set_meta("bbbbbb", &meta).await.unwrap();
+
+    storage.delete("aaaaaa").await.unwrap();
+    assert_eq!(storage.cleanup().await.unwrap(), ["bbbbbb"]);
+    assert!(!storage.exists("aaaaaa").await.unwrap());
+    assert!(!storage.exists("bbbbbb").await.unwrap());
+    // Their ids stay taken
+    assert!(trash.contains("aaaaaa").await);
+    assert!(trash.contains("bbbbbb").await);
+
+    let trashed = trash.list().await.unwrap();
+    assert_eq!(trashed.len(), 2);
+    let expired = trashed.iter().find(|p| p.id == "bbbbbb").unwrap();
+    assert!(matches!(expired.reason, TrashReason::Expired));
+    assert_eq!(expired.purge_time, expired.deleted_time + Duration::days(7));
+
+    // Back with the same content and key, and no longer expiring
+    assert!(trash.restore(&storage, "bbbbbb").await.unwrap());
+    assert_eq!(content(&storage, "bbbbbb").await, "bbbbbb");
+    let meta = storage.get_meta("bbbbbb").await.unwrap();
+    assert!(meta.validate("key"));
+    assert_eq!(meta.expire_time, None);
+    assert!(!trash.contains("bbbbbb").await);
+    assert!(!trash.restore(&storage, "bbbbbb").await.unwrap());
+
+    // Not over a paste that took the id meanwhile
+    create(&storage, "aaaaaa", "taken").await;
+    assert!(trash.restore(&storage, "aaaaaa").await.is_err());
+    assert_eq!(content(&storage, "aaaaaa").await, "taken");
+
+    assert!(trash.remove("aaaaaa").await.unwrap());
+    assert!(trash.list().await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn trash_purge() {
+    let (backend, dir) = filesystem(None);
+    // Purged as soon as the next cleanup runs
+    let trash = Trash::new(&PathBuf::from(dir.path()), 0);
+    let storage = TrashStorage::new(
+        StorageBox {
+            inner: Box::new(backend),
+        },
+        trash.clone(),
+    );
+    create(&storage, "aaaaaa", "hello").await;
+    storage.delete("aaaaaa").await.unwrap();
+    assert!(storage.next_expiry().await.unwrap().unwrap() <= Utc::now());
+
+    storage.cleanup().await.unwrap();
+    assert!(trash.list().await.unwrap().is_empty());
+    assert!(!trash.contains("aaaaaa").await);
+    assert!(!trash.restore(&storage, "aaaaaa").await.unwrap());
+    assert_eq!(storage.next_expiry().await.unwrap(), None);
+}